@@ -0,0 +1,66 @@
+use anyhow::Context;
+use chrono::{Local, SecondsFormat};
+use lru::FileInfo;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// An append-only record of every file a run disposes of, one tab-separated line per path giving
+/// the time, the run's id, what was done, the bytes freed and the path. Each line is written
+/// straight to the file, so that a crash mid-run still leaves the earlier lines behind.
+pub struct AuditLog {
+    path : PathBuf,
+    file : File,
+    /// rotate the log once it would grow beyond this many bytes
+    max_size : Option<u64>,
+    size : u64,
+    /// identifies the lines written by this run, e.g. `20240131T120000-1234`
+    run_id : String,
+    action : &'static str,
+}
+
+fn open_for_appending(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, max_size: Option<u64>, action: &'static str) -> anyhow::Result<Self> {
+        let file = open_for_appending(path).with_context(|| format!("failed to open log file {}", path.display()))?;
+        let size = file.metadata().with_context(|| format!("failed to read log file {}", path.display()))?.len();
+        Ok(AuditLog {
+            path : path.to_path_buf(),
+            file,
+            max_size,
+            size,
+            run_id : format!("{}-{}", Local::now().format("%Y%m%dT%H%M%S"), process::id()),
+            action,
+        })
+    }
+
+    /// Move the current log aside to `<path>.1`, replacing any earlier one, and start a new log.
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, rotated)?;
+        self.file = open_for_appending(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Record that every path of `file` was disposed of.
+    pub fn record(&mut self, file: &FileInfo) -> io::Result<()> {
+        let now = Local::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        for (i, path) in file.paths().enumerate() {
+            // the file's size is attributed to its first path, as in the other output formats
+            let size = if i == 0 { file.size } else { 0 };
+            let line = format!("{}\t{}\t{}\t{}\t{}\n", now, self.run_id, self.action, size, path.display());
+            if self.max_size.is_some_and(|max_size| self.size > 0 && self.size + line.len() as u64 > max_size) {
+                self.rotate()?;
+            }
+            self.file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+}
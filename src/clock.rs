@@ -0,0 +1,27 @@
+use chrono::{DateTime, Local};
+
+/// The source of the current time, against which file ages are measured.
+pub trait Clock : Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+/// The system's wall clock.
+#[derive(Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// A clock stopped at a single moment, so that which files count as old enough is reproducible,
+/// e.g. in tests.
+#[derive(Clone, Copy)]
+pub struct FixedClock(pub DateTime<Local>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Local> {
+        self.0
+    }
+}
@@ -0,0 +1,221 @@
+use crate::interval::Interval;
+use crate::size::ByteSize;
+use crate::Args;
+use anyhow::Context;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Options loaded from a --config file. Keys mirror the names of the command-line options, with
+/// underscores in place of dashes, e.g. `target_available_space = "50GB"`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    dry_run : Option<bool>,
+    target_available_space : Option<ByteSize>,
+    target_available_percent : Option<f64>,
+    target_available_inodes : Option<u64>,
+    max_bytes_to_delete : Option<ByteSize>,
+    max_files_to_delete : Option<usize>,
+    max_candidates : Option<usize>,
+    older_than : Option<i64>,
+    purge_older_than : Option<i64>,
+    retention : Option<String>,
+    by : Option<String>,
+    on_missing_atime : Option<String>,
+    future_time : Option<String>,
+    strategy : Option<String>,
+    size_source : Option<String>,
+    min_file_size : Option<ByteSize>,
+    max_file_size : Option<ByteSize>,
+    #[serde(default)]
+    exclude : Vec<String>,
+    no_global_ignore : Option<bool>,
+    #[serde(default)]
+    include : Vec<String>,
+    #[serde(default)]
+    exclude_regex : Vec<String>,
+    #[serde(default)]
+    match_regex : Vec<String>,
+    #[serde(default)]
+    ext : Vec<String>,
+    user : Option<String>,
+    keep_marker : Option<String>,
+    group_by : Option<String>,
+    skip_hidden : Option<bool>,
+    respect_ignore : Option<bool>,
+    follow_symlinks : Option<bool>,
+    one_file_system : Option<bool>,
+    assume_sorted : Option<bool>,
+    index : Option<PathBuf>,
+    low_memory : Option<bool>,
+    max_depth : Option<usize>,
+    min_depth : Option<usize>,
+    prune_empty_dirs : Option<bool>,
+    strict : Option<bool>,
+    yes : Option<bool>,
+    max_delete_percent : Option<f64>,
+    keep_newest : Option<usize>,
+    keep_per_dir : Option<usize>,
+    skip_open : Option<bool>,
+    skip_readonly : Option<bool>,
+    trash : Option<bool>,
+    move_to : Option<PathBuf>,
+    shred : Option<bool>,
+    shred_passes : Option<u32>,
+    delete_retries : Option<u32>,
+    delete_threads : Option<usize>,
+    recheck_interval : Option<usize>,
+    rate : Option<f64>,
+    rate_bytes : Option<ByteSize>,
+    time_format : Option<String>,
+    sort : Option<String>,
+    utc : Option<bool>,
+    progress : Option<bool>,
+    breakdown : Option<bool>,
+    format : Option<String>,
+    color : Option<String>,
+    if_locked : Option<String>,
+    metrics_file : Option<PathBuf>,
+    journald : Option<bool>,
+    log_file : Option<PathBuf>,
+    log_max_size : Option<ByteSize>,
+    interval : Option<Interval>,
+    min_interval : Option<Interval>,
+    max_interval : Option<Interval>,
+    watch : Option<bool>,
+    watch_debounce : Option<Interval>,
+    watch_events : Option<String>,
+    #[serde(default)]
+    paths : Vec<PathBuf>,
+    verbose : Option<u8>,
+    quiet : Option<bool>,
+}
+
+impl FileConfig {
+    /// Load a config file, which is parsed as JSON if its extension is `.json` and as TOML
+    /// otherwise.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("failed to read config file {}", path.display()))?;
+        let config = if path.extension().is_some_and(|extension| extension == "json") {
+            serde_json::from_str(&contents).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(&contents).map_err(anyhow::Error::from)
+        };
+        config.with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Fill in any options which weren't given on the command line. Options which take a list of
+    /// values accumulate the values from both instead, and switches are on if either turns them on.
+    pub fn merge_into(self, args: &mut Args) -> anyhow::Result<()> {
+        fn parse<T: FromStr<Err = String>>(key: &str, value: Option<String>) -> anyhow::Result<Option<T>> {
+            value.map(|value| value.parse().map_err(|err| anyhow::anyhow!("{}: {}", key, err))).transpose()
+        }
+
+        args.dry_run |= self.dry_run.unwrap_or(false);
+        // the two targets are mutually exclusive, so a target given on the command line replaces
+        // either target in the file
+        if args.target_available_space.is_none() && args.target_available_percent.is_none() {
+            args.target_available_space = self.target_available_space;
+            args.target_available_percent = self.target_available_percent;
+        }
+        args.target_available_inodes = args.target_available_inodes.or(self.target_available_inodes);
+        args.max_bytes_to_delete = args.max_bytes_to_delete.or(self.max_bytes_to_delete);
+        args.max_files_to_delete = args.max_files_to_delete.or(self.max_files_to_delete);
+        args.max_candidates = args.max_candidates.or(self.max_candidates);
+        args.older_than = args.older_than.or(self.older_than);
+        args.purge_older_than = args.purge_older_than.or(self.purge_older_than);
+        if args.retention.is_none() {
+            args.retention = parse("retention", self.retention)?;
+        }
+        args.by = args.by.or(parse("by", self.by)?);
+        args.on_missing_atime = args.on_missing_atime.or(parse("on_missing_atime", self.on_missing_atime)?);
+        args.future_time = args.future_time.or(parse("future_time", self.future_time)?);
+        args.strategy = args.strategy.or(parse("strategy", self.strategy)?);
+        args.size_source = args.size_source.or(parse("size_source", self.size_source)?);
+        args.min_file_size = args.min_file_size.or(self.min_file_size);
+        args.max_file_size = args.max_file_size.or(self.max_file_size);
+        args.exclude.extend(self.exclude);
+        args.no_global_ignore |= self.no_global_ignore.unwrap_or(false);
+        args.include.extend(self.include);
+        args.exclude_regex.extend(self.exclude_regex);
+        args.match_regex.extend(self.match_regex);
+        args.ext.extend(self.ext);
+        if args.user.is_none() {
+            args.user = self.user;
+        }
+        if args.keep_marker.is_none() {
+            args.keep_marker = self.keep_marker;
+        }
+        if args.group_by.is_none() {
+            args.group_by = self.group_by;
+        }
+        args.skip_hidden |= self.skip_hidden.unwrap_or(false);
+        args.respect_ignore |= self.respect_ignore.unwrap_or(false);
+        args.follow_symlinks |= self.follow_symlinks.unwrap_or(false);
+        args.one_file_system |= self.one_file_system.unwrap_or(false);
+        args.assume_sorted |= self.assume_sorted.unwrap_or(false);
+        if args.index.is_none() {
+            args.index = self.index;
+        }
+        args.low_memory |= self.low_memory.unwrap_or(false);
+        args.max_depth = args.max_depth.or(self.max_depth);
+        args.min_depth = args.min_depth.or(self.min_depth);
+        args.prune_empty_dirs |= self.prune_empty_dirs.unwrap_or(false);
+        args.strict |= self.strict.unwrap_or(false);
+        args.yes |= self.yes.unwrap_or(false);
+        args.max_delete_percent = args.max_delete_percent.or(self.max_delete_percent);
+        args.keep_newest = args.keep_newest.or(self.keep_newest);
+        args.keep_per_dir = args.keep_per_dir.or(self.keep_per_dir);
+        args.skip_open |= self.skip_open.unwrap_or(false);
+        args.skip_readonly |= self.skip_readonly.unwrap_or(false);
+        // likewise, --trash, --move-to and --shred are mutually exclusive
+        if !args.trash && args.move_to.is_none() && !args.shred && args.shred_passes.is_none() {
+            args.trash = self.trash.unwrap_or(false);
+            args.move_to = self.move_to;
+            args.shred = self.shred.unwrap_or(false);
+            args.shred_passes = self.shred_passes;
+        }
+        args.delete_retries = args.delete_retries.or(self.delete_retries);
+        args.delete_threads = args.delete_threads.or(self.delete_threads);
+        args.recheck_interval = args.recheck_interval.or(self.recheck_interval);
+        args.rate = args.rate.or(self.rate);
+        args.rate_bytes = args.rate_bytes.or(self.rate_bytes);
+        if args.time_format.is_none() {
+            args.time_format = parse("time_format", self.time_format)?;
+        }
+        args.utc |= self.utc.unwrap_or(false);
+        args.sort = args.sort.or(parse("sort", self.sort)?);
+        args.progress |= self.progress.unwrap_or(false);
+        args.breakdown |= self.breakdown.unwrap_or(false);
+        args.format = args.format.or(parse("format", self.format)?);
+        args.color = args.color.or(parse("color", self.color)?);
+        args.if_locked = args.if_locked.or(parse("if_locked", self.if_locked)?);
+        if args.metrics_file.is_none() {
+            args.metrics_file = self.metrics_file;
+        }
+        args.journald |= self.journald.unwrap_or(false);
+        if args.log_file.is_none() {
+            args.log_file = self.log_file;
+        }
+        args.log_max_size = args.log_max_size.or(self.log_max_size);
+        args.interval = args.interval.or(self.interval);
+        args.min_interval = args.min_interval.or(self.min_interval);
+        args.max_interval = args.max_interval.or(self.max_interval);
+        args.watch |= self.watch.unwrap_or(false);
+        args.watch_debounce = args.watch_debounce.or(self.watch_debounce);
+        args.watch_events = args.watch_events.or(parse("watch_events", self.watch_events)?);
+        // unlike --exclude and --include, directories given on the command line replace those in
+        // the file, so that a single directory can be handled with the file's policy
+        if args.paths.is_empty() {
+            args.paths = self.paths;
+        }
+        // likewise, --verbose and --quiet are mutually exclusive
+        if args.verbose == 0 && !args.quiet {
+            args.verbose = self.verbose.unwrap_or(0);
+            args.quiet = self.quiet.unwrap_or(false);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use crate::filesystem::FileSystem;
+use std::fs::{self, File, FileTimes, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait before the first retry of a transient failure. Each further retry waits
+/// twice as long as the last.
+const INITIAL_BACKOFF : Duration = Duration::from_millis(50);
+
+/// Whether `err` may well succeed if retried, as opposed to a permanent failure such as permission
+/// being denied.
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::ResourceBusy | io::ErrorKind::ExecutableFileBusy
+    )
+}
+
+/// Whether `err` means there's no room left to dispose of files into, so that every further
+/// disposal would fail the same way, as when --move-to's destination or the trash is full.
+pub fn is_out_of_space(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded)
+}
+
+/// The most zeros written at once while shredding a file.
+const SHRED_CHUNK_SIZE : usize = 1 << 20;
+
+/// The longest to sleep at once while throttled, so that an interrupt is noticed promptly.
+const MAX_THROTTLE_SLEEP : Duration = Duration::from_millis(100);
+
+/// What to do with the files selected for reclamation.
+#[derive(Clone)]
+pub enum Disposal {
+    /// delete files outright
+    Delete,
+    /// move files to the system trash
+    Trash,
+    /// move files beneath this directory, preserving their paths relative to the top-level
+    /// directory
+    MoveTo(PathBuf),
+    /// overwrite files with zeros this many times before deleting them
+    Shred(u32),
+}
+
+impl Disposal {
+    /// Dispose of `path`, which lives beneath the top-level directory `root`.
+    pub fn apply(&self, root: &Path, path: &Path) -> io::Result<()> {
+        match self {
+            Disposal::Delete => fs::remove_file(path),
+            Disposal::Shred(passes) => {
+                shred(path, *passes)?;
+                fs::remove_file(path)
+            }
+            Disposal::Trash => trash::delete(path).map_err(io::Error::other),
+            Disposal::MoveTo(directory) => {
+                let relative_path = path.strip_prefix(root).map_err(|_| {
+                    io::Error::other(format!("{} is not beneath {}", path.display(), root.display()))
+                })?;
+                move_file(path, &directory.join(relative_path))
+            }
+        }
+    }
+
+    /// Like `apply`, but through `filesystem`, and retrying up to `retries` times with exponential
+    /// backoff while the failure is transient, as it may be on network filesystems.
+    pub fn apply_with_retries(&self, filesystem: &dyn FileSystem, root: &Path, path: &Path, retries: u32) -> io::Result<()> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match filesystem.remove(self, root, path) {
+                Err(err) if attempt < retries && is_transient(&err) => {
+                    log::debug!("Retrying {} in {:?} after: {}", path.display(), backoff, err);
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The past-tense verb describing what happened to a disposed file.
+    pub fn verb(&self) -> &'static str {
+        match self {
+            Disposal::Delete => "Deleted",
+            Disposal::Trash => "Trashed",
+            Disposal::MoveTo(_) => "Moved",
+            Disposal::Shred(_) => "Shredded",
+        }
+    }
+
+    /// A machine-readable name for what happens to disposed files.
+    pub fn action(&self) -> &'static str {
+        match self {
+            Disposal::Delete => "delete",
+            Disposal::Trash => "trash",
+            Disposal::MoveTo(_) => "move",
+            Disposal::Shred(_) => "shred",
+        }
+    }
+}
+
+/// Overwrite the contents of `path` with zeros `passes` times, syncing each pass to disk. This only
+/// destroys the data where the filesystem overwrites blocks in place, which copy-on-write
+/// filesystems and the wear levelling of SSDs don't.
+fn shred(path: &Path, passes: u32) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let zeros = vec![0; SHRED_CHUNK_SIZE];
+    for _ in 0..passes {
+        file.seek(SeekFrom::Start(0))?;
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(zeros.len() as u64) as usize;
+            file.write_all(&zeros[..n])?;
+            remaining -= n as u64;
+        }
+        // otherwise each pass may only overwrite the last in the page cache
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+/// Move `source` to `destination`, creating any missing parent directories. Moves within a
+/// filesystem are a simple rename, while moves across filesystems copy the file, along with its
+/// permissions and access and modification times, and then delete the source. If the move fails
+/// part-way, the source is left intact and any partial copy is removed. A file already at
+/// `destination`, e.g. one archived by an earlier run, is never replaced: the move fails as
+/// already existing instead.
+fn move_file(source: &Path, destination: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // NOTE: rename replaces whatever is at the destination, so a file which appears there between
+    // this check and the rename is still lost
+    if fs::symlink_metadata(destination).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", destination.display())));
+    }
+    match fs::rename(source, destination) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            // NOTE: read before copying, since reading the file may update its atime
+            let metadata = fs::metadata(source)?;
+            // the copy is only ever removed once we know we created it
+            let mut output = OpenOptions::new().write(true).create_new(true).open(destination)?;
+            let mut copy = || -> io::Result<()> {
+                io::copy(&mut File::open(source)?, &mut output)?;
+                let mut times = FileTimes::new().set_modified(metadata.modified()?);
+                if let Ok(accessed) = metadata.accessed() {
+                    times = times.set_accessed(accessed);
+                }
+                output.set_times(times)?;
+                fs::set_permissions(destination, metadata.permissions())
+            };
+            if let Err(err) = copy() {
+                let _ = fs::remove_file(destination);
+                return Err(err);
+            }
+            if let Err(err) = fs::remove_file(source) {
+                // keep exactly one copy of the file, in its original location
+                let _ = fs::remove_file(destination);
+                return Err(err);
+            }
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Paces disposals to at most a number of files and bytes per second, by sleeping until the files
+/// disposed of so far would have taken that long at the given rates.
+pub struct Throttle {
+    files_per_second : Option<f64>,
+    bytes_per_second : Option<u64>,
+    start : Instant,
+    n_files : u64,
+    n_bytes : u64,
+}
+
+impl Throttle {
+    pub fn new(files_per_second: Option<f64>, bytes_per_second: Option<u64>) -> Self {
+        Throttle { files_per_second, bytes_per_second, start : Instant::now(), n_files : 0, n_bytes : 0 }
+    }
+
+    /// Wait until another `n_files` files holding `n_bytes` bytes may be disposed of, or until
+    /// `is_interrupted` returns true.
+    pub fn wait(&mut self, n_files: u64, n_bytes: u64, is_interrupted: impl Fn() -> bool) {
+        let files_due = self.files_per_second.map_or(0.0, |rate| self.n_files as f64 / rate);
+        let bytes_due = self.bytes_per_second.map_or(0.0, |rate| self.n_bytes as f64 / rate as f64);
+        let due = self.start + Duration::from_secs_f64(files_due.max(bytes_due));
+        loop {
+            let now = Instant::now();
+            if now >= due || is_interrupted() {
+                break;
+            }
+            thread::sleep((due - now).min(MAX_THROTTLE_SLEEP));
+        }
+        self.n_files += n_files;
+        self.n_bytes += n_bytes;
+    }
+}
@@ -0,0 +1,227 @@
+use crate::filesystem::FileStat;
+use crate::group::Groups;
+use crate::{index, open, timestamp, FileInfo, Filters, FutureTime, Protected, ReclaimConfig, RetentionPolicy, Strategy};
+use chrono::prelude::*;
+use chrono::Duration;
+use regex::Regex;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// What decides which files a run could delete, once it's worked out before walking.
+pub struct Eligibility<'a> {
+    pub filters : &'a Filters,
+    pub now : DateTime<Local>,
+    pub older_than_time : Option<DateTime<Local>>,
+    /// the times each retention band reaches back to, youngest first
+    pub retention : Vec<(Option<DateTime<Local>>, RetentionPolicy)>,
+    pub open_files : Option<HashSet<(u64, u64)>>,
+    /// the indexes a run keeps, by path and by inode where it's known, which are never deleted
+    pub own_files : Vec<(PathBuf, Option<(u64, u64)>)>,
+}
+
+/// What a walk has counted of the files it kept, for the warnings a run gives once it's walked.
+#[derive(Default)]
+pub struct Tally {
+    pub warned_creation_time_unsupported : bool,
+    /// files whose timestamps are in the future
+    pub future : usize,
+    /// files kept because they're larger than `max_file_size`
+    pub too_large : usize,
+    /// files kept because they're immutable or append-only
+    pub undeletable : usize,
+}
+
+impl<'a> Eligibility<'a> {
+    pub fn new(config: &ReclaimConfig, filters: &'a Filters, now: DateTime<Local>) -> Self {
+        let mut own_files = Vec::new();
+        if let Some(walk_index_path) = &config.walk_index {
+            let inode = config.filesystem.stat(walk_index_path, false).ok().and_then(|metadata| metadata.inode);
+            own_files.push((walk_index_path.clone(), inode));
+        }
+        if config.strategy == Strategy::Lfu {
+            own_files.push((config.path.join(index::FILE_NAME), None));
+        }
+        Eligibility {
+            filters,
+            now,
+            // NOTE: files used exactly `older_than` ago are old enough, and with no minimum age even
+            // files used "after" now, e.g. on another machine with a fast clock, are candidates
+            older_than_time : (config.older_than > Duration::zero()).then(|| now - config.older_than),
+            retention : config.retention.iter().map(|band| (band.younger_than.map(|age| now - age), band.policy)).collect(),
+            open_files : if config.skip_open { open::open_files() } else { None },
+            own_files,
+        }
+    }
+
+    /// The policy of the retention band a file used at `accessed` falls in, if there are any.
+    pub fn retention_policy(&self, accessed: DateTime<Local>) -> Option<RetentionPolicy> {
+        self.retention.iter()
+            .find(|(since, _)| since.is_none_or(|since| accessed > since))
+            .map(|(_, policy)| *policy)
+    }
+
+    /// The file described by `path` and `metadata` as a candidate for deletion, or `None` if it's
+    /// kept whatever the target, for the reasons an ordinary run would keep it. `frequency` gives
+    /// the access frequency of each file which isn't excluded, given the timestamp ranking it,
+    /// whether or not it's then kept. Hard-linked files are left to the caller, since whether
+    /// deleting them frees anything depends on their other links.
+    pub fn candidate(
+        &self, config: &ReclaimConfig, path: PathBuf, metadata: &FileStat, tally: &mut Tally, frequency: impl FnOnce(&Path, DateTime<Local>) -> u64,
+    ) -> anyhow::Result<Option<FileInfo>> {
+        if !metadata.is_file {
+            return Ok(None);
+        }
+        // excluded (or not included) files still take up space, but are never candidates for
+        // deletion
+        if self.filters.excludes(config, &path, metadata) {
+            log::debug!("Excluding {}", path.display());
+            return Ok(None);
+        }
+        if self.own_files.iter().any(|(own_path, inode)| path == *own_path || inode.is_some() && metadata.inode == *inode) {
+            return Ok(None);
+        }
+        let accessed = match timestamp(config, &path, metadata, &mut tally.warned_creation_time_unsupported)? {
+            // files used while we walk are merely newer than `now`, not in the future
+            Some(accessed) if accessed > self.now && accessed > config.clock.now() => {
+                tally.future += 1;
+                match config.future_time {
+                    FutureTime::Warn => accessed,
+                    FutureTime::Clamp => {
+                        log::debug!("Ranking {} as used now, rather than in the future at {}", path.display(), accessed);
+                        self.now
+                    }
+                    FutureTime::Skip => {
+                        log::debug!("Keeping {}, since it was used in the future at {}", path.display(), accessed);
+                        return Ok(None);
+                    }
+                }
+            }
+            Some(accessed) => accessed,
+            None => return Ok(None),
+        };
+        let frequency = frequency(&path, accessed);
+        let too_new = self.older_than_time.is_some_and(|older_than_time| accessed > older_than_time)
+            || self.retention_policy(accessed) == Some(RetentionPolicy::Protect);
+        if too_new || metadata.len < config.min_file_size {
+            return Ok(None);
+        }
+        if config.max_file_size.is_some_and(|max_file_size| metadata.len > max_file_size) {
+            tally.too_large += 1;
+            return Ok(None);
+        }
+        if self.open_files.as_ref().zip(metadata.inode).is_some_and(|(open_files, inode)| open_files.contains(&inode)) {
+            log::debug!("Keeping {}, since it's open", path.display());
+            return Ok(None);
+        }
+        if config.skip_readonly && metadata.readonly {
+            log::debug!("Keeping {}, since it's read-only", path.display());
+            return Ok(None);
+        }
+        // deleting them would only fail, so their space can't be counted on
+        if config.filesystem.is_undeletable(&path) {
+            log::debug!("Keeping {}, since it's immutable or append-only", path.display());
+            tally.undeletable += 1;
+            return Ok(None);
+        }
+        let size = config.size_source.of(metadata);
+        let rank = config.strategy.rank(accessed, size, frequency, self.now);
+        Ok(Some(FileInfo { rank, accessed, frequency, size, length : metadata.len, inode : metadata.inode, path, links : Vec::new() }))
+    }
+}
+
+/// The candidates of one walk of the tree, as a run deletes them: whole groups, hard-linked files
+/// all of whose links were found, and only once `keep_newest` and `keep_per_dir` no longer protect
+/// them.
+pub struct Walk<'a> {
+    eligibility : &'a Eligibility<'a>,
+    groups : Option<Groups<'a>>,
+    /// the links found so far to each hard-linked file, and the number of links it has in total
+    hard_links : HashMap<(u64, u64), (FileInfo, u64)>,
+    protected : Protected,
+    /// the number of candidates found, and the bytes they take
+    pub candidates : usize,
+    pub candidate_bytes : u64,
+}
+
+impl<'a> Walk<'a> {
+    pub fn new(config: &ReclaimConfig, eligibility: &'a Eligibility<'a>, group_pattern: Option<&'a Regex>) -> Self {
+        Walk {
+            eligibility,
+            groups : group_pattern.map(Groups::new),
+            hard_links : HashMap::new(),
+            protected : Protected::new(config.keep_newest, config.keep_per_dir),
+            candidates : 0,
+            candidate_bytes : 0,
+        }
+    }
+
+    /// Add the entry described by `path` and `metadata`, passing `unprotected` each candidate now
+    /// known to be protected by nothing, with its access frequency given by `frequency` as for
+    /// `Eligibility::candidate`.
+    pub fn found(
+        &mut self, config: &ReclaimConfig, path: PathBuf, metadata: &FileStat, tally: &mut Tally,
+        frequency: impl FnOnce(&Path, DateTime<Local>) -> u64, unprotected: impl FnMut(FileInfo),
+    ) -> anyhow::Result<()> {
+        if !metadata.is_file {
+            return Ok(());
+        }
+        // every file of a group is counted, so that those kept for any reason keep it whole
+        let group = self.groups.as_mut().and_then(|groups| {
+            let key = groups.key(path.strip_prefix(&config.path).unwrap_or(&path))?;
+            groups.found(&key);
+            Some(key)
+        });
+        let file = match self.eligibility.candidate(config, path, metadata, tally, frequency)? {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        if let (Some(groups), Some(key)) = (self.groups.as_mut(), &group) {
+            // whether a hard-linked file's space is freed depends on links which may lie outside
+            // its group
+            if metadata.links > 1 {
+                log::debug!("Keeping {}, since a file with several hard links can't be grouped", file.path.display());
+            } else {
+                groups.admit(key, file);
+            }
+            return Ok(());
+        }
+        match (file.inode, metadata.links) {
+            // every link to a hard-linked file shares its metadata, so the links found later are
+            // simply deleted along with the first
+            (Some(inode), n_links) if n_links > 1 => match self.hard_links.entry(inode) {
+                Entry::Occupied(mut entry) => entry.get_mut().0.links.push(file.path),
+                Entry::Vacant(entry) => {
+                    entry.insert((file, n_links));
+                }
+            },
+            _ => self.admit(file, unprotected),
+        }
+        Ok(())
+    }
+
+    fn admit(&mut self, file: FileInfo, unprotected: impl FnMut(FileInfo)) {
+        self.candidates += 1;
+        self.candidate_bytes += file.size;
+        self.protected.admit(file, unprotected);
+    }
+
+    /// Pass `unprotected` the candidates held back until the walk was over, i.e. the hard-linked
+    /// files and groups, returning the number of files `keep_newest` and `keep_per_dir` protect.
+    pub fn finish(&mut self, config: &ReclaimConfig, mut unprotected: impl FnMut(FileInfo)) -> usize {
+        // a hard-linked file's space is only freed by deleting every one of its links, which is
+        // only possible if they were all found beneath the top-level directory
+        for (file, n_links) in std::mem::take(&mut self.hard_links).into_values() {
+            if file.paths().count() as u64 != n_links {
+                log::debug!("Keeping {}, since some of its {} hard links are outside {}", file.path.display(), n_links, config.path.display());
+                continue;
+            }
+            self.admit(file, &mut unprotected);
+        }
+        let now = self.eligibility.now;
+        for file in self.groups.take().into_iter().flat_map(|groups| groups.into_files(|accessed, size, frequency| config.strategy.rank(accessed, size, frequency, now))) {
+            self.admit(file, &mut unprotected);
+        }
+        self.protected.len()
+    }
+}
@@ -0,0 +1,487 @@
+use crate::dispose::Disposal;
+use crate::walk::{self, WalkEntry, WalkError, WalkOptions};
+use crate::walk_index;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+#[cfg(unix)]
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// What's known about a file or directory once it's been stat'd, whatever the platform.
+#[derive(Clone, Debug)]
+pub struct FileStat {
+    pub is_file : bool,
+    /// whether the path names a symlink, rather than what it points to
+    pub is_symlink : bool,
+    /// the logical length in bytes
+    pub len : u64,
+    /// the bytes allocated to the file, where the platform reports them
+    pub allocated : Option<u64>,
+    /// the timestamps the platform and filesystem record
+    pub accessed : Option<SystemTime>,
+    pub modified : Option<SystemTime>,
+    pub created : Option<SystemTime>,
+    /// when the file's metadata last changed, i.e. its ctime, where the platform records it. unlike
+    /// the other timestamps, it can't be set to an arbitrary time
+    pub changed : Option<SystemTime>,
+    /// the device and inode numbers, where the platform provides them
+    pub inode : Option<(u64, u64)>,
+    /// the number of hard links, which is 1 where the platform doesn't report it
+    pub links : u64,
+    /// the user id of the owner, where the platform has them
+    pub owner : Option<u32>,
+    /// whether the platform marks the file as hidden, as Windows does with an attribute. names
+    /// beginning with a dot are considered separately
+    pub hidden : bool,
+    /// whether the file's mode withholds write permission from us, or from its owner when we're
+    /// root, whom the mode doesn't bind. on Windows, whether it has the read-only attribute
+    pub readonly : bool,
+}
+
+/// Whether the mode of `metadata` withholds write permission from the class of user we fall
+/// into for it, taking root to be the owner.
+#[cfg(unix)]
+fn is_readonly(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::OnceLock;
+
+    static IDENTITY : OnceLock<(u32, Vec<u32>)> = OnceLock::new();
+    let (uid, gids) = IDENTITY.get_or_init(|| {
+        #[cfg(not(target_vendor = "apple"))]
+        let mut gids : Vec<u32> = nix::unistd::getgroups().unwrap_or_default().into_iter().map(|gid| gid.as_raw()).collect();
+        #[cfg(target_vendor = "apple")]
+        let mut gids = Vec::new();
+        gids.push(nix::unistd::getegid().as_raw());
+        (nix::unistd::geteuid().as_raw(), gids)
+    });
+    let write = if *uid == 0 || metadata.uid() == *uid {
+        0o200
+    } else if gids.contains(&metadata.gid()) {
+        0o020
+    } else {
+        0o002
+    };
+    metadata.mode() & write == 0
+}
+
+impl FileStat {
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        let (allocated, changed, inode, links, owner) = {
+            use std::convert::TryFrom;
+            use std::os::unix::fs::MetadataExt;
+            let changed = u32::try_from(metadata.ctime_nsec()).ok().and_then(|nanos| {
+                let since_epoch = Duration::new(metadata.ctime().unsigned_abs(), nanos);
+                if metadata.ctime() >= 0 { SystemTime::UNIX_EPOCH.checked_add(since_epoch) } else { SystemTime::UNIX_EPOCH.checked_sub(since_epoch) }
+            });
+            // st_blocks is always in 512-byte units, whatever the filesystem's block size
+            (Some(metadata.blocks() * 512), changed, Some((metadata.dev(), metadata.ino())), metadata.nlink(), Some(metadata.uid()))
+        };
+        #[cfg(not(unix))]
+        let (allocated, changed, inode, links, owner) = (None, None, None, 1, None);
+        #[cfg(windows)]
+        let hidden = {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_HIDDEN : u32 = 0x2;
+            metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+        };
+        #[cfg(not(windows))]
+        let hidden = false;
+        #[cfg(unix)]
+        let readonly = is_readonly(metadata);
+        #[cfg(not(unix))]
+        let readonly = metadata.permissions().readonly();
+        FileStat {
+            is_file : metadata.is_file(),
+            is_symlink : metadata.file_type().is_symlink(),
+            len : metadata.len(),
+            allocated,
+            accessed : metadata.accessed().ok(),
+            modified : metadata.modified().ok(),
+            created : metadata.created().ok(),
+            changed,
+            inode,
+            links,
+            owner,
+            hidden,
+            readonly,
+        }
+    }
+}
+
+/// Everything a reclamation run needs of the filesystem holding its top-level directory, so that
+/// runs can be tested against `MemoryFileSystem` rather than real files. Only the frequency index
+/// kept by `Strategy::Lfu` and the scan for open files bypass it.
+pub trait FileSystem : Send + Sync {
+    /// Recursively walk `root`, yielding an error for each entry which can't be read or stat'd and
+    /// carrying on past it.
+    fn walk(&self, root: &Path, options: &WalkOptions) -> Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>>;
+
+    /// Stat `path`, or the file it points to if it's a symlink and `follow_symlinks` is set.
+    fn stat(&self, path: &Path, follow_symlinks: bool) -> io::Result<FileStat>;
+
+    /// The absolute path of `path`, with every symlink resolved.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Dispose of the file `path`, which lives beneath the top-level directory `root`.
+    fn remove(&self, disposal: &Disposal, root: &Path, path: &Path) -> io::Result<()>;
+
+    /// Remove the directory `path`, failing unless it's empty.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// The free space available to us on the filesystem containing `path`.
+    fn available_space(&self, path: &Path) -> io::Result<u64>;
+
+    /// The capacity of the filesystem containing `path`.
+    fn total_space(&self, path: &Path) -> io::Result<u64>;
+
+    /// The inodes available to us on the filesystem containing `path`, or `None` where the
+    /// platform or filesystem doesn't limit them.
+    fn available_inodes(&self, path: &Path) -> io::Result<Option<u64>>;
+
+    /// Whether the file `path` is marked so that it can't be deleted, whoever tries, as Linux's
+    /// immutable and append-only attributes do. False wherever that can't be told.
+    fn is_undeletable(&self, path: &Path) -> bool;
+}
+
+/// Whether `path` has the immutable or append-only attribute, either of which stops it being
+/// deleted or renamed even by root. Fails on filesystems without attributes, and for files we
+/// can't open.
+#[cfg(target_os = "linux")]
+fn has_undeletable_attribute(path: &Path) -> io::Result<bool> {
+    use nix::libc;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    // from linux/fs.h
+    const FS_IMMUTABLE_FL : libc::c_int = 0x10;
+    const FS_APPEND_FL : libc::c_int = 0x20;
+    // NOTE: the request is declared as taking a long, but the kernel only ever reads and writes an
+    // int through it
+    nix::ioctl_read_bad!(get_flags, libc::FS_IOC_GETFLAGS, libc::c_int);
+
+    // opening never blocks, nor follows a symlink swapped in since the walk
+    let file = fs::OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW).open(path)?;
+    let mut flags = 0;
+    // SAFETY: the descriptor is open for the duration of the call, and `flags` is the int it writes
+    unsafe { get_flags(file.as_raw_fd(), &mut flags) }?;
+    Ok(flags & (FS_IMMUTABLE_FL | FS_APPEND_FL) != 0)
+}
+
+/// The filesystems the operating system provides.
+#[derive(Clone, Copy, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn walk(&self, root: &Path, options: &WalkOptions) -> Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> {
+        match &options.index {
+            Some(index) => walk_index::walk(root, options, index.clone()),
+            None => walk::walk(root, options),
+        }
+    }
+
+    fn stat(&self, path: &Path, follow_symlinks: bool) -> io::Result<FileStat> {
+        let metadata = if follow_symlinks { fs::metadata(path) } else { fs::symlink_metadata(path) };
+        Ok(FileStat::from_metadata(&metadata?))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn remove(&self, disposal: &Disposal, root: &Path, path: &Path) -> io::Result<()> {
+        disposal.apply(root, path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        fs2::available_space(path)
+    }
+
+    fn total_space(&self, path: &Path) -> io::Result<u64> {
+        fs2::total_space(path)
+    }
+
+    #[cfg(unix)]
+    fn available_inodes(&self, path: &Path) -> io::Result<Option<u64>> {
+        let stat = nix::sys::statvfs::statvfs(path)?;
+        // filesystems which allocate inodes dynamically, such as btrfs, report a total of zero
+        if stat.files() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(stat.files_available() as u64))
+    }
+
+    #[cfg(not(unix))]
+    fn available_inodes(&self, _path: &Path) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn is_undeletable(&self, path: &Path) -> bool {
+        has_undeletable_attribute(path).unwrap_or(false)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn is_undeletable(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+/// The device number shared by every file in a `MemoryFileSystem`.
+const MEMORY_DEVICE : u64 = 0;
+
+/// A filesystem of a fixed capacity held entirely in memory, for tests. It only has regular files,
+/// each occupying exactly its length however many hard links it has, and directories exist just
+/// while there are files beneath them. Walks visit files in order of path and honor the depth
+/// limits, keep markers and closed directories, but not ignore files, symlinks or other
+/// filesystems, and no inodes are limited.
+pub struct MemoryFileSystem {
+    state : Mutex<MemoryState>,
+}
+
+struct MemoryState {
+    capacity : u64,
+    files : BTreeMap<PathBuf, FileStat>,
+    next_inode : u64,
+    /// the error with which disposing each of these paths fails
+    failures : HashMap<PathBuf, io::ErrorKind>,
+    /// the error with which reading the metadata of each of these paths fails
+    unreadable : HashMap<PathBuf, io::ErrorKind>,
+    undeletable : HashSet<PathBuf>,
+}
+
+impl MemoryState {
+    fn is_directory(&self, path: &Path) -> bool {
+        self.files.keys().any(|file| file != path && file.starts_with(path))
+    }
+
+    fn directory_stat() -> FileStat {
+        FileStat {
+            is_file : false,
+            is_symlink : false,
+            len : 0,
+            allocated : Some(0),
+            accessed : None,
+            modified : None,
+            created : None,
+            changed : None,
+            inode : Some((MEMORY_DEVICE, 0)),
+            links : 1,
+            owner : None,
+            hidden : false,
+            readonly : false,
+        }
+    }
+}
+
+impl MemoryFileSystem {
+    /// An empty filesystem which can hold `capacity` bytes.
+    pub fn new(capacity: u64) -> Self {
+        MemoryFileSystem {
+            state : Mutex::new(MemoryState { capacity, files : BTreeMap::new(), next_inode : 1, failures : HashMap::new(), unreadable : HashMap::new(), undeletable : HashSet::new() }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        self.state.lock().expect("the lock is never poisoned")
+    }
+
+    /// Add a file of `len` bytes at `path`, replacing any already there, with every timestamp set
+    /// to `accessed`.
+    pub fn add_file(&self, path: impl Into<PathBuf>, len: u64, accessed: SystemTime) {
+        let mut state = self.state();
+        let inode = state.next_inode;
+        state.next_inode += 1;
+        state.files.insert(path.into(), FileStat {
+            is_file : true,
+            is_symlink : false,
+            len,
+            allocated : Some(len),
+            accessed : Some(accessed),
+            modified : Some(accessed),
+            created : Some(accessed),
+            changed : Some(accessed),
+            inode : Some((MEMORY_DEVICE, inode)),
+            links : 1,
+            owner : None,
+            hidden : false,
+            readonly : false,
+        });
+    }
+
+    /// Add `link` as another hard link to the file at `path`, sharing its inode and so its space.
+    pub fn add_link(&self, path: &Path, link: impl Into<PathBuf>) {
+        let mut state = self.state();
+        if let Some(metadata) = state.files.get(path).cloned() {
+            for other in state.files.values_mut().filter(|other| other.inode == metadata.inode) {
+                other.links += 1;
+            }
+            state.files.insert(link.into(), FileStat { links : metadata.links + 1, ..metadata });
+        }
+    }
+
+    /// Make every attempt to dispose of the file at `path` fail with an error of `kind`.
+    pub fn fail_removal(&self, path: impl Into<PathBuf>, kind: io::ErrorKind) {
+        self.state().failures.insert(path.into(), kind);
+    }
+
+    /// Mark the file at `path` as undeletable, as the immutable attribute does, so that every
+    /// attempt to dispose of it fails as permission being denied.
+    pub fn make_undeletable(&self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        let mut state = self.state();
+        state.failures.insert(path.clone(), io::ErrorKind::PermissionDenied);
+        state.undeletable.insert(path);
+    }
+
+    /// Withhold write permission to the file at `path`, as chmod would.
+    pub fn make_readonly(&self, path: &Path) {
+        if let Some(metadata) = self.state().files.get_mut(path) {
+            metadata.readonly = true;
+        }
+    }
+
+    /// Make reading the metadata of the file at `path` fail with an error of `kind`, both while
+    /// walking and when it's stat'd.
+    pub fn fail_stat(&self, path: impl Into<PathBuf>, kind: io::ErrorKind) {
+        self.state().unreadable.insert(path.into(), kind);
+    }
+
+    /// Set when the file at `path` was last accessed, or that the filesystem didn't record it.
+    pub fn set_accessed(&self, path: &Path, accessed: Option<SystemTime>) {
+        if let Some(metadata) = self.state().files.get_mut(path) {
+            metadata.accessed = accessed;
+        }
+    }
+
+    /// Set when the file at `path` was last modified, or that the filesystem didn't record it.
+    pub fn set_modified(&self, path: &Path, modified: Option<SystemTime>) {
+        if let Some(metadata) = self.state().files.get_mut(path) {
+            metadata.modified = modified;
+        }
+    }
+
+    /// Set when the file at `path` was created, or that the filesystem didn't record it.
+    pub fn set_created(&self, path: &Path, created: Option<SystemTime>) {
+        if let Some(metadata) = self.state().files.get_mut(path) {
+            metadata.created = created;
+        }
+    }
+
+    /// Whether there's a file at `path`.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.state().files.contains_key(path)
+    }
+
+    /// The paths of every file, in order.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.state().files.keys().cloned().collect()
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn walk(&self, root: &Path, options: &WalkOptions) -> Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> {
+        let state = self.state();
+        let kept = |directory: &Path| options.keep_marker.as_ref().is_some_and(|keep_marker| state.files.contains_key(&directory.join(keep_marker)));
+        let entries : Vec<_> = state.files.iter()
+            .filter(|(path, _)| path.starts_with(root))
+            .filter(|(path, _)| {
+                let depth = path.strip_prefix(root).map_or(0, |relative_path| relative_path.components().count());
+                depth >= options.min_depth && options.max_depth.is_none_or(|max_depth| depth <= max_depth)
+            })
+            .filter(|(path, _)| !path.ancestors().skip(1).take_while(|directory| directory.starts_with(root)).any(kept))
+            .map(|(path, metadata)| match state.unreadable.get(path) {
+                Some(kind) => Err(WalkError { path : Some(path.clone()), error : (*kind).into() }),
+                None => Ok(WalkEntry { path : path.clone(), metadata : metadata.clone() }),
+            })
+            .collect();
+        let closed_directories = options.closed_directories.clone();
+        Box::new(entries.into_iter()
+            .filter(move |entry| {
+                let path = match entry {
+                    Ok(entry) => Some(&entry.path),
+                    Err(err) => err.path.as_ref(),
+                };
+                closed_directories.as_ref().is_none_or(|closed_directories| {
+                    path.and_then(|path| path.parent()).is_none_or(|parent| !closed_directories.lock().expect("the lock is never poisoned").contains(parent))
+                })
+            }))
+    }
+
+    fn stat(&self, path: &Path, _follow_symlinks: bool) -> io::Result<FileStat> {
+        let state = self.state();
+        if let Some(kind) = state.unreadable.get(path) {
+            return Err((*kind).into());
+        }
+        match state.files.get(path) {
+            Some(metadata) => Ok(metadata.clone()),
+            None if state.is_directory(path) => Ok(MemoryState::directory_stat()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.stat(path, true).map(|_| path.to_path_buf())
+    }
+
+    fn remove(&self, disposal: &Disposal, root: &Path, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        if let Some(kind) = state.failures.get(path) {
+            return Err((*kind).into());
+        }
+        let destination = match disposal {
+            Disposal::MoveTo(directory) => Some(directory.join(path.strip_prefix(root).map_err(|_| {
+                io::Error::other(format!("{} is not beneath {}", path.display(), root.display()))
+            })?)),
+            Disposal::Delete | Disposal::Trash | Disposal::Shred(_) => None,
+        };
+        if destination.as_ref().is_some_and(|destination| state.files.contains_key(destination)) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        let metadata = state.files.remove(path).ok_or(io::ErrorKind::NotFound)?;
+        match destination {
+            Some(destination) => {
+                state.files.insert(destination, metadata);
+            }
+            None => {
+                for other in state.files.values_mut().filter(|other| other.inode == metadata.inode) {
+                    other.links -= 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        if self.state().is_directory(path) {
+            return Err(io::ErrorKind::DirectoryNotEmpty.into());
+        }
+        Ok(())
+    }
+
+    fn available_space(&self, _path: &Path) -> io::Result<u64> {
+        let state = self.state();
+        // hard links share their space
+        let used : HashMap<_, _> = state.files.values().map(|metadata| (metadata.inode, metadata.len)).collect();
+        Ok(state.capacity.saturating_sub(used.values().sum()))
+    }
+
+    fn total_space(&self, _path: &Path) -> io::Result<u64> {
+        Ok(self.state().capacity)
+    }
+
+    fn available_inodes(&self, _path: &Path) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    fn is_undeletable(&self, path: &Path) -> bool {
+        self.state().undeletable.contains(path)
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Context;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The global ignore files, lowest precedence first: the system's, then the user's. Either may be
+/// missing, as may the variables they're found through.
+pub fn layers() -> Vec<PathBuf> {
+    let mut layers = Vec::new();
+    if cfg!(windows) {
+        layers.extend(env::var_os("ProgramData").map(|directory| Path::new(&directory).join("lru").join("ignore")));
+        layers.extend(env::var_os("APPDATA").map(|directory| Path::new(&directory).join("lru").join("ignore")));
+    } else {
+        layers.push(PathBuf::from("/etc/lru/ignore"));
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|directory| Path::new(directory).is_absolute())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        layers.extend(config_home.map(|directory| directory.join("lru").join("ignore")));
+    }
+    layers
+}
+
+/// The --exclude patterns of the ignore files at `layers`, lowest precedence first, followed by
+/// `cli`, which take precedence over all of them. Each file holds one glob per line, with blank
+/// lines and those starting with `#` passed over. A line `!glob` withdraws `glob` from the files
+/// before it, e.g. so that a user can keep deleting what the system ignores, but can't withdraw
+/// patterns given on the command line or in a --config file. Missing files are passed over, and
+/// unreadable ones or invalid globs are an error.
+pub fn merge(layers: &[PathBuf], cli: Vec<String>) -> anyhow::Result<Vec<String>> {
+    let mut patterns = Vec::<String>::new();
+    for path in layers {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to read global ignore file {}", path.display())),
+        };
+        // a file only withdraws what lower layers add, never its own patterns
+        let (mut added, mut withdrawn) = (Vec::new(), Vec::new());
+        for (n_line, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (list, glob) = match line.strip_prefix('!') {
+                Some(glob) => (&mut withdrawn, glob.trim_start()),
+                None => (&mut added, line),
+            };
+            globset::Glob::new(glob).with_context(|| format!("invalid glob on line {} of global ignore file {}", n_line + 1, path.display()))?;
+            list.push(glob.to_string());
+        }
+        log::debug!("Read {} patterns from global ignore file {}, withdrawing {}", added.len(), path.display(), withdrawn.len());
+        patterns.retain(|pattern| !withdrawn.contains(pattern));
+        patterns.extend(added);
+    }
+    patterns.extend(cli);
+    Ok(patterns)
+}
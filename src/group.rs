@@ -0,0 +1,74 @@
+use crate::FileInfo;
+use chrono::prelude::*;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The groups of files found by a walk, keyed by what `ReclaimConfig::group_by` extracts from their
+/// paths, so that each group can be deleted as a whole or not at all.
+pub struct Groups<'a> {
+    pattern : &'a Regex,
+    groups : HashMap<String, Group>,
+}
+
+#[derive(Default)]
+struct Group {
+    /// the number of files found in the group, including those which can't be deleted
+    n_found : usize,
+    /// the files of the group which could be deleted
+    members : Vec<FileInfo>,
+}
+
+impl<'a> Groups<'a> {
+    pub fn new(pattern: &'a Regex) -> Self {
+        Groups { pattern, groups : HashMap::new() }
+    }
+
+    /// The key of the group of the file at `relative_path`: what the pattern's first capture group
+    /// matches, or the whole match if it has none. Files the pattern doesn't match are in no group.
+    pub fn key(&self, relative_path: &Path) -> Option<String> {
+        let relative_path = relative_path.to_string_lossy();
+        let captures = self.pattern.captures(&relative_path)?;
+        let key = captures.get(1).or_else(|| captures.get(0))?;
+        Some(key.as_str().to_string())
+    }
+
+    /// Note that a file of the group `key` was found, whether or not it can be deleted.
+    pub fn found(&mut self, key: &str) {
+        match self.groups.get_mut(key) {
+            Some(group) => group.n_found += 1,
+            None => {
+                self.groups.insert(key.to_string(), Group { n_found : 1, members : Vec::new() });
+            }
+        }
+    }
+
+    /// Add `file`, which could be deleted, to the group `key` it was found in.
+    pub fn admit(&mut self, key: &str, file: FileInfo) {
+        if let Some(group) = self.groups.get_mut(key) {
+            group.members.push(file);
+        }
+    }
+
+    /// Each group every file of which could be deleted, as a single file whose paths are those of
+    /// its members. It was accessed when its least recently used member was, takes the space of
+    /// all of them and the frequency of the most frequently used, and is ranked by `rank`
+    /// accordingly. Groups with any file which can't be deleted are kept whole.
+    pub fn into_files(self, rank: impl Fn(DateTime<Local>, u64, u64) -> (i128, i128)) -> impl Iterator<Item = FileInfo> {
+        self.groups.into_iter().filter_map(move |(key, group)| {
+            if group.members.len() != group.n_found {
+                log::debug!("Keeping the {} files of group {:?}, since {} of them can't be deleted", group.n_found, key, group.n_found - group.members.len());
+                return None;
+            }
+            let mut members = group.members;
+            members.sort_by(|a, b| a.path.cmp(&b.path));
+            let accessed = members.iter().map(|member| member.accessed).min()?;
+            let frequency = members.iter().map(|member| member.frequency).max()?;
+            let size = members.iter().map(|member| member.size).sum();
+            let length = members.iter().map(|member| member.length).sum();
+            let mut paths = members.into_iter().map(|member| member.path);
+            let path = paths.next()?;
+            Some(FileInfo { rank : rank(accessed, size, frequency), accessed, frequency, size, length, inode : None, path, links : paths.collect() })
+        })
+    }
+}
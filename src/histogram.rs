@@ -0,0 +1,74 @@
+use chrono::{DateTime, Local};
+use lru::FileInfo;
+use serde::Serialize;
+
+/// The buckets files are counted in by age, each labelled and holding files younger than its
+/// bound in days.
+const AGES : &[(&str, i64)] = &[("<1d", 1), ("1-7d", 7), ("7-30d", 30), (">30d", i64::MAX)];
+/// The buckets files are counted in by size, each labelled and holding files smaller than its
+/// bound in bytes.
+const SIZES : &[(&str, u64)] = &[
+    ("<4KiB", 4 << 10),
+    ("4KiB-1MiB", 1 << 20),
+    ("1MiB-100MiB", 100 << 20),
+    ("100MiB-1GiB", 1 << 30),
+    (">1GiB", u64::MAX),
+];
+
+#[derive(Serialize)]
+struct Bucket {
+    bucket : &'static str,
+    files : usize,
+    bytes : u64,
+}
+
+/// The distribution of the ages and sizes of the files beneath every top-level directory, for
+/// --histogram. Ages are measured by the timestamp chosen with --by, and sizes by --size-source.
+#[derive(Serialize)]
+pub struct Histogram {
+    files : usize,
+    bytes : u64,
+    by_age : Vec<Bucket>,
+    by_size : Vec<Bucket>,
+    #[serde(skip)]
+    now : DateTime<Local>,
+}
+
+fn buckets<T>(bounds: &[(&'static str, T)]) -> Vec<Bucket> {
+    bounds.iter().map(|(bucket, _)| Bucket { bucket, files : 0, bytes : 0 }).collect()
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram { files : 0, bytes : 0, by_age : buckets(AGES), by_size : buckets(SIZES), now : Local::now() }
+    }
+
+    pub fn add(&mut self, file: &FileInfo) {
+        self.files += 1;
+        self.bytes += file.size;
+        // files from the future count as new
+        let age_in_days = (self.now - file.accessed).num_days();
+        let by_age = AGES.iter().position(|(_, bound)| age_in_days < *bound).unwrap_or(AGES.len() - 1);
+        let by_size = SIZES.iter().position(|(_, bound)| file.size < *bound).unwrap_or(SIZES.len() - 1);
+        for bucket in [&mut self.by_age[by_age], &mut self.by_size[by_size]] {
+            bucket.files += 1;
+            bucket.bytes += file.size;
+        }
+    }
+
+    /// Print the histogram as a table of counts and bytes for each bucket.
+    pub fn print_human(&self) {
+        for (heading, buckets) in [("age", &self.by_age), ("size", &self.by_size)] {
+            println!("{:<12} {:>10} {:>16}", heading, "files", "bytes");
+            for bucket in buckets {
+                println!("{:<12} {:>10} {:>16}", bucket.bucket, bucket.files, bucket.bytes);
+            }
+            println!();
+        }
+        println!("{:<12} {:>10} {:>16}", "total", self.files, self.bytes);
+    }
+
+    pub fn print_json(&self) {
+        println!("{}", serde_json::to_string(self).expect("histograms are always serializable"));
+    }
+}
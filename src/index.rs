@@ -0,0 +1,77 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The name of the sidecar file, stored at the top-level directory, which persists access
+/// frequencies between runs for --strategy lfu.
+pub const FILE_NAME : &str = ".lru-index.json";
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// the last access time we observed, in nanoseconds since the unix epoch
+    accessed : i64,
+    /// the number of runs in which we observed the access time advance
+    frequency : u64,
+}
+
+/// Tracks how often each file has been accessed across runs. Since filesystems only record the
+/// most recent access, a file's frequency is the number of runs in which we saw its access time
+/// advance since the previous run.
+#[derive(Default)]
+pub struct FrequencyIndex {
+    /// entries loaded from the previous run
+    previous : HashMap<PathBuf, Entry>,
+    /// entries observed during this run, which are persisted by `save`
+    current : HashMap<PathBuf, Entry>,
+}
+
+impl FrequencyIndex {
+    /// Load the index written by a previous run, or start an empty one if none exists.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        let previous = serde_json::from_reader(BufReader::new(file))?;
+        Ok(FrequencyIndex { previous, current : HashMap::new() })
+    }
+
+    /// Record that `path` was last accessed at `accessed`, returning its access frequency. Files
+    /// which weren't in the previous run's index start with a frequency of zero.
+    pub fn observe(&mut self, path: &Path, accessed: DateTime<Local>) -> u64 {
+        let accessed = accessed.timestamp_nanos_opt().unwrap_or(i64::MAX);
+        let frequency = match self.previous.remove(path) {
+            Some(entry) if accessed > entry.accessed => entry.frequency + 1,
+            Some(entry) => entry.frequency,
+            None => 0,
+        };
+        self.current.insert(path.to_path_buf(), Entry { accessed, frequency });
+        frequency
+    }
+
+    /// The access frequency of a file already observed during this run.
+    pub fn frequency(&self, path: &Path) -> u64 {
+        self.current.get(path).map_or(0, |entry| entry.frequency)
+    }
+
+    /// Drop a file which has been deleted from the index.
+    pub fn forget(&mut self, path: &Path) {
+        self.current.remove(path);
+    }
+
+    /// Persist the files observed during this run. Files which weren't observed are dropped.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // write to a temporary file and rename it into place so that an interrupted run never
+        // leaves a truncated index behind
+        let temporary_path = path.with_extension("json.tmp");
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        serde_json::to_writer(&mut writer, &self.current)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temporary_path, path)
+    }
+}
@@ -0,0 +1,82 @@
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A length of time, parsed from a human-readable string such as `30s`, `5m`, `2h` or `1d`. A bare
+/// number is a count of seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Interval(pub Duration);
+
+const SUFFIXES : &[(&str, u64)] = &[("s", 1), ("m", 60), ("h", 60 * 60), ("d", 24 * 60 * 60)];
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let split = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(trimmed.len());
+        let (number, suffix) = trimmed.split_at(split);
+        let suffix = suffix.trim().to_ascii_lowercase();
+
+        let multiplier = if suffix.is_empty() {
+            1
+        } else {
+            match SUFFIXES.iter().find(|(name, _)| *name == suffix) {
+                Some((_, multiplier)) => *multiplier,
+                None => return Err(format!("invalid interval {:?}: unknown suffix {:?} (expected s, m, h or d)", s, suffix)),
+            }
+        };
+        let seconds = number.parse::<u64>()
+            .map_err(|_| format!("invalid interval {:?}: expected a whole number optionally followed by a unit, e.g. 30s or 5m", s))?;
+        match seconds.checked_mul(multiplier) {
+            Some(0) => Err(format!("invalid interval {:?}: must be greater than 0", s)),
+            Some(seconds) => Ok(Interval(Duration::from_secs(seconds))),
+            None => Err(format!("invalid interval {:?}: too large", s)),
+        }
+    }
+}
+
+/// Intervals in config files may be either a number of seconds or a string in the same format as
+/// on the command line.
+impl<'de> Deserialize<'de> for Interval {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct IntervalVisitor;
+
+        impl Visitor<'_> for IntervalVisitor {
+            type Value = Interval;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a number of seconds or an interval such as \"5m\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, seconds: u64) -> Result<Interval, E> {
+                self.visit_str(&seconds.to_string())
+            }
+
+            fn visit_i64<E: de::Error>(self, seconds: i64) -> Result<Interval, E> {
+                if seconds < 0 {
+                    return Err(E::custom(format!("invalid interval {}: must not be negative", seconds)));
+                }
+                self.visit_str(&seconds.to_string())
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Interval, E> {
+                s.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(IntervalVisitor)
+    }
+}
+
+/// The time to wait between runs for --min-interval and --max-interval, given how much more free
+/// space than its target the top-level directory closest to its target had after the last run, as
+/// a fraction of that target. The wait grows exponentially with the headroom, from `min` with none
+/// to `max` with as much free space again as the target, so that each extra slice of headroom
+/// multiplies the wait by the same factor.
+pub fn adaptive(min: Duration, max: Duration, headroom: f64) -> Duration {
+    let headroom = headroom.clamp(0.0, 1.0);
+    let (min, max) = (min.as_secs_f64(), max.as_secs_f64());
+    Duration::from_secs_f64(min * (max / min).powf(headroom))
+}
@@ -0,0 +1,294 @@
+use lru::{Disposal, FileInfo, ReclaimReport};
+use nix::fcntl::{fcntl, FcntlArg, SealFlag};
+use nix::libc;
+use nix::sys::memfd::{memfd_create, MFdFlags};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+
+/// Where journald listens for entries sent with its native protocol.
+const SOCKET_PATH : &str = "/run/systemd/journal/socket";
+
+/// The syslog priorities of the entries we send.
+const PRIORITY_WARNING : &str = "4";
+const PRIORITY_NOTICE : &str = "5";
+const PRIORITY_INFO : &str = "6";
+
+/// Sends an entry to the systemd journal for every file a run disposes of and for the outcome of
+/// each top-level directory, with fields such as LRU_PATH, LRU_SIZE and LRU_BYTES_FREED to filter
+/// them by, e.g. `journalctl LRU_PATH=/var/cache/foo`.
+pub struct Journal {
+    socket : UnixDatagram,
+    /// e.g. "Deleted", or "Would delete" in a dry run
+    verb : &'static str,
+    /// e.g. "delete", or "would-delete" in a dry run, as in --format ndjson's events
+    action : &'static str,
+    dry_run : bool,
+}
+
+/// Append the field `name` with `value` to `entry`, in the journal's native serialization.
+fn append_field(entry: &mut Vec<u8>, name: &str, value: &[u8]) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains(&b'\n') {
+        // values spanning lines are sent as their length in little-endian bytes, then the value
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value);
+    entry.push(b'\n');
+}
+
+/// Send `entry` over `socket`, as a datagram if it fits in one, like most do. Larger entries, such
+/// as those for very long paths, are written to a sealed memfd instead, whose descriptor is sent
+/// alone, as journald expects.
+fn send_entry(socket: &UnixDatagram, entry: &[u8]) -> io::Result<()> {
+    match socket.send(entry) {
+        Err(err) if err.raw_os_error() == Some(libc::EMSGSIZE) || err.raw_os_error() == Some(libc::ENOBUFS) => {
+            let memfd = memfd_create("lru-journal-entry", MFdFlags::MFD_ALLOW_SEALING | MFdFlags::MFD_CLOEXEC)?;
+            let mut file = File::from(memfd);
+            file.write_all(entry)?;
+            // journald refuses descriptors whose contents could still change under it
+            let seals = SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_GROW | SealFlag::F_SEAL_WRITE | SealFlag::F_SEAL_SEAL;
+            fcntl(&file, FcntlArg::F_ADD_SEALS(seals))?;
+            send_descriptor(socket, &file)
+        }
+        result => result.map(|_| ()),
+    }
+}
+
+/// Send `file`'s descriptor over `socket`, in an otherwise empty datagram.
+fn send_descriptor(socket: &UnixDatagram, file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    // u64s keep the buffer aligned for the header, and 4 of them fit one descriptor's worth
+    let mut control = [0u64; 4];
+    // SAFETY: CMSG_SPACE is arithmetic on its argument alone
+    let control_len = unsafe { libc::CMSG_SPACE(std::mem::size_of_val(&fd) as _) } as usize;
+    assert!(control_len <= std::mem::size_of_val(&control));
+    // SAFETY: msghdr is plain data, for which all zeroes describe no address, data or ancillary data
+    let mut message : libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = control_len as _;
+    // SAFETY: `message` describes `control`, which is large enough for a header, so the first
+    // header is non-null and lies within it
+    let header = unsafe { libc::CMSG_FIRSTHDR(&message) };
+    // SAFETY: `header` points to a whole header within `control`, which outlives these writes, and
+    // CMSG_DATA lies after it with room left for the descriptor, which isn't necessarily aligned
+    unsafe {
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(std::mem::size_of_val(&fd) as _) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(header).cast(), fd);
+    }
+    // SAFETY: `message` and `control` outlive the call, and both descriptors are open for its
+    // duration, since `socket` and `file` are borrowed
+    if unsafe { libc::sendmsg(socket.as_raw_fd(), &message, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Journal {
+    pub fn connect(disposal: &Disposal, dry_run: bool) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(SOCKET_PATH)?;
+        Ok(Journal {
+            socket,
+            verb : if dry_run { "Would delete" } else { disposal.verb() },
+            action : if dry_run { "would-delete" } else { disposal.action() },
+            dry_run,
+        })
+    }
+
+    fn send(&self, priority: &str, message: &str, fields: &[(&str, &[u8])]) -> io::Result<()> {
+        let mut entry = Vec::new();
+        append_field(&mut entry, "MESSAGE", message.as_bytes());
+        append_field(&mut entry, "PRIORITY", priority.as_bytes());
+        append_field(&mut entry, "SYSLOG_IDENTIFIER", b"lru");
+        append_field(&mut entry, "LRU_ACTION", self.action.as_bytes());
+        append_field(&mut entry, "LRU_DRY_RUN", if self.dry_run { b"1" } else { b"0" });
+        for (name, value) in fields {
+            append_field(&mut entry, name, value);
+        }
+        send_entry(&self.socket, &entry)
+    }
+
+    /// Record that every path of `file` was disposed of, or would have been in a dry run.
+    pub fn disposed(&self, file: &FileInfo) -> io::Result<()> {
+        for (i, path) in file.paths().enumerate() {
+            // the file's size is attributed to its first path, as in the other output formats
+            let size = if i == 0 { file.size } else { 0 };
+            self.send(PRIORITY_INFO, &format!("{} {} ({} bytes)", self.verb, path.display(), size), &[
+                ("LRU_PATH", path.as_os_str().as_bytes()),
+                ("LRU_SIZE", size.to_string().as_bytes()),
+                ("LRU_ACCESSED", file.accessed.timestamp().to_string().as_bytes()),
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// Record the outcome of the run for a top-level directory, as a warning if it fell short.
+    pub fn finished(&self, report: &ReclaimReport) -> io::Result<()> {
+        let n_skipped : usize = report.skipped.values().sum();
+        let n_failed : usize = report.failed.values().sum();
+        let priority = if n_failed > 0 || report.target_unmet || report.aborted || report.interrupted {
+            PRIORITY_WARNING
+        } else {
+            PRIORITY_NOTICE
+        };
+        let message = format!("{} {} files ({} bytes) from {}", self.verb, report.deleted.len(), report.bytes_freed, report.path.display());
+        let flag = |set: bool| if set { "1" } else { "0" }.to_string();
+        let mut fields = vec![
+            ("LRU_CANDIDATES", report.candidates.to_string()),
+            ("LRU_FILES", report.deleted.len().to_string()),
+            ("LRU_BYTES_FREED", report.bytes_freed.to_string()),
+            ("LRU_FREE_BEFORE", report.free_before.to_string()),
+            ("LRU_FREE_AFTER", report.free_after.to_string()),
+            ("LRU_SKIPPED", n_skipped.to_string()),
+            ("LRU_FAILED", n_failed.to_string()),
+            ("LRU_TARGET_UNMET", flag(report.target_unmet)),
+            ("LRU_INTERRUPTED", flag(report.interrupted)),
+            ("LRU_ABORTED", flag(report.aborted)),
+        ];
+        if let (Some(target), Some(target_met)) = (report.target, report.target_met) {
+            fields.push(("LRU_TARGET", target.to_string()));
+            fields.push(("LRU_TARGET_MET", flag(target_met)));
+        }
+        let path = ("LRU_PATH", report.path.as_os_str().as_bytes());
+        let fields : Vec<_> = std::iter::once(path).chain(fields.iter().map(|(name, value)| (*name, value.as_bytes()))).collect();
+        self.send(priority, &message, &fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+    use chrono::Local;
+    use std::convert::TryInto;
+    use std::os::unix::io::{FromRawFd, RawFd};
+    use std::path::{Path, PathBuf};
+
+    /// A `Journal` sending over one end of a socket pair, and the end it sends to.
+    fn journal() -> (Journal, UnixDatagram) {
+        let (socket, receiver) = UnixDatagram::pair().unwrap();
+        (Journal { socket, verb : "Deleted", action : "delete", dry_run : false }, receiver)
+    }
+
+    /// The fields of `entry`, in the journal's native serialization, in order.
+    fn parse(mut entry: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut fields = Vec::new();
+        while !entry.is_empty() {
+            let end = entry.iter().position(|byte| *byte == b'=' || *byte == b'\n').unwrap();
+            let name = String::from_utf8(entry[..end].to_vec()).unwrap();
+            let value = if entry[end] == b'=' {
+                let length = entry[end + 1..].iter().position(|byte| *byte == b'\n').unwrap();
+                let value = entry[end + 1..end + 1 + length].to_vec();
+                entry = &entry[end + 1 + length + 1..];
+                value
+            } else {
+                let length = u64::from_le_bytes(entry[end + 1..end + 9].try_into().unwrap()) as usize;
+                let value = entry[end + 9..end + 9 + length].to_vec();
+                assert_eq!(entry[end + 9 + length], b'\n');
+                entry = &entry[end + 9 + length + 1..];
+                value
+            };
+            fields.push((name, value));
+        }
+        fields
+    }
+
+    /// The value of the field `name` of `entry`.
+    fn field<'a>(fields: &'a [(String, Vec<u8>)], name: &str) -> &'a [u8] {
+        &fields.iter().find(|(field, _)| field == name).unwrap().1
+    }
+
+    /// The descriptor sent alone in the next datagram on `socket`.
+    fn receive_descriptor(socket: &UnixDatagram) -> File {
+        let mut control = [0u64; 4];
+        let mut data = [0u8; 1];
+        let mut iov = libc::iovec { iov_base : data.as_mut_ptr().cast(), iov_len : data.len() };
+        // SAFETY: msghdr is plain data, for which all zeroes describe no address, data or
+        // ancillary data
+        let mut message : libc::msghdr = unsafe { std::mem::zeroed() };
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = std::mem::size_of_val(&control) as _;
+        // SAFETY: `message`, `iov`, `data` and `control` outlive the call, and the socket is open
+        assert_eq!(unsafe { libc::recvmsg(socket.as_raw_fd(), &mut message, 0) }, 0);
+        // SAFETY: `message` describes `control`, which the kernel has just filled in
+        let header = unsafe { libc::CMSG_FIRSTHDR(&message) };
+        assert!(!header.is_null());
+        // SAFETY: `header` is non-null, so it points to a whole header within `control`
+        assert_eq!(unsafe { ((*header).cmsg_level, (*header).cmsg_type) }, (libc::SOL_SOCKET, libc::SCM_RIGHTS));
+        // SAFETY: an SCM_RIGHTS header is followed by the descriptors it passes, here just the one,
+        // which isn't necessarily aligned
+        let fd : RawFd = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(header).cast()) };
+        // SAFETY: the descriptor was just passed to us, so nothing else owns it
+        unsafe { File::from_raw_fd(fd) }
+    }
+
+    /// The whole of the entry sealed in `file`.
+    fn read_sealed(mut file: File) -> Vec<u8> {
+        let seals = SealFlag::from_bits_truncate(fcntl(&file, FcntlArg::F_GET_SEALS).unwrap());
+        assert!(seals.contains(SealFlag::F_SEAL_SHRINK | SealFlag::F_SEAL_GROW | SealFlag::F_SEAL_WRITE | SealFlag::F_SEAL_SEAL));
+        // the descriptor shares the offset its writing left at the end, whereas journald maps it
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn values_spanning_lines_are_sent_with_their_length() {
+        let mut entry = Vec::new();
+        append_field(&mut entry, "LRU_PATH", b"/tmp/a\nb");
+        append_field(&mut entry, "LRU_SIZE", b"1");
+        assert_eq!(entry, b"LRU_PATH\n\x08\0\0\0\0\0\0\0/tmp/a\nb\nLRU_SIZE=1\n");
+
+        let (journal, receiver) = journal();
+        let path = Path::new("/tmp/line\nbreak");
+        let file = FileInfo { rank : (0, 0), accessed : Local::now(), frequency : 0, size : 100, length : 100, inode : None, path : path.into(), links : Vec::new() };
+        journal.disposed(&file).unwrap();
+        let mut received = vec![0; 4096];
+        let n_received = receiver.recv(&mut received).unwrap();
+        let fields = parse(&received[..n_received]);
+        assert_eq!(field(&fields, "LRU_PATH"), path.as_os_str().as_bytes());
+        assert_eq!(field(&fields, "MESSAGE"), b"Deleted /tmp/line\nbreak (100 bytes)");
+        assert_eq!(field(&fields, "LRU_SIZE"), b"100");
+    }
+
+    #[test]
+    fn entries_too_large_for_a_datagram_are_sent_in_a_sealed_memfd() {
+        let (sender, receiver) = UnixDatagram::pair().unwrap();
+        let mut small = Vec::new();
+        append_field(&mut small, "MESSAGE", b"Deleted /tmp/a (1 bytes)");
+        send_entry(&sender, &small).unwrap();
+        let mut received = vec![0; small.len() + 1];
+        assert_eq!(receiver.recv(&mut received).unwrap(), small.len());
+        assert_eq!(&received[..small.len()], &small[..]);
+
+        // far beyond the largest datagram the kernel accepts by default
+        let mut large = Vec::new();
+        append_field(&mut large, "LRU_PATH", "a/".repeat(4 << 20).as_bytes());
+        send_entry(&sender, &large).unwrap();
+        let contents = read_sealed(receive_descriptor(&receiver));
+        assert!(contents == large, "read {} bytes of the {} sent", contents.len(), large.len());
+    }
+
+    #[test]
+    fn disposing_of_a_file_with_a_huge_path_spanning_lines_sends_it_whole() {
+        let (journal, receiver) = journal();
+        let path = PathBuf::from(format!("/{}\n{}", "a/".repeat(1 << 20), "b".repeat(1 << 20)));
+        let file = FileInfo { rank : (0, 0), accessed : Local::now(), frequency : 0, size : 1, length : 1, inode : None, path : path.clone(), links : Vec::new() };
+        journal.disposed(&file).unwrap();
+        let fields = parse(&read_sealed(receive_descriptor(&receiver)));
+        assert!(field(&fields, "LRU_PATH") == path.as_os_str().as_bytes());
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), b"lru");
+        assert_eq!(field(&fields, "LRU_ACTION"), b"delete");
+    }
+}
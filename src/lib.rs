@@ -0,0 +1,1859 @@
+//! Turn a filesystem into an LRU cache: when the filesystem holding a directory runs low on free
+//! space, reclaim files beneath it in least-recently-used order until a target is reached.
+
+use anyhow::Context;
+use chrono::prelude::*;
+use chrono::Duration;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexSet};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::cmp::{Ordering, Reverse};
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+mod clock;
+mod dispose;
+mod eligible;
+mod filesystem;
+mod group;
+mod index;
+mod low_memory;
+mod open;
+mod walk;
+mod walk_index;
+pub use clock::{Clock, FixedClock, SystemClock};
+pub use dispose::Disposal;
+pub use filesystem::{FileStat, FileSystem, MemoryFileSystem, RealFileSystem};
+use dispose::{is_out_of_space, Throttle};
+use eligible::{Eligibility, Tally, Walk};
+use index::FrequencyIndex;
+use walk::{is_hidden, stat_listed};
+pub use walk::{ClosedDirectories, WalkEntry, WalkError, WalkOptions};
+pub use walk_index::{SharedWalkIndex, WalkIndex};
+
+/// The name of the file which, by default, protects the directory containing it, along with
+/// everything beneath it.
+pub const DEFAULT_KEEP_MARKER : &str = ".lrukeep";
+
+/// The share of the candidates which a plan may delete before it's suspected of being
+/// misconfigured.
+const MAX_SHARE_OF_CANDIDATES : f64 = 0.9;
+
+/// A file selected for reclamation. Files are equal whenever they compare as equal, i.e. their
+/// rank, path and size are.
+#[derive(Clone)]
+pub struct FileInfo {
+    /// the eviction rank assigned by the strategy in use, compared lexicographically. files with a
+    /// lower rank are deleted first, so the heap's maximum is always the file we'd most like to
+    /// keep
+    pub rank : (i128, i128),
+    pub accessed : DateTime<Local>,
+    /// the number of runs in which this file was seen to be accessed, as tracked by `Strategy::Lfu`
+    pub frequency : u64,
+    /// the bytes freed by deleting the file along with all of its links, as measured by the
+    /// `SizeSource` in use
+    pub size : u64,
+    /// the file's logical length in bytes, which may differ from `size` for sparse or compressed
+    /// files
+    pub length : u64,
+    /// the device and inode numbers of the file, where the platform provides them
+    pub inode : Option<(u64, u64)>,
+    pub path : PathBuf,
+    /// the other hard links to the file, which are deleted along with `path` since no space is
+    /// freed until every link has been
+    pub links : Vec<PathBuf>,
+}
+
+impl FileInfo {
+    /// All paths which are deleted along with this file.
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.path).chain(&self.links)
+    }
+
+    /// Whether this file ranks at or below `threshold`, a file's rank and path.
+    fn is_at_or_below(&self, threshold: Option<&((i128, i128), PathBuf)>) -> bool {
+        threshold.is_some_and(|(rank, path)| (self.rank, &self.path) <= (*rank, path))
+    }
+}
+
+impl PartialEq for FileInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for FileInfo {}
+
+impl PartialOrd for FileInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FileInfo {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // ties are broken by path, and then size, so that dry runs, real runs and passes limited
+        // by --max-candidates all agree on the order
+        (self.rank, &self.path, self.size).cmp(&(other.rank, &other.path, other.size))
+    }
+}
+
+/// The policy used to decide which files to delete first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// delete the least-recently-used files first
+    Lru,
+    /// delete the files with the highest `size * age` score first, where size is in bytes and age
+    /// is the number of whole seconds since the file was last used. a 4GB file unused for a day
+    /// therefore goes before a 1KB file unused for a year
+    SizeWeighted,
+    /// delete the least-frequently-used files first, breaking ties by least-recent use. frequencies
+    /// are tracked across runs in an index file at the top-level directory, so the first run
+    /// behaves like lru
+    Lfu,
+}
+
+impl Strategy {
+    fn rank(self, accessed: DateTime<Local>, size: u64, frequency: u64, now: DateTime<Local>) -> (i128, i128) {
+        let recency = accessed.timestamp_nanos_opt().unwrap_or(i64::MAX) as i128;
+        match self {
+            Strategy::Lru => (recency, 0),
+            Strategy::SizeWeighted => {
+                let age = (now - accessed).num_seconds().max(0) as i128;
+                (-(size as i128 * age), recency)
+            }
+            Strategy::Lfu => (frequency as i128, recency),
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(Strategy::Lru),
+            "size-weighted" => Ok(Strategy::SizeWeighted),
+            "lfu" => Ok(Strategy::Lfu),
+            _ => Err(format!("unknown strategy {:?}: expected lru, size-weighted or lfu", s)),
+        }
+    }
+}
+
+/// What happens to the files in one band of a retention policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// never delete them
+    Protect,
+    /// delete them as needed to reach the targets, as for files with no retention policy
+    Pressure,
+    /// delete every one of them, whatever the free space
+    Purge,
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "protect" => Ok(RetentionPolicy::Protect),
+            "pressure" => Ok(RetentionPolicy::Pressure),
+            "purge" => Ok(RetentionPolicy::Purge),
+            _ => Err(format!("unknown retention policy {:?}: expected protect, pressure or purge", s)),
+        }
+    }
+}
+
+/// The files last used less than `younger_than` ago, and not by an earlier band, or every file
+/// left for the last band, along with what happens to them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetentionBand {
+    pub younger_than : Option<Duration>,
+    pub policy : RetentionPolicy,
+}
+
+/// The file timestamp used to decide how recently a file was used.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    Accessed,
+    Modified,
+    Created,
+    /// the later of the access and modification times, so that a file used either way counts as
+    /// recently used. whichever is available is used alone if the other isn't
+    Newest,
+}
+
+impl Timestamp {
+    fn of(self, metadata: &FileStat) -> Option<SystemTime> {
+        match self {
+            Timestamp::Accessed => metadata.accessed,
+            Timestamp::Modified => metadata.modified,
+            Timestamp::Created => metadata.created,
+            // NOTE: an access time of the epoch means that it's unavailable, as for `Accessed`
+            Timestamp::Newest => metadata.accessed.filter(|accessed| *accessed != SystemTime::UNIX_EPOCH).max(metadata.modified),
+        }
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "atime" => Ok(Timestamp::Accessed),
+            "mtime" => Ok(Timestamp::Modified),
+            "ctime" => Ok(Timestamp::Created),
+            "newest" => Ok(Timestamp::Newest),
+            _ => Err(format!("unknown timestamp {:?}: expected atime, mtime, ctime or newest", s)),
+        }
+    }
+}
+
+/// How the space occupied by a file is measured.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SizeSource {
+    /// the blocks allocated to the file, which is what deleting it actually frees. only
+    /// supported on unix, and equivalent to `Length` elsewhere
+    Blocks,
+    /// the file's logical length, which overstates the space occupied by sparse or compressed
+    /// files
+    Length,
+}
+
+impl SizeSource {
+    fn of(self, metadata: &FileStat) -> u64 {
+        match self {
+            SizeSource::Blocks => metadata.allocated.unwrap_or(metadata.len),
+            SizeSource::Length => metadata.len,
+        }
+    }
+}
+
+impl FromStr for SizeSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blocks" => Ok(SizeSource::Blocks),
+            "length" => Ok(SizeSource::Length),
+            _ => Err(format!("unknown size source {:?}: expected blocks or length", s)),
+        }
+    }
+}
+
+/// What to do with files whose access time is unavailable.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum MissingAtime {
+    /// never delete the file
+    Skip,
+    /// rank the file by its modification time instead
+    Mtime,
+    /// abort the run
+    Error,
+}
+
+impl FromStr for MissingAtime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(MissingAtime::Skip),
+            "mtime" => Ok(MissingAtime::Mtime),
+            "error" => Ok(MissingAtime::Error),
+            _ => Err(format!("unknown policy {:?}: expected skip, mtime or error", s)),
+        }
+    }
+}
+
+/// What to do with files whose timestamp, as chosen by `Timestamp`, is in the future, e.g. because
+/// of clock skew or a restore from backup, which would otherwise rank them as the most recently
+/// used of all.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FutureTime {
+    /// rank the file by its timestamp regardless, warning how many such files were found
+    Warn,
+    /// rank the file as though it were used at the start of the run
+    Clamp,
+    /// never delete the file
+    Skip,
+}
+
+impl FromStr for FutureTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(FutureTime::Warn),
+            "clamp" => Ok(FutureTime::Clamp),
+            "skip" => Ok(FutureTime::Skip),
+            _ => Err(format!("unknown policy {:?}: expected warn, clamp or skip", s)),
+        }
+    }
+}
+
+/// How much free space to leave on the filesystem.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    /// leave at least this many bytes available
+    Bytes(u64),
+    /// leave at least this percentage (0-100) of the filesystem's total capacity available
+    Percent(f64),
+}
+
+/// Describes a single reclamation run. The fields mirror the command-line options of the same names.
+#[derive(Clone)]
+pub struct ReclaimConfig {
+    /// the top-level directory at which to recursively reclaim files
+    pub path : PathBuf,
+    /// how much space to leave available, if any
+    pub target : Option<Target>,
+    /// the minimum number of inodes to leave available. only supported on unix
+    pub target_inodes : Option<u64>,
+    /// if set, don't remove any files, but report those which would have been removed
+    pub dry_run : bool,
+    /// the most filesystem space to reclaim in a single run
+    pub max_bytes_to_delete : Option<u64>,
+    /// the most files to delete in a single run
+    pub max_files_to_delete : Option<usize>,
+    /// the most files to hold in memory while planning which to delete, at the cost of another
+    /// walk of the tree whenever more are needed
+    pub max_candidates : Option<usize>,
+    /// only delete files that were last used at least this long ago. zero places no restriction
+    /// at all, even on files whose timestamps are in the future
+    pub older_than : Duration,
+    /// delete every file last used longer ago than this, whatever the free space, in addition to
+    /// any files needed to reach the targets
+    pub purge_older_than : Option<Duration>,
+    /// the bands of age, youngest first, which decide whether files are protected, deleted to reach
+    /// the targets or purged. every band but the last is bounded by an age greater than the one
+    /// before, and the last takes every older file. generalizes, and can't be combined with,
+    /// `older_than` and `purge_older_than`. empty for no bands
+    pub retention : Vec<RetentionBand>,
+    pub by : Timestamp,
+    pub on_missing_atime : MissingAtime,
+    pub future_time : FutureTime,
+    pub strategy : Strategy,
+    pub size_source : SizeSource,
+    /// never delete files whose logical length is less than this many bytes
+    pub min_file_size : u64,
+    /// never delete files whose logical length is more than this many bytes
+    pub max_file_size : Option<u64>,
+    /// never delete files whose path relative to `path` matches one of these globs
+    pub exclude : Vec<String>,
+    /// if nonempty, only delete files whose path relative to `path` matches one of these globs
+    pub include : Vec<String>,
+    /// never delete files whose path relative to `path` matches one of these regular expressions
+    pub exclude_regex : Vec<String>,
+    /// if nonempty, only delete files whose path relative to `path` matches one of these regular
+    /// expressions
+    pub match_regex : Vec<String>,
+    /// if nonempty, only delete files with one of these extensions, compared case-insensitively
+    /// and with or without a leading dot
+    pub extensions : Vec<String>,
+    /// only delete files owned by the user with this id. only supported on unix
+    pub owner : Option<u32>,
+    /// never delete anything in or beneath a directory containing a file with this name
+    pub keep_marker : Option<String>,
+    /// a regular expression extracting a key from each file's path relative to `path`: its first
+    /// capture group, or the whole match without one. files sharing a key form a group which is
+    /// ranked by its least recently used file and deleted whole, or kept whole if any of its files
+    /// can't be deleted, so the last group deleted may overshoot the target. files it doesn't
+    /// match are deleted singly as usual
+    pub group_by : Option<String>,
+    /// never delete hidden files, whose names begin with a dot or which, on Windows, have the
+    /// hidden attribute. files in hidden directories are still deleted
+    pub skip_hidden : bool,
+    /// assume that the names of the entries in each directory sort in order of their timestamps,
+    /// oldest first, as for caches which name files or directories by when they were written. the
+    /// walk then visits entries in order of name and skips the rest of a directory, without
+    /// stat'ing it, once the plan holds enough and one of its files is already too new to delete.
+    /// files out of order may therefore be missed. only supported with `Strategy::Lru`, and not
+    /// with `keep_newest`, `keep_per_dir` or `purge_older_than`, which must see every file
+    pub assume_sorted : bool,
+    /// consider only these files, as though they were all that walking `path` found, skipping any
+    /// which can't be stat'd and passing over any which aren't beneath `path`. nothing is walked,
+    /// so `respect_ignore`, `one_file_system`, `max_depth`, `min_depth` and `keep_marker` have no
+    /// effect, while the filters on each file's path, size, owner and age still apply
+    pub listed_files : Option<Arc<Vec<PathBuf>>>,
+    /// cache the walk in the index at this path, kept between runs, so that later walks skip
+    /// reading directories whose entries haven't changed and stat'ing the files they already know.
+    /// since reading or writing a file doesn't change its directory, the cached metadata may be out
+    /// of date, so each file is stat'd again before it's deleted and left for another pass if it
+    /// has changed. files whose timestamps are set back, making them look older than cached, are
+    /// only noticed once their directory changes. only effective on unix, and not supported with
+    /// `respect_ignore`, `follow_symlinks`, `listed_files`, `assume_sorted` or `Strategy::Lfu`
+    pub walk_index : Option<PathBuf>,
+    /// walk the tree twice rather than holding every candidate in memory, for trees with too many
+    /// files for that: once to count the candidates in a coarse histogram of their ages, finding
+    /// the age beyond which files must go to reach the targets, and again to delete files older
+    /// than that as they're found. the files near that age are deleted in the order they're found
+    /// rather than strictly oldest first, so the run may delete some files slightly newer than
+    /// others it keeps, or stop a file short of the target if the tree changes between the walks.
+    /// only supported with `Strategy::Lru`, and not with `keep_newest`, `keep_per_dir`,
+    /// `max_candidates`, `purge_older_than`, `retention`, `group_by`, `walk_index` or
+    /// `assume_sorted`
+    pub low_memory : bool,
+    /// never delete files matched by .gitignore or .ignore files found during the traversal
+    pub respect_ignore : bool,
+    /// follow symlinks during the traversal, deleting the files they point to, which are filtered,
+    /// grouped and reported by the links' paths
+    pub follow_symlinks : bool,
+    /// never cross into other filesystems mounted beneath `path`
+    pub one_file_system : bool,
+    /// only consider files at most this many levels below `path`
+    pub max_depth : Option<usize>,
+    /// only consider files at least this many levels below `path`
+    pub min_depth : usize,
+    /// remove directories beneath `path` which were left empty by the run
+    pub prune_empty_dirs : bool,
+    /// fail, rather than warn, when the target exceeds the filesystem's capacity or reaching it
+    /// means deleting most of the candidates
+    pub strict : bool,
+    /// in a real run, delete nothing if reaching the target means deleting more than this
+    /// percentage of the candidates, by count or by size, and report the files which would have
+    /// been deleted as `ReclaimReport::withheld` instead. files purged by `purge_older_than` don't
+    /// count towards it
+    pub max_delete_percent : Option<f64>,
+    /// never delete this many of the most recently accessed files beneath `path`, even if the
+    /// target can't be met without them
+    pub keep_newest : usize,
+    /// never delete this many of the most recently accessed files in each directory, grouped by
+    /// their immediate parent. files protected by either this or `keep_newest` are never deleted
+    pub keep_per_dir : usize,
+    /// never delete files which some process has open, since their space isn't freed until
+    /// they're closed. only supported on Linux, and best-effort since files may be opened at any
+    /// time
+    pub skip_open : bool,
+    /// never delete files which we lack permission to write, letting them be pinned with chmod. on
+    /// by default, since deleting a file only takes permission to write its directory, so they're
+    /// otherwise deleted like any other
+    pub skip_readonly : bool,
+    pub disposal : Disposal,
+    /// how many times to retry deleting a file which fails transiently, e.g. because it's busy
+    pub delete_retries : u32,
+    /// how many files to delete at once, each on its own thread, which helps on filesystems where
+    /// every deletion waits on the network or the disk. files are still handed over in rank order
+    /// and throttled, but may finish out of order. ignored by dry runs
+    pub delete_threads : usize,
+    /// while deleting, re-query free space after every this many files, stopping as soon as the
+    /// targets are met and making another pass if they still aren't once the plan is done. ignored
+    /// by dry runs, which free nothing
+    pub recheck_interval : Option<usize>,
+    /// delete at most this many files per second. ignored by dry runs
+    pub max_files_per_second : Option<f64>,
+    /// delete at most this many bytes per second. ignored by dry runs
+    pub max_bytes_per_second : Option<u64>,
+    /// called with each pass's plan before it deletes anything, e.g. to size a progress bar
+    pub on_planned : Option<OnPlanned>,
+    /// called with each pass's plan, unless it's empty, before it deletes anything. if it returns
+    /// false, nothing more is deleted and the run is reported as aborted. ignored by dry runs
+    pub confirm : Option<Confirm>,
+    /// the source of the current time, against which `older_than` and `purge_older_than` are
+    /// measured and by which `Strategy::SizeWeighted` ages files
+    pub clock : Arc<dyn Clock>,
+    /// the filesystem walked and reclaimed from, which is only ever replaced in tests
+    pub filesystem : Arc<dyn FileSystem>,
+    /// once this is set, stop as soon as the file being deleted has been, and report the work done
+    /// so far
+    pub interrupt : Option<Arc<AtomicBool>>,
+}
+
+impl ReclaimConfig {
+    /// A config which deletes least-recently-accessed files beneath `path` until `target` is met,
+    /// with every other option at its default.
+    pub fn new(path: impl Into<PathBuf>, target: Target) -> Self {
+        ReclaimConfig {
+            path : path.into(),
+            target : Some(target),
+            target_inodes : None,
+            dry_run : false,
+            max_bytes_to_delete : None,
+            max_files_to_delete : None,
+            max_candidates : None,
+            older_than : Duration::zero(),
+            purge_older_than : None,
+            retention : Vec::new(),
+            by : Timestamp::Accessed,
+            on_missing_atime : MissingAtime::Skip,
+            future_time : FutureTime::Warn,
+            strategy : Strategy::Lru,
+            size_source : SizeSource::Blocks,
+            min_file_size : 0,
+            max_file_size : None,
+            exclude : Vec::new(),
+            include : Vec::new(),
+            exclude_regex : Vec::new(),
+            match_regex : Vec::new(),
+            extensions : Vec::new(),
+            owner : None,
+            keep_marker : Some(DEFAULT_KEEP_MARKER.to_string()),
+            group_by : None,
+            skip_hidden : false,
+            assume_sorted : false,
+            listed_files : None,
+            walk_index : None,
+            low_memory : false,
+            respect_ignore : false,
+            follow_symlinks : false,
+            one_file_system : false,
+            max_depth : None,
+            min_depth : 0,
+            prune_empty_dirs : false,
+            strict : false,
+            max_delete_percent : None,
+            keep_newest : 0,
+            keep_per_dir : 0,
+            skip_open : false,
+            skip_readonly : true,
+            disposal : Disposal::Delete,
+            delete_retries : 3,
+            delete_threads : 1,
+            recheck_interval : None,
+            max_files_per_second : None,
+            max_bytes_per_second : None,
+            on_planned : None,
+            confirm : None,
+            clock : Arc::new(SystemClock),
+            filesystem : Arc::new(RealFileSystem),
+            interrupt : None,
+        }
+    }
+}
+
+/// The files a pass of a reclamation run is about to delete. A run makes more than one pass when
+/// `max_candidates` stops the first from holding every file needed.
+pub struct Plan {
+    pub files : usize,
+    pub bytes : u64,
+}
+
+/// A callback for `ReclaimConfig::on_planned`, shared by every config which uses it.
+pub type OnPlanned = Arc<dyn Fn(&Plan) + Send + Sync>;
+
+/// A callback for `ReclaimConfig::confirm`, given the top-level directory and its plan.
+pub type Confirm = Arc<dyn Fn(&Path, &Plan) -> bool + Send + Sync>;
+
+/// The outcome of a reclamation run.
+pub struct ReclaimReport {
+    /// the top-level directory reclaimed from
+    pub path : PathBuf,
+    /// the number of files old enough to be deleted
+    pub candidates : usize,
+    /// the files deleted (or which would be deleted, in a dry run), in the order they were handled
+    pub deleted : Vec<PathBuf>,
+    pub bytes_freed : u64,
+    pub free_before : u64,
+    /// the free space queried again once every file was handled, so that failed deletions and
+    /// space used meanwhile show
+    pub free_after : u64,
+    /// the space to leave available in bytes, with a percentage resolved against the filesystem's
+    /// capacity, if there was a space target
+    pub target : Option<u64>,
+    /// whether `free_after` reached `target`, if there was one. dry runs free nothing, so theirs are
+    /// judged by the space they would have freed, along with earlier dry runs on the same filesystem
+    pub target_met : Option<bool>,
+    /// the number of entries which couldn't be read while walking, by error kind
+    pub skipped : BTreeMap<String, usize>,
+    /// the number of files which couldn't be deleted, by error kind
+    pub failed : BTreeMap<String, usize>,
+    /// the number of empty directories removed by `prune_empty_dirs`
+    pub directories_pruned : usize,
+    /// whether there weren't enough deletable files to reach the target. `reclaim_all` only sets
+    /// this for the last directory on each filesystem, since later directories may make up the
+    /// difference
+    pub target_unmet : bool,
+    /// whether the run was stopped early by `interrupt`
+    pub interrupted : bool,
+    /// whether the run stopped before deleting because `max_delete_percent` was exceeded or
+    /// `confirm` declined the plan
+    pub aborted : bool,
+    /// the files an aborted run would have deleted, in the order it would have deleted them
+    pub withheld : Vec<FileInfo>,
+}
+
+/// Query the free space available to us on the filesystem containing `config.path`.
+fn available_space(config: &ReclaimConfig) -> anyhow::Result<u64> {
+    config.filesystem.available_space(&config.path)
+        .with_context(|| format!("failed to query free space on {}", config.path.display()))
+}
+
+/// Query the inodes available to us on the filesystem containing `config.path`, or `None` where
+/// the platform or filesystem doesn't limit them.
+fn available_inodes(config: &ReclaimConfig) -> anyhow::Result<Option<u64>> {
+    config.filesystem.available_inodes(&config.path)
+        .with_context(|| format!("failed to query free inodes on {}", config.path.display()))
+}
+
+/// Query the capacity of the filesystem containing `config.path`.
+fn total_space(config: &ReclaimConfig) -> anyhow::Result<u64> {
+    config.filesystem.total_space(&config.path)
+        .with_context(|| format!("failed to query the capacity of {}", config.path.display()))
+}
+
+/// The device holding `path` on `config.filesystem`, where the platform provides device ids.
+fn device(config: &ReclaimConfig, path: &Path) -> Option<u64> {
+    config.filesystem.stat(path, true).ok().and_then(|metadata| metadata.inode).map(|(device, _)| device)
+}
+
+/// Compile glob patterns matched against paths relative to the top-level directory.
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+    }
+    builder.build()
+}
+
+/// Pop the highest-ranked files off `heap` for as long as the files remaining would still add
+/// up to more than `n_bytes`, and would still number at least `n_files`.
+fn trim_heap(heap: &mut BinaryHeap<FileInfo>, aggregate_heap_file_size: &mut u64, n_bytes: u64, n_files: u64) {
+    while let Some(file) = heap.peek() {
+        let enough_bytes = n_bytes == 0 || *aggregate_heap_file_size - file.size > n_bytes;
+        if enough_bytes && heap.len() as u64 > n_files {
+            *aggregate_heap_file_size -= file.size;
+            heap.pop();
+        } else {
+            break;
+        }
+    }
+}
+
+/// Push `file` onto `heap` if it's needed to free `n_bytes` or `n_files`, or if it ranks below
+/// a file already on the heap. The heap never holds more than `max_files` files, and true is
+/// returned if that meant discarding a file which was needed.
+fn push_candidate(heap: &mut BinaryHeap<FileInfo>, aggregate_heap_file_size: &mut u64, file: FileInfo, n_bytes: u64, n_files: u64, max_files: Option<usize>) -> bool {
+    let heap_needs_more = *aggregate_heap_file_size < n_bytes || (heap.len() as u64) < n_files;
+    if heap_needs_more || heap.peek().is_some_and(|highest| file <= *highest) {
+        *aggregate_heap_file_size += file.size;
+        heap.push(file);
+
+        // forget about any higher-ranked files that we no longer need to delete now that we
+        // have pushed a lower-ranked file onto the heap
+        trim_heap(heap, aggregate_heap_file_size, n_bytes, n_files);
+        if max_files.is_some_and(|max_files| heap.len() > max_files) {
+            let discarded = heap.pop().expect("the heap is over capacity, so it isn't empty");
+            *aggregate_heap_file_size -= discarded.size;
+            return true;
+        }
+    } else {
+        // if our file ranks above the highest-ranked file already on the heap, and our heap
+        // is already at capacity, there's no sense in pushing the file onto the heap
+        // only to remove it immediately afterward
+    }
+    false
+}
+
+/// A file ordered by the timestamp which ranked it, most recent last.
+struct ByAccessed(FileInfo);
+
+impl PartialEq for ByAccessed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ByAccessed {}
+
+impl Ord for ByAccessed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.0.accessed, &self.0.path, self.0.size).cmp(&(other.0.accessed, &other.0.path, other.0.size))
+    }
+}
+
+impl PartialOrd for ByAccessed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Holds back the files protected by `keep_newest` and `keep_per_dir` for as long as they may be,
+/// so that only files which neither protects are ever candidates for deletion.
+struct Protected {
+    n_newest : usize,
+    n_per_dir : usize,
+    /// the `n_newest` most recently accessed files found so far, by timestamp and path
+    newest : BinaryHeap<Reverse<(DateTime<Local>, PathBuf)>>,
+    /// the `n_per_dir` most recently accessed files found so far in each directory
+    per_dir : HashMap<PathBuf, BinaryHeap<Reverse<ByAccessed>>>,
+    /// files which their directory no longer protects, but which may yet be among the newest
+    pending : BinaryHeap<Reverse<ByAccessed>>,
+}
+
+impl Protected {
+    fn new(n_newest: usize, n_per_dir: usize) -> Self {
+        Protected { n_newest, n_per_dir, newest : BinaryHeap::new(), per_dir : HashMap::new(), pending : BinaryHeap::new() }
+    }
+
+    /// Add `file`, passing `unprotected` each file which is now known to be protected by neither.
+    fn admit(&mut self, file: FileInfo, mut unprotected: impl FnMut(FileInfo)) {
+        if self.n_newest > 0 {
+            self.newest.push(Reverse((file.accessed, file.path.clone())));
+            if self.newest.len() > self.n_newest {
+                self.newest.pop();
+            }
+        }
+        let file = if self.n_per_dir > 0 {
+            let directory = file.path.parent().map(Path::to_path_buf).unwrap_or_default();
+            let siblings = self.per_dir.entry(directory).or_default();
+            siblings.push(Reverse(ByAccessed(file)));
+            if siblings.len() <= self.n_per_dir {
+                None
+            } else {
+                siblings.pop().map(|Reverse(ByAccessed(file))| file)
+            }
+        } else {
+            Some(file)
+        };
+        if self.n_newest == 0 {
+            file.into_iter().for_each(unprotected);
+            return;
+        }
+        self.pending.extend(file.map(|file| Reverse(ByAccessed(file))));
+        // the newest files only ever get newer, so anything older than all of them is never
+        // among them again
+        if self.newest.len() < self.n_newest {
+            return;
+        }
+        let Reverse((oldest_accessed, oldest_path)) = self.newest.peek().expect("the newest files are at capacity, so there's one");
+        while self.pending.peek().is_some_and(|Reverse(ByAccessed(file))| (file.accessed, &file.path) < (*oldest_accessed, oldest_path)) {
+            let Reverse(ByAccessed(file)) = self.pending.pop().expect("we just peeked at it");
+            unprotected(file);
+        }
+    }
+
+    /// The number of files protected, once every file has been admitted.
+    fn len(&self) -> usize {
+        self.pending.len() + self.per_dir.values().map(BinaryHeap::len).sum::<usize>()
+    }
+}
+
+/// The files to consider beneath `config.path`, whether walked or listed, skipping the rest of
+/// any directory added to `closed_directories` as it's walked and walking through `index` if given.
+fn entries(config: &ReclaimConfig, closed_directories: Option<ClosedDirectories>, index: Option<SharedWalkIndex>) -> Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> {
+    match &config.listed_files {
+        Some(listed_files) => stat_listed(config.filesystem.clone(), &config.path, listed_files.clone(), config.follow_symlinks),
+        None => config.filesystem.walk(&config.path, &WalkOptions {
+            respect_ignore : config.respect_ignore,
+            follow_symlinks : config.follow_symlinks,
+            one_file_system : config.one_file_system,
+            max_depth : config.max_depth,
+            min_depth : config.min_depth,
+            keep_marker : config.keep_marker.clone(),
+            closed_directories,
+            index,
+        }),
+    }
+}
+
+/// The compiled patterns which decide, along with the rest of a config, which files are never
+/// candidates for deletion whatever their age or size.
+struct Filters {
+    excludes : GlobSet,
+    includes : GlobSet,
+    excluded_regexes : RegexSet,
+    matched_regexes : RegexSet,
+}
+
+impl Filters {
+    fn new(config: &ReclaimConfig) -> anyhow::Result<Self> {
+        Ok(Filters {
+            excludes : build_glob_set(&config.exclude).context("invalid exclude pattern")?,
+            includes : build_glob_set(&config.include).context("invalid include pattern")?,
+            excluded_regexes : RegexSet::new(&config.exclude_regex).context("invalid exclude regex")?,
+            matched_regexes : RegexSet::new(&config.match_regex).context("invalid match regex")?,
+        })
+    }
+
+    /// Whether `config` excludes (or doesn't include) the file at `path`, described by `metadata`.
+    fn excludes(&self, config: &ReclaimConfig, path: &Path, metadata: &FileStat) -> bool {
+        let relative_path = path.strip_prefix(&config.path).unwrap_or(path);
+        let relative_path_string = relative_path.to_string_lossy();
+        (config.skip_hidden && is_hidden(path, metadata))
+            || (config.owner.is_some() && metadata.owner.is_some_and(|uid| Some(uid) != config.owner))
+            || (!config.extensions.is_empty() && !path.extension().is_some_and(|extension| {
+                config.extensions.iter().any(|wanted| extension.to_string_lossy().eq_ignore_ascii_case(wanted.trim_start_matches('.')))
+            }))
+            || self.excludes.is_match(relative_path)
+            || (!config.include.is_empty() && !self.includes.is_match(relative_path))
+            || self.excluded_regexes.is_match(&relative_path_string)
+            || (!config.match_regex.is_empty() && !self.matched_regexes.is_match(&relative_path_string))
+    }
+}
+
+/// The timestamp of the file at `path` chosen by `config.by`, falling back as
+/// `config.on_missing_atime` and the platform require, or `None` if the file should be skipped.
+fn timestamp(config: &ReclaimConfig, path: &Path, metadata: &FileStat, warned_creation_time_unsupported: &mut bool) -> anyhow::Result<Option<DateTime<Local>>> {
+    Ok(Some(match config.by.of(metadata) {
+        Some(accessed) if config.by != Timestamp::Accessed || accessed != SystemTime::UNIX_EPOCH => accessed.into(),
+        _ if config.by == Timestamp::Accessed => match config.on_missing_atime {
+            MissingAtime::Skip => return Ok(None),
+            MissingAtime::Mtime => match metadata.modified {
+                Some(modified) => modified.into(),
+                None => return Ok(None),
+            },
+            MissingAtime::Error => anyhow::bail!(
+                "access time is unavailable for {}; pass --on-missing-atime skip or mtime to continue past such files",
+                path.display(),
+            ),
+        },
+        _ if config.by == Timestamp::Created => match metadata.modified {
+            // many filesystems (e.g. ext4 on older kernels) don't record creation times, in which
+            // case mtime is the closest approximation
+            Some(modified) => {
+                if !*warned_creation_time_unsupported {
+                    log::warn!("creation time is unavailable for {}; falling back to mtime", path.display());
+                    *warned_creation_time_unsupported = true;
+                }
+                modified.into()
+            }
+            None => return Ok(None),
+        },
+        // the requested timestamp isn't available for this file on this platform
+        _ => return Ok(None),
+    }))
+}
+
+/// Dispose of every path of `file`, recording each in `deleted_files`, and stopping at the first
+/// which fails.
+fn dispose_paths(config: &ReclaimConfig, file: &FileInfo, deleted_files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for path in file.paths() {
+        // whatever planned it, e.g. a hand-written plan, the top-level directory is never ours to
+        // delete
+        if *path == config.path {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("refusing to delete the top-level directory {}", path.display())));
+        }
+        // dry runs plan and account for files exactly as real runs do, and only skip touching the
+        // filesystem, so that they preview precisely what a real run would remove
+        if config.dry_run {
+            deleted_files.push(path.clone());
+            continue;
+        }
+        // a followed symlink stands for the file it leads to, which is what's disposed of
+        let target = match config.filesystem.stat(path, false) {
+            Ok(metadata) if config.follow_symlinks && metadata.is_symlink => config.filesystem.canonicalize(path),
+            _ => Ok(path.clone()),
+        };
+        let result = target.and_then(|target| config.disposal.apply_with_retries(config.filesystem.as_ref(), &config.path, &target, config.delete_retries));
+        if let Err(err) = result {
+            log::info!("Failed to {} {}: {}", config.disposal.action(), path.display(), err);
+            return Err(err);
+        }
+        deleted_files.push(path.clone());
+    }
+    Ok(())
+}
+
+/// The outcome of disposing of a file: the paths disposed of, which stop short of the first that
+/// failed, and what it failed with.
+struct Disposed {
+    file : FileInfo,
+    paths : Vec<PathBuf>,
+    result : io::Result<()>,
+}
+
+/// Disposes of files as they're handed to it, either one at a time or, with `delete_threads` above
+/// 1, on that many worker threads at once. Each file's outcome is returned once it's known, which
+/// for the workers may be after later files were handed over.
+enum Disposer {
+    Serial,
+    Pool {
+        jobs : SyncSender<FileInfo>,
+        outcomes : Receiver<Disposed>,
+        workers : Vec<thread::JoinHandle<()>>,
+        /// the files handed over whose outcome isn't known yet, and their paths
+        n_in_flight : usize,
+        n_paths_in_flight : usize,
+    },
+}
+
+impl Disposer {
+    fn new(config: &ReclaimConfig) -> Self {
+        // dry runs touch nothing, so have nothing to gain from workers
+        if config.delete_threads <= 1 || config.dry_run {
+            return Disposer::Serial;
+        }
+        let (jobs, queue) = mpsc::sync_channel::<FileInfo>(config.delete_threads);
+        let (sender, outcomes) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..config.delete_threads).map(|_| {
+            let (config, queue, sender) = (config.clone(), queue.clone(), sender.clone());
+            thread::spawn(move || loop {
+                let file = match queue.lock().expect("the lock is never poisoned").recv() {
+                    Ok(file) => file,
+                    Err(_) => return,
+                };
+                let mut paths = Vec::new();
+                let result = dispose_paths(&config, &file, &mut paths);
+                if sender.send(Disposed { file, paths, result }).is_err() {
+                    return;
+                }
+            })
+        }).collect();
+        Disposer::Pool { jobs, outcomes, workers, n_in_flight : 0, n_paths_in_flight : 0 }
+    }
+
+    /// The paths handed over which may yet be disposed of.
+    fn n_paths_in_flight(&self) -> usize {
+        match self {
+            Disposer::Serial => 0,
+            Disposer::Pool { n_paths_in_flight, .. } => *n_paths_in_flight,
+        }
+    }
+
+    /// Dispose of `file`, returning the outcomes known so far. With workers, waits for one to
+    /// finish first once every worker already has a file.
+    fn dispose(&mut self, config: &ReclaimConfig, file: FileInfo) -> Vec<Disposed> {
+        let (jobs, outcomes, n_in_flight, n_paths_in_flight) = match self {
+            Disposer::Serial => {
+                let mut paths = Vec::new();
+                let result = dispose_paths(config, &file, &mut paths);
+                return vec![Disposed { file, paths, result }];
+            }
+            Disposer::Pool { jobs, outcomes, n_in_flight, n_paths_in_flight, .. } => (jobs, outcomes, n_in_flight, n_paths_in_flight),
+        };
+        let mut finished = Vec::new();
+        if *n_in_flight >= config.delete_threads {
+            finished.extend(outcomes.recv().ok());
+        }
+        finished.extend(outcomes.try_iter());
+        for disposed in &finished {
+            *n_in_flight -= 1;
+            *n_paths_in_flight -= disposed.file.paths().count();
+        }
+        *n_in_flight += 1;
+        *n_paths_in_flight += file.paths().count();
+        jobs.send(file).expect("the workers outlive the disposer");
+        finished
+    }
+
+    /// Wait for every file handed over to be disposed of, returning the outcomes not yet returned.
+    fn finish(self) -> Vec<Disposed> {
+        match self {
+            Disposer::Serial => Vec::new(),
+            Disposer::Pool { jobs, outcomes, workers, .. } => {
+                drop(jobs);
+                for worker in workers {
+                    let _ = worker.join();
+                }
+                outcomes.try_iter().collect()
+            }
+        }
+    }
+}
+
+/// Account for `disposed`: record the paths it disposed of in `deleted_files`, and count it as
+/// freed and call `on_disposed` if nothing failed, or count its failure otherwise, returning the
+/// error.
+fn account(
+    config: &ReclaimConfig,
+    disposed: Disposed,
+    frequency_index: Option<&mut FrequencyIndex>,
+    deleted_files: &mut Vec<PathBuf>,
+    n_bytes_deleted: &mut u64,
+    failed: &mut BTreeMap<String, usize>,
+    on_disposed: &mut impl FnMut(&FileInfo),
+) -> Option<io::Error> {
+    if let Some(frequency_index) = frequency_index.filter(|_| !config.dry_run) {
+        for path in &disposed.paths {
+            frequency_index.forget(path);
+        }
+    }
+    deleted_files.extend(disposed.paths);
+    match disposed.result {
+        // a hard-linked file's space is only freed once every link is gone
+        Ok(()) => {
+            *n_bytes_deleted += disposed.file.size;
+            on_disposed(&disposed.file);
+            None
+        }
+        Err(err) => {
+            *failed.entry(err.kind().to_string()).or_insert(0) += 1;
+            Some(err)
+        }
+    }
+}
+
+/// Warn that a run is stopping after `n_deleted` files since disposing of another failed with
+/// `err`, for want of space.
+fn warn_out_of_space(config: &ReclaimConfig, n_deleted: usize, err: &io::Error) {
+    let destination = match &config.disposal {
+        Disposal::MoveTo(directory) => directory.display().to_string(),
+        Disposal::Trash => "the trash".to_string(),
+        // e.g. shredding a file on a copy-on-write filesystem writes new blocks
+        Disposal::Delete | Disposal::Shred(_) => "its filesystem".to_string(),
+    };
+    log::warn!(
+        "stopping after handling {} files from {}, since {} has no room for more: {}",
+        n_deleted, config.path.display(), destination, err,
+    );
+}
+
+/// Remove the directories between each of `deleted_files` and `root` which are now empty, deepest
+/// first so that a parent can be removed once its last child directory has been. `root` itself is
+/// never removed. Returns the number of directories removed.
+fn prune_empty_directories(filesystem: &dyn FileSystem, root: &Path, deleted_files: &[PathBuf]) -> usize {
+    let mut directories = BTreeSet::new();
+    for file in deleted_files {
+        for directory in file.ancestors().skip(1).take_while(|directory| *directory != root && directory.starts_with(root)) {
+            directories.insert((directory.components().count(), directory.to_path_buf()));
+        }
+    }
+
+    let mut n_directories_removed = 0;
+    for (_, directory) in directories.iter().rev() {
+        // NOTE: this fails for any directory which still has something in it, which is exactly
+        // what we want
+        if filesystem.remove_dir(directory).is_ok() {
+            n_directories_removed += 1;
+            log::info!("Removed empty directory {}", directory.display());
+        }
+    }
+    n_directories_removed
+}
+
+/// Stat `config.path`, failing unless it's a directory, the device of which is returned where the
+/// platform provides it. A file given in its place would otherwise be walked, and deleted, as its
+/// own only entry.
+fn validate_root(config: &ReclaimConfig) -> anyhow::Result<Option<u64>> {
+    let metadata = config.filesystem.stat(&config.path, true).with_context(|| format!("failed to read {}", config.path.display()))?;
+    if metadata.is_file {
+        anyhow::bail!("{} is a file, not a directory", config.path.display());
+    }
+    Ok(metadata.inode.map(|(device, _)| device))
+}
+
+/// Reclaim space as described by `config`.
+pub fn reclaim(config: &ReclaimConfig) -> anyhow::Result<ReclaimReport> {
+    reclaim_with(config, |_| {})
+}
+
+/// Reclaim space as described by `config`, calling `on_disposed` with each file as soon as it's
+/// been deleted, or as soon as it's been selected in a dry run.
+pub fn reclaim_with(config: &ReclaimConfig, on_disposed: impl FnMut(&FileInfo)) -> anyhow::Result<ReclaimReport> {
+    reclaim_assuming_freed(config, Freed::default(), on_disposed)
+}
+
+/// Space which earlier dry runs would have freed on a filesystem.
+#[derive(Clone, Copy, Default)]
+struct Freed {
+    bytes : u64,
+    inodes : u64,
+}
+
+/// Reclaim space as described by each of `configs` in turn, calling `on_disposed` like
+/// `reclaim_with`. Since each run re-queries free space, space freed beneath one top-level
+/// directory counts towards the target of any later directory on the same filesystem. Dry runs
+/// free nothing, so the space they would have freed is credited to later runs on the same
+/// filesystem instead.
+pub fn reclaim_all(configs: &[ReclaimConfig], mut on_disposed: impl FnMut(&FileInfo)) -> anyhow::Result<Vec<ReclaimReport>> {
+    // the bytes the dry runs so far would have freed, and the latest run, by device
+    let mut freed_by_device = HashMap::new();
+    let mut latest_by_device = HashMap::new();
+    let mut reports : Vec<ReclaimReport> = Vec::new();
+    for config in configs {
+        let device = device(config, &config.path);
+        let already_freed = device.and_then(|device| freed_by_device.get(&device)).copied().unwrap_or_default();
+        let report = reclaim_assuming_freed(config, already_freed, &mut on_disposed)?;
+        if let Some(device) = device {
+            if config.dry_run {
+                let freed : &mut Freed = freed_by_device.entry(device).or_default();
+                freed.bytes += report.bytes_freed;
+                freed.inodes += report.deleted.len() as u64;
+            }
+            if let Some(previous) = latest_by_device.insert(device, reports.len()) {
+                reports[previous].target_unmet = false;
+            }
+        }
+        let stop = report.interrupted || report.aborted;
+        reports.push(report);
+        if stop {
+            break;
+        }
+    }
+    Ok(reports)
+}
+
+/// Call `on_file` with every file beneath `config.path` which its filters don't exclude, whatever
+/// its age or size, without planning or deleting anything. A hard-linked file is only passed once,
+/// however many of its links are found. Returns the number of entries which couldn't be read, by
+/// error kind.
+pub fn survey(config: &ReclaimConfig, mut on_file: impl FnMut(&FileInfo)) -> anyhow::Result<BTreeMap<String, usize>> {
+    let filters = Filters::new(config)?;
+    let is_interrupted = || config.interrupt.as_ref().is_some_and(|interrupt| interrupt.load(atomic::Ordering::SeqCst));
+    let mut warned_creation_time_unsupported = false;
+    let mut seen = HashSet::new();
+    let mut skipped = BTreeMap::new();
+    for entry in entries(config, None, None) {
+        if is_interrupted() {
+            break;
+        }
+        let WalkEntry { path, metadata } = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::info!("Skipping {}", err);
+                *skipped.entry(err.error.kind().to_string()).or_insert(0) += 1;
+                continue;
+            }
+        };
+        if !metadata.is_file || filters.excludes(config, &path, &metadata) {
+            continue;
+        }
+        let accessed = match timestamp(config, &path, &metadata, &mut warned_creation_time_unsupported)? {
+            Some(accessed) => accessed,
+            None => continue,
+        };
+        let inode = metadata.inode;
+        if metadata.links > 1 && inode.is_some_and(|inode| !seen.insert(inode)) {
+            continue;
+        }
+        let size = config.size_source.of(&metadata);
+        on_file(&FileInfo { rank : (0, 0), accessed, frequency : 0, size, length : metadata.len, inode, path, links : Vec::new() });
+    }
+    Ok(skipped)
+}
+
+/// The largest files found by `largest`.
+pub struct Largest {
+    /// the largest candidates, largest first, with files of the same size in order of path
+    pub files : Vec<FileInfo>,
+    /// the number of files which could be deleted, of which `files` are the largest
+    pub candidates : usize,
+    pub candidate_bytes : u64,
+    /// the number of entries which couldn't be read while walking, by error kind
+    pub skipped : BTreeMap<String, usize>,
+}
+
+/// The `n` largest files beneath `config.path` which a run could delete, i.e. those it would rank as
+/// candidates, without planning or deleting anything. Only the `n` largest found so far are held
+/// while walking, along with what keeping the newest files holds back. A group or a file with
+/// several hard links is listed once, by its first path, with the space all of its paths take, and
+/// left out if any of its paths can't be deleted.
+pub fn largest(config: &ReclaimConfig, n: usize) -> anyhow::Result<Largest> {
+    if config.max_file_size.is_some_and(|max_file_size| config.min_file_size > max_file_size) {
+        anyhow::bail!("the minimum file size ({}) must not be greater than the maximum file size ({})", config.min_file_size, config.max_file_size.unwrap_or_default());
+    }
+    if config.older_than < Duration::zero() {
+        anyhow::bail!("the minimum age of files to delete must not be negative");
+    }
+    validate_retention(config)?;
+    let group_pattern = config.group_by.as_deref().map(Regex::new).transpose().context("invalid group regex")?;
+    validate_root(config)?;
+    let filters = Filters::new(config)?;
+    let eligibility = Eligibility::new(config, &filters, config.clock.now());
+    let is_interrupted = || config.interrupt.as_ref().is_some_and(|interrupt| interrupt.load(atomic::Ordering::SeqCst));
+    let mut tally = Tally::default();
+    // ranked by size alone, so that the heap's maximum is the smallest file, which makes way for
+    // any larger one once the heap is full
+    let mut heap = BinaryHeap::new();
+    let mut consider = |mut file: FileInfo| {
+        file.rank = (-i128::from(file.size), 0);
+        heap.push(file);
+        if heap.len() > n {
+            heap.pop();
+        }
+    };
+    let mut walk = Walk::new(config, &eligibility, group_pattern.as_ref());
+    let mut skipped = BTreeMap::new();
+    for entry in entries(config, None, None) {
+        if is_interrupted() {
+            break;
+        }
+        let WalkEntry { path, metadata } = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::info!("Skipping {}", err);
+                *skipped.entry(err.error.kind().to_string()).or_insert(0) += 1;
+                continue;
+            }
+        };
+        walk.found(config, path, &metadata, &mut tally, |_, _| 0, &mut consider)?;
+    }
+    walk.finish(config, &mut consider);
+    Ok(Largest { files : heap.into_sorted_vec(), candidates : walk.candidates, candidate_bytes : walk.candidate_bytes, skipped })
+}
+
+/// Why the file planned as `file` can no longer be deleted as planned, if it can't: every one of
+/// its paths must still be a file with the same inode, length and timestamp as when it was planned.
+fn changed_since_planned(config: &ReclaimConfig, file: &FileInfo, warned_creation_time_unsupported: &mut bool) -> anyhow::Result<Option<String>> {
+    for path in file.paths() {
+        let metadata = match config.filesystem.stat(path, config.follow_symlinks) {
+            Ok(metadata) => metadata,
+            Err(err) => return Ok(Some(err.kind().to_string())),
+        };
+        let accessed = timestamp(config, path, &metadata, warned_creation_time_unsupported)?;
+        // a file clamped to the time it was planned is unchanged for as long as it stays ahead
+        let clamped = config.future_time == FutureTime::Clamp && accessed.is_some_and(|accessed| accessed > file.accessed && accessed > config.clock.now());
+        let changed = !metadata.is_file
+            || metadata.len != file.length
+            || file.inode.is_some_and(|inode| metadata.inode != Some(inode))
+            || accessed != Some(file.accessed) && !clamped;
+        if changed {
+            return Ok(Some("changed since planned".to_string()));
+        }
+        if config.skip_readonly && metadata.readonly {
+            return Ok(Some("made read-only since planned".to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Dispose of exactly the files in `plan`, typically found by an earlier dry run of `config`, in
+/// order and without walking `config.path` or checking any target. Files are only deleted if they
+/// haven't changed since they were planned, and are otherwise counted as skipped. `on_disposed`
+/// is called as for `reclaim_with`.
+pub fn apply_plan(config: &ReclaimConfig, plan: Vec<FileInfo>, mut on_disposed: impl FnMut(&FileInfo)) -> anyhow::Result<ReclaimReport> {
+    validate_deletion_limits(config)?;
+    validate_root(config)?;
+    let is_interrupted = || config.interrupt.as_ref().is_some_and(|interrupt| interrupt.load(atomic::Ordering::SeqCst));
+    let current_available_space = available_space(config)?;
+    let n_candidates = plan.len();
+    let mut throttle = Throttle::new(config.max_files_per_second, config.max_bytes_per_second);
+    let mut warned_creation_time_unsupported = false;
+    let mut deleted_files = Vec::new();
+    let mut n_bytes_deleted = 0;
+    let mut skipped = BTreeMap::new();
+    let mut failed = BTreeMap::new();
+    let mut interrupted = false;
+    let mut disposer = Disposer::new(config);
+    // every later file would fail the same way, so the rest are left in place
+    let mut out_of_space = None;
+    for file in plan {
+        if out_of_space.is_some() {
+            break;
+        }
+        if !config.dry_run {
+            throttle.wait(file.paths().count() as u64, file.size, is_interrupted);
+        }
+        if is_interrupted() {
+            log::info!("Interrupted after deleting {} files", deleted_files.len());
+            interrupted = true;
+            break;
+        }
+        if let Some(reason) = changed_since_planned(config, &file, &mut warned_creation_time_unsupported)? {
+            log::info!("Skipping {}: {}", file.path.display(), reason);
+            *skipped.entry(reason).or_insert(0) += 1;
+            continue;
+        }
+        if config.max_files_to_delete.is_some_and(|max| deleted_files.len() + disposer.n_paths_in_flight() + file.paths().count() > max) {
+            log::info!("Stopping after deleting {} files; more runs will be required", deleted_files.len() + disposer.n_paths_in_flight());
+            break;
+        }
+        for disposed in disposer.dispose(config, file) {
+            if let Some(err) = account(config, disposed, None, &mut deleted_files, &mut n_bytes_deleted, &mut failed, &mut on_disposed) {
+                out_of_space = out_of_space.or(Some(err).filter(is_out_of_space));
+            }
+        }
+    }
+    for disposed in disposer.finish() {
+        if let Some(err) = account(config, disposed, None, &mut deleted_files, &mut n_bytes_deleted, &mut failed, &mut on_disposed) {
+            out_of_space = out_of_space.or(Some(err).filter(is_out_of_space));
+        }
+    }
+    if let Some(err) = &out_of_space {
+        warn_out_of_space(config, deleted_files.len(), err);
+    }
+
+    let directories_pruned = if config.prune_empty_dirs && !config.dry_run {
+        prune_empty_directories(config.filesystem.as_ref(), &config.path, &deleted_files)
+    } else {
+        0
+    };
+
+    Ok(ReclaimReport {
+        path : config.path.clone(),
+        candidates : n_candidates,
+        deleted : deleted_files,
+        bytes_freed : n_bytes_deleted,
+        free_before : current_available_space,
+        free_after : available_space(config)?,
+        target : None,
+        target_met : None,
+        skipped,
+        failed,
+        directories_pruned,
+        target_unmet : false,
+        interrupted,
+        aborted : false,
+        withheld : Vec::new(),
+    })
+}
+
+/// Check that the bands of `config.retention` run from youngest to oldest, each older than the last,
+/// ending in one which takes every file left, and that no other age limit is set alongside them.
+fn validate_retention(config: &ReclaimConfig) -> anyhow::Result<()> {
+    let (last, bounded) = match config.retention.split_last() {
+        Some(bands) => bands,
+        None => return Ok(()),
+    };
+    if config.older_than > Duration::zero() || config.purge_older_than.is_some() {
+        anyhow::bail!("retention bands can't be combined with a minimum age or an age to purge files at");
+    }
+    if last.younger_than.is_some() {
+        anyhow::bail!("the last retention band must take every file older than the bands before it, without an age of its own");
+    }
+    let mut previous = Duration::zero();
+    for band in bounded {
+        match band.younger_than {
+            Some(younger_than) if younger_than > previous => previous = younger_than,
+            Some(_) => anyhow::bail!("the ages of the retention bands must be positive and increase from each band to the next"),
+            None => anyhow::bail!("every retention band but the last must have an age"),
+        }
+    }
+    Ok(())
+}
+
+/// Check the limits on how quickly and how much `config` may delete.
+fn validate_deletion_limits(config: &ReclaimConfig) -> anyhow::Result<()> {
+    if config.max_files_to_delete == Some(0) {
+        anyhow::bail!("the maximum number of files to delete must be greater than 0");
+    }
+    if config.max_files_per_second.is_some_and(|rate| !(rate > 0.0 && rate.is_finite())) {
+        anyhow::bail!("the rate of files to delete must be a positive number");
+    }
+    if config.max_bytes_per_second == Some(0) {
+        anyhow::bail!("the rate of bytes to delete must be greater than 0");
+    }
+    if config.delete_threads == 0 {
+        anyhow::bail!("the number of threads to delete files on must be greater than 0");
+    }
+    if matches!(config.disposal, Disposal::Shred(0)) {
+        anyhow::bail!("the number of passes to shred files with must be greater than 0");
+    }
+    Ok(())
+}
+
+/// Reclaim space as described by `config`, planning as though `already_freed` more bytes and
+/// inodes were available on its filesystem than really are.
+fn reclaim_assuming_freed(config: &ReclaimConfig, already_freed: Freed, mut on_disposed: impl FnMut(&FileInfo)) -> anyhow::Result<ReclaimReport> {
+    // guard against misconfigured targets, which would otherwise delete everything and still fail
+    let warn_or_bail = |message: String| {
+        if config.strict {
+            Err(anyhow::anyhow!(message))
+        } else {
+            log::warn!("{}", message);
+            Ok(())
+        }
+    };
+    let target_available_space = match config.target {
+        None => 0,
+        Some(Target::Bytes(target_available_space)) => {
+            let total_space = total_space(config)?;
+            if target_available_space > total_space {
+                warn_or_bail(format!(
+                    "the target of {} bytes available exceeds the {} byte capacity of the filesystem containing {}, so it can never be reached",
+                    target_available_space, total_space, config.path.display(),
+                ))?;
+            }
+            target_available_space
+        }
+        Some(Target::Percent(percent)) => {
+            if !(0.0..=100.0).contains(&percent) {
+                anyhow::bail!("the target percentage must be between 0 and 100, got {}", percent);
+            }
+            let total_space = total_space(config)?;
+            (total_space as f64 * percent / 100.0) as u64
+        }
+    };
+    validate_deletion_limits(config)?;
+    if config.max_candidates == Some(0) {
+        anyhow::bail!("the maximum number of candidates must be greater than 0");
+    }
+    if config.max_file_size.is_some_and(|max_file_size| config.min_file_size > max_file_size) {
+        anyhow::bail!("the minimum file size ({}) must not be greater than the maximum file size ({})", config.min_file_size, config.max_file_size.unwrap_or_default());
+    }
+    if config.max_delete_percent.is_some_and(|percent| !(0.0..=100.0).contains(&percent)) {
+        anyhow::bail!("the maximum percentage of candidates to delete must be between 0 and 100, got {}", config.max_delete_percent.unwrap_or_default());
+    }
+    if config.recheck_interval == Some(0) {
+        anyhow::bail!("the interval between free space checks must be greater than 0");
+    }
+    let purges_by_retention = config.retention.iter().any(|band| band.policy == RetentionPolicy::Purge);
+    if config.assume_sorted && (config.strategy != Strategy::Lru || config.keep_newest > 0 || config.keep_per_dir > 0 || config.purge_older_than.is_some() || purges_by_retention) {
+        anyhow::bail!("assuming sorted names is only supported by the lru strategy, without keeping the newest files or purging old ones");
+    }
+    if config.older_than < Duration::zero() {
+        anyhow::bail!("the minimum age of files to delete must not be negative");
+    }
+    if config.purge_older_than.is_some_and(|purge_older_than| purge_older_than < Duration::zero()) {
+        anyhow::bail!("the age of files to purge must not be negative");
+    }
+    validate_retention(config)?;
+    if let Some(max_depth) = config.max_depth {
+        if config.min_depth > max_depth {
+            anyhow::bail!("the minimum depth ({}) must not be greater than the maximum depth ({})", config.min_depth, max_depth);
+        }
+    }
+    if config.walk_index.is_some() && (config.respect_ignore || config.follow_symlinks || config.listed_files.is_some() || config.assume_sorted || config.strategy == Strategy::Lfu) {
+        anyhow::bail!("the walk index isn't supported when respecting ignore files, following symlinks, listing files, assuming sorted names or with the lfu strategy");
+    }
+    // skipping files, or relying on metadata which may be stale, could split a group
+    if config.group_by.is_some() && (config.walk_index.is_some() || config.assume_sorted) {
+        anyhow::bail!("grouping files isn't supported with the walk index or when assuming sorted names");
+    }
+    let low_memory_conflicts = config.strategy != Strategy::Lru || config.keep_newest > 0 || config.keep_per_dir > 0 || config.max_candidates.is_some()
+        || config.purge_older_than.is_some() || !config.retention.is_empty() || config.group_by.is_some() || config.walk_index.is_some() || config.assume_sorted;
+    if config.low_memory && low_memory_conflicts {
+        anyhow::bail!("the low-memory mode is only supported by the lru strategy, without keeping the newest files, capping candidates, purging, retention bands, grouping, the walk index or assuming sorted names");
+    }
+    let group_pattern = config.group_by.as_deref().map(Regex::new).transpose().context("invalid group regex")?;
+    let root_device = validate_root(config)?;
+    let filters = Filters::new(config)?;
+    let current_available_space = available_space(config)?;
+    let target_available_inodes = config.target_inodes.unwrap_or(0);
+    let current_available_inodes = if config.target_inodes.is_some() {
+        let available_inodes = available_inodes(config)?;
+        if available_inodes.is_none() {
+            log::warn!("inode counts are unavailable for {}, so --target-available-inodes has no effect", config.path.display());
+        }
+        available_inodes
+    } else {
+        None
+    };
+    let now = config.clock.now();
+    let purge_time = config.purge_older_than.map(|purge_older_than| now - purge_older_than);
+
+    if config.one_file_system && !cfg!(unix) {
+        log::warn!("device ids are unavailable on this platform, so --one-file-system has no effect");
+    }
+    if config.owner.is_some() && !cfg!(unix) {
+        log::warn!("file owners are unavailable on this platform, so --user has no effect");
+    }
+    if !cfg!(unix) {
+        log::debug!("Hard links can't be detected on this platform, so each link is treated as a separate file");
+    }
+    if !config.dry_run {
+        match &config.disposal {
+            Disposal::Trash => log::warn!("--trash doesn't free any space until the trash is emptied, so free space won't reflect the files trashed by this run"),
+            Disposal::MoveTo(move_to) => {
+                if let (Ok(root), Ok(destination)) = (config.filesystem.canonicalize(&config.path), config.filesystem.canonicalize(move_to)) {
+                    if destination.starts_with(&root) {
+                        anyhow::bail!("the directory to move files to must not be beneath the top-level directory");
+                    }
+                }
+                // the destination may not exist yet, in which case it will be created on the
+                // filesystem of its nearest existing ancestor
+                let destination_device = move_to.ancestors()
+                    .find_map(|directory| config.filesystem.stat(directory, true).ok())
+                    .and_then(|metadata| metadata.inode)
+                    .map(|(device, _)| device);
+                let root_device = device(config, &config.path);
+                if destination_device.is_some() && destination_device == root_device {
+                    log::warn!("--move-to {} is on the same filesystem as {}, so moving files there frees NO space", move_to.display(), config.path.display());
+                }
+            }
+            Disposal::Shred(_) => log::warn!("--shred can't reliably destroy data on copy-on-write filesystems or SSDs, which may write the zeros elsewhere and leave the original blocks intact"),
+            Disposal::Delete => {}
+        }
+    }
+
+    let index_path = config.path.join(index::FILE_NAME);
+    let mut frequency_index = if config.strategy == Strategy::Lfu {
+        match FrequencyIndex::load(&index_path) {
+            Ok(frequency_index) => Some(frequency_index),
+            Err(err) => {
+                log::warn!("ignoring unreadable index {}: {}", index_path.display(), err);
+                Some(FrequencyIndex::default())
+            }
+        }
+    } else {
+        None
+    };
+
+    let walk_index = config.walk_index.as_ref().map(|walk_index_path| {
+        let index = WalkIndex::load(walk_index_path).unwrap_or_else(|err| {
+            log::warn!("ignoring unreadable walk index {}: {}", walk_index_path.display(), err);
+            WalkIndex::default()
+        });
+        (walk_index_path, SharedWalkIndex::new(index.into()))
+    });
+    if walk_index.is_some() && !cfg!(unix) {
+        log::warn!("directory timestamps can't be relied on for this platform, so the walk index has no effect");
+    }
+
+    // every pass decides which files are candidates just as `largest` does
+    let eligibility = Eligibility::new(config, &filters, now);
+    let is_expired = |file: &FileInfo| {
+        purge_time.is_some_and(|purge_time| file.accessed < purge_time) || eligibility.retention_policy(file.accessed) == Some(RetentionPolicy::Purge)
+    };
+    if config.low_memory {
+        let setup = low_memory::Setup {
+            eligibility : &eligibility,
+            free_before : current_available_space,
+            inodes_before : current_available_inodes,
+            target_available_space,
+            target_available_inodes,
+            already_freed,
+        };
+        return low_memory::reclaim(config, setup, on_disposed);
+    }
+
+    let mut n_candidates = 0;
+    let mut n_candidate_bytes = 0;
+    let mut n_bytes_deleted = 0;
+    // the number of entries we couldn't read, and files we couldn't delete, by error kind
+    let mut skipped = BTreeMap::new();
+    let mut failed = BTreeMap::new();
+    let mut target_unmet = false;
+    let mut deleted_files = Vec::new();
+    let mut tally = Tally::default();
+    let is_interrupted = || config.interrupt.as_ref().is_some_and(|interrupt| interrupt.load(atomic::Ordering::SeqCst));
+    let mut interrupted = false;
+    let mut aborted = false;
+    let mut withheld = Vec::new();
+    // the number of files protected by `keep_newest` and `keep_per_dir` in the latest pass
+    let mut n_protected = 0;
+    let mut warned_other_filesystem = false;
+    // when --max-candidates stops a pass from holding every file it needs, the pass deletes the
+    // lowest-ranked files it could hold, and the next pass picks up above the highest of them
+    let mut threshold : Option<((i128, i128), PathBuf)> = None;
+    let mut another_pass_reason = "";
+    let rechecking = config.recheck_interval.filter(|_| !config.dry_run);
+    loop {
+        let first_pass = threshold.is_none();
+        // dry runs free nothing, so the files earlier passes would have deleted are credited instead
+        let planned = if config.dry_run {
+            Freed { bytes : already_freed.bytes + n_bytes_deleted, inodes : already_freed.inodes + deleted_files.len() as u64 }
+        } else {
+            already_freed
+        };
+        let (available_space_now, available_inodes_now) = if first_pass {
+            (current_available_space, current_available_inodes)
+        } else {
+            (available_space(config)?, current_available_inodes.and(available_inodes(config)?))
+        };
+        let assumed_available_space = available_space_now + planned.bytes;
+        let inode_shortfall = available_inodes_now
+            .map_or(0, |available_inodes| target_available_inodes.saturating_sub(available_inodes + planned.inodes));
+        // lfu must observe every file on every run to keep its access frequencies up to date, and a
+        // purge deletes files, even when there's nothing else to delete
+        let walk_anyway = first_pass && (frequency_index.is_some() || purge_time.is_some() || purges_by_retention);
+        if !(assumed_available_space < target_available_space || inode_shortfall > 0 || walk_anyway) {
+            break;
+        }
+        if !first_pass {
+            log::info!("Starting another pass, since {}", another_pass_reason);
+        }
+        target_unmet = false;
+
+        let mut files_to_delete = BinaryHeap::<FileInfo>::new();
+        let mut aggregate_heap_file_size = 0;
+        // whether --max-candidates discarded a file this pass would otherwise have deleted
+        let mut saturated = false;
+        let mut walk = Walk::new(config, &eligibility, group_pattern.as_ref());
+        // the files to purge whatever the free space, which are all found by the first pass
+        let mut expired = Vec::new();
+        let closed_directories = config.assume_sorted.then(ClosedDirectories::default);
+        let space_shortfall = target_available_space.saturating_sub(assumed_available_space);
+        let remaining_cap = config.max_bytes_to_delete.map(|cap| cap.saturating_sub(n_bytes_deleted));
+        let max_n_bytes_to_delete = match remaining_cap {
+            Some(cap) if cap < space_shortfall => {
+                if first_pass {
+                    log::info!("Capping reclamation at {} of the {} bytes needed to reach the target; more runs will be required", cap, space_shortfall);
+                }
+                cap
+            }
+            _ => space_shortfall,
+        };
+
+        // purge expired files, and push the rest onto the heap, once nothing protects them
+        let mut consider = |file: FileInfo| {
+            if is_expired(&file) {
+                if first_pass {
+                    expired.push(file);
+                }
+            } else if !file.is_at_or_below(threshold.as_ref()) {
+                // once the heap holds enough and this file ranks above all of it, every later
+                // entry in its directory is assumed to as well
+                let closes_directory = closed_directories.is_some()
+                    && aggregate_heap_file_size >= max_n_bytes_to_delete
+                    && files_to_delete.len() as u64 >= inode_shortfall
+                    && files_to_delete.peek().is_some_and(|highest| file > *highest);
+                if let (true, Some(closed_directories), Some(parent)) = (closes_directory, &closed_directories, file.path.parent()) {
+                    if closed_directories.lock().expect("the lock is never poisoned").insert(parent.to_path_buf()) {
+                        log::debug!("Skipping the rest of {}, since {} is already too new to delete", parent.display(), file.path.display());
+                    }
+                }
+                saturated |= push_candidate(&mut files_to_delete, &mut aggregate_heap_file_size, file, max_n_bytes_to_delete, inode_shortfall, config.max_candidates);
+            }
+        };
+
+        for entry in entries(config, closed_directories.clone(), walk_index.as_ref().map(|(_, index)| index.clone())) {
+            if is_interrupted() {
+                interrupted = true;
+                break;
+            }
+            let WalkEntry { path, metadata } = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    if first_pass {
+                        log::info!("Skipping {}", err);
+                        *skipped.entry(err.error.kind().to_string()).or_insert(0) += 1;
+                    }
+                    continue;
+                }
+            };
+            // free space is only measured for the top-level directory's filesystem, so files
+            // beyond it don't bring the target any closer
+            if !config.one_file_system && !warned_other_filesystem && root_device.is_some_and(|root_device| metadata.inode.is_some_and(|(device, _)| device != root_device)) {
+                log::warn!(
+                    "{} lies on a different filesystem from {}, whose free space alone is counted toward the target; pass --one-file-system to stay on it",
+                    path.display(), config.path.display(),
+                );
+                warned_other_filesystem = true;
+            }
+            let frequency = |path: &Path, accessed| match frequency_index.as_mut() {
+                Some(frequency_index) if first_pass => frequency_index.observe(path, accessed),
+                Some(frequency_index) => frequency_index.frequency(path),
+                None => 0,
+            };
+            walk.found(config, path, &metadata, &mut tally, frequency, &mut consider)?;
+        }
+
+        // an interrupted walk can't have found everything, so nothing it found is deleted
+        if interrupted {
+            break;
+        }
+        if first_pass && config.listed_files.is_some() && !skipped.is_empty() {
+            log::warn!("skipped {} of the listed files beneath {} which couldn't be read", skipped.values().sum::<usize>(), config.path.display());
+        }
+        if first_pass && tally.future > 0 {
+            let consequence = match config.future_time {
+                FutureTime::Warn => "so they rank as the most recently used",
+                FutureTime::Clamp => "so they rank as used now",
+                FutureTime::Skip => "so they're never deleted",
+            };
+            log::warn!("{} files beneath {} have timestamps in the future, {}", tally.future, config.path.display(), consequence);
+        }
+        if first_pass && tally.undeletable > 0 {
+            log::info!("Skipping {} files beneath {} which are immutable or append-only, and can't be deleted", tally.undeletable, config.path.display());
+        }
+
+        n_protected = walk.finish(config, &mut consider);
+        if first_pass {
+            n_candidates = walk.candidates;
+            n_candidate_bytes = walk.candidate_bytes;
+        }
+
+        // re-query available space in case our capacity has been reduced since we started running the program
+        let n_bytes_to_delete = target_available_space
+            .saturating_sub(available_space(config)? + planned.bytes)
+            .min(max_n_bytes_to_delete);
+        let n_inodes_to_free = match current_available_inodes {
+            Some(_) => available_inodes(config)?
+                .map_or(0, |available_inodes| target_available_inodes.saturating_sub(available_inodes + planned.inodes)),
+            None => 0,
+        };
+        if n_bytes_to_delete == 0 && n_inodes_to_free == 0 && expired.is_empty() {
+            break;
+        }
+        // purged files count towards the targets, so the heap only needs to make up the rest
+        expired.sort();
+        let mut n_expired_bytes : u64 = expired.iter().map(|file| file.size).sum();
+        let n_heap_bytes_to_delete = n_bytes_to_delete.saturating_sub(n_expired_bytes);
+        let n_heap_inodes_to_free = n_inodes_to_free.saturating_sub(expired.len() as u64);
+        // the heap only holds less than we need once every deletable file is on it, or once
+        // --max-candidates has stopped it growing
+        target_unmet = aggregate_heap_file_size < n_heap_bytes_to_delete || (files_to_delete.len() as u64) < n_heap_inodes_to_free;
+        // if the space we need to reclaim has shrunk since we initially queried it (prior
+        // to filling up the heap), pop the highest-ranked elements until the heap
+        // reaches an appropriate size.
+        trim_heap(&mut files_to_delete, &mut aggregate_heap_file_size, n_heap_bytes_to_delete, n_heap_inodes_to_free);
+        if !expired.is_empty() {
+            log::debug!("Purging {} bytes from {} files older than --purge-older-than", n_expired_bytes, expired.len());
+        }
+        log::debug!(
+            "Planning to reclaim {} bytes from {} of {} candidates to free the {} bytes and {} inodes needed",
+            aggregate_heap_file_size, files_to_delete.len(), n_candidates, n_heap_bytes_to_delete, n_heap_inodes_to_free,
+        );
+        if let Some(cap) = remaining_cap {
+            // the heap may overshoot the space we need by up to one file, but it must never
+            // overshoot the cap, and nor may a purge
+            while aggregate_heap_file_size + n_expired_bytes > cap {
+                if let Some(file) = files_to_delete.pop() {
+                    aggregate_heap_file_size -= file.size;
+                } else if let Some(file) = expired.pop() {
+                    n_expired_bytes -= file.size;
+                } else {
+                    break;
+                }
+            }
+        }
+        if first_pass && n_candidates > 0 && files_to_delete.len() as f64 > n_candidates as f64 * MAX_SHARE_OF_CANDIDATES {
+            warn_or_bail(format!(
+                "reaching the target for {} means deleting {} of its {} candidate files, more than {}% of them",
+                config.path.display(), files_to_delete.len(), n_candidates, MAX_SHARE_OF_CANDIDATES * 100.0,
+            ))?;
+        }
+        if let Some(percent) = config.max_delete_percent.filter(|_| first_pass && !config.dry_run) {
+            let share = |part: f64, whole: f64| if whole > 0.0 { part * 100.0 / whole } else { 0.0 };
+            let share_of_files = share(files_to_delete.len() as f64, n_candidates as f64);
+            let share_of_bytes = share(aggregate_heap_file_size as f64, n_candidate_bytes as f64);
+            if share_of_files > percent || share_of_bytes > percent {
+                log::warn!(
+                    "deleting nothing from {}, since reaching the target means deleting {} of its {} candidate files ({} of {} bytes), more than --max-delete-percent {}%",
+                    config.path.display(), files_to_delete.len(), n_candidates, aggregate_heap_file_size, n_candidate_bytes, percent,
+                );
+                aborted = true;
+                withheld = expired.into_iter().chain(files_to_delete.into_sorted_vec()).collect();
+                break;
+            }
+        }
+        let plan = Plan { files : expired.len() + files_to_delete.len(), bytes : n_expired_bytes + aggregate_heap_file_size };
+        if let Some(confirm) = config.confirm.as_ref().filter(|_| plan.files > 0 && !config.dry_run) {
+            if !confirm(&config.path, &plan) {
+                log::info!("Deleting nothing more from {}, since the plan wasn't confirmed", config.path.display());
+                aborted = true;
+                break;
+            }
+        }
+        if let Some(on_planned) = &config.on_planned {
+            on_planned(&plan);
+        }
+        // delete in rank order so that the files we least want to keep go first if we stop early,
+        // purged files first of all since they're deleted regardless
+        let mut stopped_early = false;
+        let mut reached = false;
+        let mut highest_handled = None;
+        // whether any planned file had changed since the walk index recorded it
+        let mut stale = false;
+        // the rates only limit deleting, so neither the walk nor earlier passes count toward them
+        let mut throttle = Throttle::new(config.max_files_per_second, config.max_bytes_per_second);
+        let mut disposer = Disposer::new(config);
+        // every later file would fail the same way, so the rest are left in place
+        let mut out_of_space = None;
+        for (n_handled, file) in expired.into_iter().chain(files_to_delete.into_sorted_vec()).enumerate() {
+            if out_of_space.is_some() {
+                break;
+            }
+            if !config.dry_run {
+                throttle.wait(file.paths().count() as u64, file.size, is_interrupted);
+            }
+            if is_interrupted() {
+                log::info!("Interrupted after deleting {} files", deleted_files.len());
+                interrupted = true;
+                break;
+            }
+            // other processes may free or consume space while we delete, so rather than trusting
+            // the plan, check whether the targets have been met yet. purged files are deleted
+            // regardless
+            if rechecking.is_some_and(|interval| n_handled > 0 && n_handled % interval == 0) && !is_expired(&file) {
+                let space_reached = available_space(config)? + planned.bytes >= target_available_space;
+                let inodes_reached = current_available_inodes.is_none() || available_inodes(config)?
+                    .is_none_or(|available_inodes| available_inodes + planned.inodes >= target_available_inodes);
+                if space_reached && inodes_reached {
+                    log::info!("Stopping after deleting {} files, since the target has been reached", deleted_files.len());
+                    reached = true;
+                    break;
+                }
+            }
+            if config.max_files_to_delete.is_some_and(|max| deleted_files.len() + disposer.n_paths_in_flight() + file.paths().count() > max) {
+                log::info!("Stopping after deleting {} files; more runs will be required", deleted_files.len() + disposer.n_paths_in_flight());
+                stopped_early = true;
+                break;
+            }
+            if let Some((_, index)) = &walk_index {
+                // the index may have planned the file by metadata which has since changed, e.g.
+                // because it was read, so it's left for the next pass to consider afresh
+                let reason = match changed_since_planned(config, &file, &mut tally.warned_creation_time_unsupported)? {
+                    None if file.paths().any(|path| config.filesystem.stat(path, false).is_ok_and(|metadata| metadata.links != file.paths().count() as u64)) => {
+                        Some("its hard links changed".to_string())
+                    }
+                    reason => reason,
+                };
+                if let Some(reason) = reason {
+                    log::debug!("Not deleting {} as planned, since {} since the walk index recorded it", file.path.display(), reason);
+                    let mut index = index.lock().expect("the lock is never poisoned");
+                    for path in file.paths() {
+                        index.forget(path);
+                    }
+                    stale = true;
+                    highest_handled = Some((file.rank, file.path));
+                    continue;
+                }
+            }
+            let handled = (file.rank, file.path.clone());
+            for disposed in disposer.dispose(config, file) {
+                if let Some(err) = account(config, disposed, frequency_index.as_mut(), &mut deleted_files, &mut n_bytes_deleted, &mut failed, &mut on_disposed) {
+                    out_of_space = out_of_space.or(Some(err).filter(is_out_of_space));
+                }
+            }
+            highest_handled = Some(handled);
+        }
+        for disposed in disposer.finish() {
+            if let Some(err) = account(config, disposed, frequency_index.as_mut(), &mut deleted_files, &mut n_bytes_deleted, &mut failed, &mut on_disposed) {
+                out_of_space = out_of_space.or(Some(err).filter(is_out_of_space));
+            }
+        }
+        if let Some(err) = &out_of_space {
+            warn_out_of_space(config, deleted_files.len(), err);
+            stopped_early = true;
+        }
+        // if the plan was carried out but space was consumed as we deleted, the next pass checks
+        // whether more is needed
+        let outpaced = rechecking.is_some() && !target_unmet;
+        match highest_handled {
+            Some(highest_handled) if (saturated || outpaced || stale) && !stopped_early && !reached && !interrupted => {
+                threshold = Some(highest_handled);
+                another_pass_reason = if saturated {
+                    "--max-candidates didn't leave room for every file needed"
+                } else if stale {
+                    "some planned files had changed since the walk index recorded them"
+                } else {
+                    "the target may not have been reached while deleting"
+                };
+            }
+            _ => break,
+        }
+    }
+    if target_unmet && tally.too_large > 0 {
+        log::warn!("the target for {} can't be met without deleting the {} file(s) larger than --max-file-size", config.path.display(), tally.too_large);
+    }
+    if target_unmet && n_protected > 0 {
+        log::warn!("the target for {} can't be met without deleting the {} file(s) protected by --keep-newest or --keep-per-dir", config.path.display(), n_protected);
+    }
+
+    let directories_pruned = if config.prune_empty_dirs && !config.dry_run {
+        prune_empty_directories(config.filesystem.as_ref(), &config.path, &deleted_files)
+    } else {
+        0
+    };
+
+    if let Some(frequency_index) = frequency_index {
+        if let Err(err) = frequency_index.save(&index_path) {
+            log::warn!("failed to save index {}: {}", index_path.display(), err);
+        }
+    }
+
+    if let Some((walk_index_path, index)) = walk_index {
+        let mut index = index.lock().expect("the lock is never poisoned");
+        if let Err(err) = index.save(walk_index_path, &config.path) {
+            log::warn!("failed to save walk index {}: {}", walk_index_path.display(), err);
+        }
+    }
+
+    let free_after = available_space(config)?;
+    let assumed_free_after = if config.dry_run { free_after + already_freed.bytes + n_bytes_deleted } else { free_after };
+    Ok(ReclaimReport {
+        path : config.path.clone(),
+        candidates : n_candidates,
+        deleted : deleted_files,
+        bytes_freed : n_bytes_deleted,
+        free_before : current_available_space,
+        free_after,
+        target : config.target.map(|_| target_available_space),
+        target_met : config.target.map(|_| assumed_free_after >= target_available_space),
+        skipped,
+        failed,
+        directories_pruned,
+        target_unmet,
+        interrupted,
+        aborted,
+        withheld,
+    })
+}
@@ -0,0 +1,75 @@
+use anyhow::Context;
+use fs2::FileExt;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// What to do when another run holds the lock for a top-level directory.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IfLocked {
+    /// leave the directory to the other run
+    Skip,
+    /// wait for the other run to finish
+    Wait,
+}
+
+impl FromStr for IfLocked {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(IfLocked::Skip),
+            "wait" => Ok(IfLocked::Wait),
+            _ => Err(format!("unknown lock policy {:?}: expected skip or wait", s)),
+        }
+    }
+}
+
+/// How often to retry a held lock while waiting for it.
+const POLL_INTERVAL : Duration = Duration::from_millis(100);
+
+/// The lock file for the canonicalized top-level directory `root`. Lock files live in the
+/// temporary directory rather than beneath `root`, so that dry runs never write to it, and are
+/// named for a hash of `root` which is stable across builds.
+fn lock_path(root: &Path) -> PathBuf {
+    // FNV-1a
+    let hash = root.to_string_lossy().bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    env::temp_dir().join(format!("lru-{:016x}.lock", hash))
+}
+
+/// Take the advisory lock for the canonicalized top-level directory `root`, which is held until
+/// the returned file is closed (including when the process exits, however it exits). Returns
+/// `None` if another run holds the lock and `if_locked` is `Skip`, or if `interrupt` is set while
+/// waiting.
+pub fn acquire(root: &Path, if_locked: IfLocked, interrupt: &AtomicBool) -> anyhow::Result<Option<File>> {
+    let path = lock_path(root);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("failed to open lock file {}", path.display()))?;
+    let mut waiting = false;
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(Some(file)),
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {}
+            Err(err) => return Err(err).with_context(|| format!("failed to lock {}", path.display())),
+        }
+        if if_locked == IfLocked::Skip || interrupt.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        if !waiting {
+            log::info!("Waiting for another run on {} to finish", root.display());
+            waiting = true;
+        }
+        // NOTE: a blocking lock can't be interrupted by our signal handler, so poll instead
+        thread::sleep(POLL_INTERVAL);
+    }
+}
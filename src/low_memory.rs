@@ -0,0 +1,246 @@
+use crate::dispose::{is_out_of_space, Throttle};
+use crate::eligible::{Eligibility, Tally};
+use crate::{
+    account, available_space, entries, prune_empty_directories, warn_out_of_space, Disposer, FileInfo, Freed, Plan, ReclaimConfig, ReclaimReport,
+    MAX_SHARE_OF_CANDIDATES,
+};
+use chrono::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::atomic;
+
+/// How much wider each bucket of ages is than the one before. The cutoff between the files deleted
+/// and those kept is only ever as precise as the bucket it falls in, i.e. to within 1% of its age.
+const AGE_BUCKET_GROWTH : f64 = 1.01;
+
+/// What a run has worked out before walking, for `reclaim`.
+pub struct Setup<'a> {
+    pub eligibility : &'a Eligibility<'a>,
+    pub free_before : u64,
+    pub inodes_before : Option<u64>,
+    pub target_available_space : u64,
+    pub target_available_inodes : u64,
+    pub already_freed : Freed,
+}
+
+/// The bucket of ages into which a file used at `accessed` falls as of `now`. Older files fall into
+/// higher buckets, and those used within the last second, or in the future, into bucket 0.
+fn bucket(accessed: DateTime<Local>, now: DateTime<Local>) -> u32 {
+    let age = (now - accessed).num_milliseconds() as f64 / 1000.0;
+    if age < 1.0 {
+        0
+    } else {
+        1 + (age.ln() / AGE_BUCKET_GROWTH.ln()) as u32
+    }
+}
+
+/// The files which the deleting pass deletes: all of those in buckets above `bucket`, and those in
+/// it until `bytes` and `files` more have been.
+struct Cutoff {
+    bucket : u32,
+    bytes : u64,
+    files : u64,
+}
+
+impl Cutoff {
+    /// The cutoff which frees `n_bytes` and `n_files` from the files counted in `histogram`, by
+    /// bucket, oldest first, along with the plan it makes. The plan includes every file of the
+    /// bucket the cutoff falls in, so it's an upper bound. Every file is deleted if that won't
+    /// suffice, as the second element being false says.
+    fn new(histogram: &BTreeMap<u32, (u64, u64)>, n_bytes: u64, n_files: u64) -> (Self, bool, Plan) {
+        let (mut older_bytes, mut older_files) = (0, 0);
+        for (&bucket, &(bytes, files)) in histogram.iter().rev() {
+            if older_bytes + bytes >= n_bytes && older_files + files >= n_files {
+                let plan = Plan { files : (older_files + files) as usize, bytes : older_bytes + bytes };
+                return (Cutoff { bucket, bytes : n_bytes - older_bytes, files : n_files.saturating_sub(older_files) }, true, plan);
+            }
+            older_bytes += bytes;
+            older_files += files;
+        }
+        (Cutoff { bucket : 0, bytes : u64::MAX, files : u64::MAX }, false, Plan { files : older_files as usize, bytes : older_bytes })
+    }
+}
+
+/// Reclaim space as described by `config` in two walks of the tree, holding no more than one file
+/// in memory at a time, however many there are. The first walk counts the candidates in a
+/// histogram of their ages, from which the age beyond which files must go to reach the target is
+/// found, and the second deletes files older than that as it finds them, until the target is
+/// reached. This is only as precise as the histogram: files near the cutoff are deleted in the
+/// order they're found, rather than strictly oldest first.
+pub fn reclaim(config: &ReclaimConfig, setup: Setup, mut on_disposed: impl FnMut(&FileInfo)) -> anyhow::Result<ReclaimReport> {
+    let is_interrupted = || config.interrupt.as_ref().is_some_and(|interrupt| interrupt.load(atomic::Ordering::SeqCst));
+    let space_shortfall = setup.target_available_space.saturating_sub(setup.free_before + setup.already_freed.bytes);
+    let n_bytes_needed = match config.max_bytes_to_delete {
+        Some(cap) if cap < space_shortfall => {
+            log::info!("Capping reclamation at {} of the {} bytes needed to reach the target; more runs will be required", cap, space_shortfall);
+            cap
+        }
+        _ => space_shortfall,
+    };
+    let n_files_needed = setup.inodes_before.map_or(0, |available_inodes| setup.target_available_inodes.saturating_sub(available_inodes + setup.already_freed.inodes));
+
+    let mut skipped = BTreeMap::new();
+    let mut failed = BTreeMap::new();
+    let mut deleted_files = Vec::new();
+    let mut n_bytes_deleted = 0;
+    let mut n_candidates = 0;
+    let mut n_candidate_bytes = 0;
+    let mut interrupted = false;
+    let mut aborted = false;
+    let mut target_unmet = false;
+    let mut tally = Tally::default();
+    if n_bytes_needed > 0 || n_files_needed > 0 {
+        // the bytes and files of the candidates in each bucket of ages
+        let mut histogram = BTreeMap::<u32, (u64, u64)>::new();
+        for entry in entries(config, None, None) {
+            if is_interrupted() {
+                interrupted = true;
+                break;
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::info!("Skipping {}", err);
+                    *skipped.entry(err.error.kind().to_string()).or_insert(0) += 1;
+                    continue;
+                }
+            };
+            // deleting one link to a file frees nothing, and holding the rest until they're all
+            // found would take memory
+            if entry.metadata.links > 1 {
+                continue;
+            }
+            if let Some(file) = setup.eligibility.candidate(config, entry.path, &entry.metadata, &mut tally, |_, _| 0)? {
+                n_candidates += 1;
+                n_candidate_bytes += file.size;
+                let totals = histogram.entry(bucket(file.accessed, setup.eligibility.now)).or_default();
+                totals.0 += file.size;
+                totals.1 += 1;
+            }
+        }
+        let (cutoff, enough, plan) = Cutoff::new(&histogram, n_bytes_needed, n_files_needed);
+        target_unmet = !enough;
+        log::debug!("Planning to reclaim up to {} bytes from {} of {} candidates, using {} buckets of ages", plan.bytes, plan.files, n_candidates, histogram.len());
+        // an interrupted walk can't have found everything, so nothing it found is deleted
+        if !interrupted {
+            if n_candidates > 0 && plan.files as f64 > n_candidates as f64 * MAX_SHARE_OF_CANDIDATES {
+                let message = format!(
+                    "reaching the target for {} means deleting up to {} of its {} candidate files, more than {}% of them",
+                    config.path.display(), plan.files, n_candidates, MAX_SHARE_OF_CANDIDATES * 100.0,
+                );
+                if config.strict {
+                    anyhow::bail!(message);
+                }
+                log::warn!("{}", message);
+            }
+            let share = |part: f64, whole: f64| if whole > 0.0 { part * 100.0 / whole } else { 0.0 };
+            let too_many = config.max_delete_percent.filter(|_| !config.dry_run).is_some_and(|percent| {
+                share(plan.files as f64, n_candidates as f64) > percent || share(plan.bytes as f64, n_candidate_bytes as f64) > percent
+            });
+            if too_many {
+                log::warn!(
+                    "deleting nothing from {}, since reaching the target means deleting up to {} of its {} candidate files ({} of {} bytes), more than --max-delete-percent {}%",
+                    config.path.display(), plan.files, n_candidates, plan.bytes, n_candidate_bytes, config.max_delete_percent.unwrap_or_default(),
+                );
+                aborted = true;
+            } else if config.confirm.as_ref().filter(|_| plan.files > 0 && !config.dry_run).is_some_and(|confirm| !confirm(&config.path, &plan)) {
+                log::info!("Deleting nothing from {}, since the plan wasn't confirmed", config.path.display());
+                aborted = true;
+            } else if let Some(on_planned) = &config.on_planned {
+                on_planned(&plan);
+            }
+        }
+        if !interrupted && !aborted {
+            let mut throttle = Throttle::new(config.max_files_per_second, config.max_bytes_per_second);
+            let mut disposer = Disposer::new(config);
+            let rechecking = config.recheck_interval.filter(|_| !config.dry_run);
+            // every later file would fail the same way, so the rest are left in place
+            let mut out_of_space = None;
+            // what's been handed over for disposal so far, all told and from the cutoff's bucket
+            let (mut n_bytes_handled, mut n_files_handled) = (0, 0);
+            let (mut n_cutoff_bytes_handled, mut n_cutoff_files_handled) = (0, 0);
+            for entry in entries(config, None, None) {
+                if out_of_space.is_some() || n_bytes_handled >= n_bytes_needed && n_files_handled >= n_files_needed {
+                    break;
+                }
+                if is_interrupted() {
+                    log::info!("Interrupted after deleting {} files", deleted_files.len());
+                    interrupted = true;
+                    break;
+                }
+                let file = match entry {
+                    Ok(entry) if entry.metadata.links > 1 => continue,
+                    Ok(entry) => match setup.eligibility.candidate(config, entry.path, &entry.metadata, &mut tally, |_, _| 0)? {
+                        Some(file) => file,
+                        None => continue,
+                    },
+                    Err(_) => continue,
+                };
+                let bucket = bucket(file.accessed, setup.eligibility.now);
+                let beyond_cutoff = bucket > cutoff.bucket
+                    || bucket == cutoff.bucket && (n_cutoff_bytes_handled < cutoff.bytes || n_cutoff_files_handled < cutoff.files);
+                // the plan may overshoot the space we need by up to one file, but never the cap
+                if !beyond_cutoff || config.max_bytes_to_delete.is_some_and(|cap| n_bytes_handled + file.size > cap) {
+                    continue;
+                }
+                if config.max_files_to_delete.is_some_and(|max| n_files_handled + 1 > max as u64) {
+                    log::info!("Stopping after deleting {} files; more runs will be required", n_files_handled);
+                    break;
+                }
+                if !config.dry_run {
+                    throttle.wait(1, file.size, is_interrupted);
+                }
+                // other processes may free or consume space while we delete, so see whether the
+                // target has been met yet, as an ordinary run would
+                let recheck_due = rechecking.is_some_and(|interval| n_files_handled > 0 && n_files_handled % interval as u64 == 0);
+                if recheck_due && n_files_needed == 0 && available_space(config)? + setup.already_freed.bytes >= setup.target_available_space {
+                    log::info!("Stopping after deleting {} files, since the target has been reached", deleted_files.len());
+                    break;
+                }
+                n_bytes_handled += file.size;
+                n_files_handled += 1;
+                if bucket == cutoff.bucket {
+                    n_cutoff_bytes_handled += file.size;
+                    n_cutoff_files_handled += 1;
+                }
+                for disposed in disposer.dispose(config, file) {
+                    if let Some(err) = account(config, disposed, None, &mut deleted_files, &mut n_bytes_deleted, &mut failed, &mut on_disposed) {
+                        out_of_space = out_of_space.or(Some(err).filter(is_out_of_space));
+                    }
+                }
+            }
+            for disposed in disposer.finish() {
+                if let Some(err) = account(config, disposed, None, &mut deleted_files, &mut n_bytes_deleted, &mut failed, &mut on_disposed) {
+                    out_of_space = out_of_space.or(Some(err).filter(is_out_of_space));
+                }
+            }
+            if let Some(err) = &out_of_space {
+                warn_out_of_space(config, deleted_files.len(), err);
+            }
+        }
+    }
+
+    let directories_pruned = if config.prune_empty_dirs && !config.dry_run {
+        prune_empty_directories(config.filesystem.as_ref(), &config.path, &deleted_files)
+    } else {
+        0
+    };
+    let free_after = available_space(config)?;
+    let assumed_free_after = if config.dry_run { free_after + setup.already_freed.bytes + n_bytes_deleted } else { free_after };
+    Ok(ReclaimReport {
+        path : config.path.clone(),
+        candidates : n_candidates,
+        deleted : deleted_files,
+        bytes_freed : n_bytes_deleted,
+        free_before : setup.free_before,
+        free_after,
+        target : config.target.map(|_| setup.target_available_space),
+        target_met : config.target.map(|_| assumed_free_after >= setup.target_available_space),
+        skipped,
+        failed,
+        directories_pruned,
+        target_unmet,
+        interrupted,
+        aborted,
+        withheld : Vec::new(),
+    })
+}
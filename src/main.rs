@@ -1,126 +1,1167 @@
+use anyhow::Context;
 use argh::FromArgs;
-use walkdir::WalkDir;
-use chrono::prelude::*;
 use chrono::Duration;
-use std::collections::BinaryHeap;
-use std::path::PathBuf;
-use std::cmp::Ordering;
-use std::fs::remove_file;
-use fs2;
-
-#[derive(PartialEq, Eq)]
-struct FileInfo {
-    accessed : DateTime<Local>,
-    size : u64,
-    path : PathBuf
-}
+use log::{Level, LevelFilter};
+use lru::{Confirm, Disposal, FutureTime, MissingAtime, OnPlanned, Plan, RealFileSystem, ReclaimConfig, ReclaimReport, RetentionPolicy, SizeSource, Strategy, SystemClock, Target, Timestamp};
+use std::collections::HashSet;
+use std::io::{self, IsTerminal, Read, Write};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{self, Instant};
 
-impl PartialOrd for FileInfo {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.accessed.cmp(&other.accessed))    
-    }
-}
-
-impl Ord for FileInfo {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.accessed.cmp(&other.accessed)
-    }
-}
+mod audit;
+mod config;
+mod global_ignore;
+mod histogram;
+mod interval;
+#[cfg(target_os = "linux")]
+mod journald;
+mod lock;
+mod metrics;
+mod plan;
+mod report;
+mod restore;
+mod retention;
+mod size;
+mod systemd;
+mod top;
+mod watch;
+use audit::AuditLog;
+use config::FileConfig;
+use histogram::Histogram;
+use interval::Interval;
+#[cfg(target_os = "linux")]
+use journald::Journal;
+use lock::IfLocked;
+use plan::{PlanDiff, PlanWriter};
+use report::{ColorChoice, Format, Reporter, SortOrder, TimeFormat, TimeStyle, Verbosity};
+use restore::{Command, ManifestWriter};
+use retention::Retention;
+use size::ByteSize;
+use systemd::Notifier;
+use top::Top;
+use watch::{WatchEvents, Watcher};
 
 #[derive(FromArgs)]
 /// Turn your filesystem into an LRU cache by running this program periodically. When run, if the
-/// filesystem for the provided path has fewer than --target-available-space free bytes, delete
-/// files in least-recently-accessed order until the target is reached.
+/// filesystem for a provided path has fewer than --target-available-space free bytes (or less
+/// than --target-available-percent of its capacity free, or fewer than --target-available-inodes
+/// free inodes), delete files beneath the path in least-recently-accessed order until the target
+/// is reached. At most one of the two space targets may be given, alongside or instead of the
+/// inode target; none has a default. With --purge-older-than, files older than it are deleted
+/// whatever the free space, and the targets become optional. Unless --yes is given, each plan is
+/// summarized and must be confirmed on stdin before anything is deleted, so runs from cron need it.
+///
+/// Exits with status 0 on success, 1 if any file could not be deleted, 2 if there weren't enough
+/// deletable files to reach the target, 3 if the arguments were invalid or the filesystem could
+/// not be queried, 4 if interrupted by SIGINT or SIGTERM, and 5 if nothing was deleted because of
+/// --max-delete-percent or because the plan wasn't confirmed. An interrupted run finishes the file
+/// it's deleting and prints a summary of the work done so far.
 struct Args {
     #[argh(switch)]
     /// if provided, do not remove any files and instead print file paths which would be removed if
     /// the program were to be run with the given arguments
     dry_run : bool,
 
+    #[argh(switch, short = 'y')]
+    /// delete without first printing a summary of the plan for each top-level directory and asking
+    /// for confirmation on stdin. runs whose stdin isn't a terminal delete nothing without it,
+    /// and exit with status 5, except with --interval or --watch, which never ask. this is a
+    /// breaking change: earlier versions never asked, so existing cron jobs and scripts must now
+    /// pass --yes
+    yes : bool,
+
+    #[argh(switch)]
+    /// delete nothing, and instead print how many files beneath the top-level directories fall
+    /// into each of several buckets by age and by size, and their total bytes. ages are measured
+    /// by --by, and sizes by --size-source. the filters on each file's path and owner apply, but
+    /// not those on its age or size. no targets are needed. only --format human and json are
+    /// supported
+    histogram : bool,
+
+    #[argh(option)]
+    /// delete nothing, and instead print the N largest files beneath the top-level directories
+    /// which could be deleted, largest first, i.e. those a run would consider deleting. sizes are
+    /// measured by --size-source. a file with several hard links, or a --group-by group, is listed
+    /// once by its first path with the space all of its paths take, and left out if any of them
+    /// can't be deleted. no targets are needed. only --format human and json are supported
+    top : Option<usize>,
+
+    #[argh(switch)]
+    /// read the files to consider from stdin, separated by NULs if there are any and by newlines
+    /// otherwise, e.g. from find -print0, rather than walking the top-level directories. each
+    /// directory considers the listed files beneath it. --include, --exclude, --ext, the regex,
+    /// size, age and ownership filters still apply, but --max-depth, --min-depth,
+    /// --respect-ignore, --one-file-system and --keep-marker don't. unreadable files are skipped.
+    /// nothing can be confirmed on stdin, so real runs need --yes
+    from_stdin : bool,
+
+    #[argh(option)]
+    /// write the files the run would delete to this file, one JSON object per line, for
+    /// --apply-plan to delete later. implies --dry-run
+    plan_out : Option<PathBuf>,
+
+    #[argh(option)]
+    /// delete exactly the files in a plan written by --plan-out, in order, skipping any whose
+    /// size or timestamp has changed since. no targets are needed, and the files beneath each
+    /// top-level directory are reclaimed as though by it. incompatible with --interval and --watch
+    apply_plan : Option<PathBuf>,
+
+    #[argh(option)]
+    /// once the run finishes, print the paths it would delete but which a plan written earlier by
+    /// --plan-out wouldn't, prefixed by +, and those the plan would delete but the run wouldn't,
+    /// prefixed by -, in order of path. planned files beneath other top-level directories are
+    /// ignored. the plan is read before --plan-out writes, so the two may name the same file to
+    /// compare each run with the last. implies --dry-run, and only supports --format human
+    diff_against : Option<PathBuf>,
+
     #[argh(option, short = 't')]
-    /// the minimum empty filesystem space in bytes to leave available for use
-    target_available_space : u64,
+    /// the minimum empty filesystem space to leave available for use, either in bytes or with a
+    /// unit suffix. as in coreutils, KB, MB, GB, TB and PB are decimal, e.g. 1.5GB is 1500000000
+    /// bytes, while KiB, MiB, GiB, TiB and PiB and the bare K, M, G, T and P are binary, e.g.
+    /// 1.5GiB or 1.5G is 1610612736 bytes
+    target_available_space : Option<ByteSize>,
+
+    #[argh(option, short = 'p')]
+    /// the minimum empty filesystem space to leave available for use, as a percentage (0-100) of
+    /// the filesystem's total capacity. mutually exclusive with --target-available-space
+    target_available_percent : Option<f64>,
+
+    #[argh(option)]
+    /// the minimum number of free inodes to leave available for use, for filesystems which run out
+    /// of inodes before they run out of space. only supported on unix, and ignored with a warning
+    /// on filesystems which don't limit inodes
+    target_available_inodes : Option<u64>,
+
+    #[argh(option)]
+    /// the most filesystem space to reclaim in a single run, either in bytes or with a unit suffix
+    /// such as 50GB. if the target would require deleting more than this, stop at the cap and leave
+    /// the remainder for subsequent runs
+    max_bytes_to_delete : Option<ByteSize>,
+
+    #[argh(option)]
+    /// the most files to delete in a single run. the least-recently-accessed files are deleted
+    /// first, and the remainder are left for subsequent runs
+    max_files_to_delete : Option<usize>,
+
+    #[argh(option)]
+    /// the most files to hold in memory while deciding which to delete. when more are needed to
+    /// reach the target, the lowest-ranked files held are deleted and the tree is walked again to
+    /// find the next lowest, so memory stays bounded at the cost of extra walks. files accessed
+    /// between walks may then be ranked by a stale timestamp or skipped until the next run
+    max_candidates : Option<usize>,
+
+    #[argh(option, short = 'o')]
+    /// only delete files that were last accessed at least --older-than minutes ago. defaults to 0,
+    /// which makes every file a candidate however recently it was accessed
+    older_than : Option<i64>,
+
+    #[argh(option)]
+    /// delete every file last accessed more than this many minutes ago, however much space is
+    /// free, e.g. 43200 for a 30-day retention policy. any target is still met afterwards by
+    /// deleting further files as usual, and without a target only these files are deleted
+    purge_older_than : Option<i64>,
+
+    #[argh(option)]
+    /// treat files differently by how long ago they were last used, as a comma-separated list of
+    /// bands, youngest first: protect never deletes a band's files, pressure deletes them only as
+    /// needed to reach the targets and purge deletes every one of them. each band but the last
+    /// gives the age it reaches up to, such as 30s, 5m, 2h or 1d, and the last takes every older
+    /// file, e.g. protect:7d,pressure:30d,purge. purged files go first, then the least recently
+    /// used pressured files until the targets are met. replaces --older-than and
+    /// --purge-older-than, and without a target only purges
+    retention : Option<Retention>,
+
+    #[argh(option)]
+    /// which timestamp decides how recently a file was used: atime (last access, the default),
+    /// mtime (last modification), ctime (creation) or newest (the later of atime and mtime, for
+    /// files which may be either read or written without the other changing). ctime falls back to
+    /// mtime on filesystems which don't record creation times, and newest uses whichever of the two
+    /// is available; otherwise files for which the platform doesn't record the chosen timestamp are
+    /// never deleted. windows may not update access times (see fsutil
+    /// behavior query disablelastaccess), in which case mtime is the better choice there
+    by : Option<Timestamp>,
+
+    #[argh(option)]
+    /// what to do with files whose access time is unavailable (or reported as the unix epoch, as
+    /// some filesystems do) when ranking --by atime: skip (never delete them, the default), mtime
+    /// (rank them by modification time instead) or error (abort the run)
+    on_missing_atime : Option<MissingAtime>,
+
+    #[argh(option)]
+    /// what to do with files whose timestamps are in the future, e.g. through clock skew or a
+    /// restore from backup, which would otherwise rank them as the most recently used: warn (rank
+    /// them as they are, warning how many there were, the default), clamp (rank them as used now)
+    /// or skip (never delete them)
+    future_time : Option<FutureTime>,
+
+    #[argh(option)]
+    /// the order in which to delete files: lru (least-recently-used first, the default),
+    /// size-weighted (highest size in bytes multiplied by seconds since last use first) or lfu
+    /// (least-frequently-used first, as recorded in a .lru-index.json file at the top-level
+    /// directory which is updated on every run)
+    strategy : Option<Strategy>,
+
+    #[argh(option)]
+    /// how to measure the space a file occupies: blocks (the blocks allocated to it, the default)
+    /// or length (its logical length). blocks reflects what deleting a sparse or compressed file
+    /// actually frees, but is only supported on unix and falls back to length elsewhere
+    size_source : Option<SizeSource>,
+
+    #[argh(option)]
+    /// never delete files whose logical length is less than this, such as 1MB, since deleting
+    /// many tiny files frees little space for the time and effort. defaults to 0
+    min_file_size : Option<ByteSize>,
+
+    #[argh(option)]
+    /// never delete files whose logical length is more than this, such as 20GB, to protect large
+    /// files which are expensive to regenerate. together with --min-file-size, this defines a
+    /// window of deletable sizes
+    max_file_size : Option<ByteSize>,
+
+    #[argh(option)]
+    /// never delete files whose path relative to the top-level directory matches this glob. `*`
+    /// and `?` don't match `/`, while `**` matches any number of directories. may be given
+    /// multiple times
+    exclude : Vec<String>,
+
+    #[argh(switch)]
+    /// don't read the global ignore files, /etc/lru/ignore and then ~/.config/lru/ignore (or
+    /// $XDG_CONFIG_HOME/lru/ignore), whose globs, one per line, are otherwise excluded alongside
+    /// those given by --exclude. on Windows they're lru\ignore in %ProgramData% and %APPDATA%.
+    /// blank lines and those starting with # are passed over, and a line !glob in the user's file
+    /// withdraws a glob the system's file excludes
+    no_global_ignore : bool,
+
+    #[argh(option)]
+    /// only delete files whose path relative to the top-level directory matches at least one of
+    /// these globs, using the same syntax as --exclude. a file matching both an --include and an
+    /// --exclude is never deleted. may be given multiple times
+    include : Vec<String>,
+
+    #[argh(option)]
+    /// never delete files whose path relative to the top-level directory matches this regular
+    /// expression, such as '^sess-[0-9a-f]{32}$'. unanchored, so it may match anywhere in the
+    /// path. may be given multiple times
+    exclude_regex : Vec<String>,
+
+    #[argh(option)]
+    /// only delete files whose path relative to the top-level directory matches at least one of
+    /// these regular expressions, like --exclude-regex. a file matching both is never deleted.
+    /// may be given multiple times
+    match_regex : Vec<String>,
+
+    #[argh(option)]
+    /// only delete files with this extension, such as tmp, compared case-insensitively. may be
+    /// given multiple times, and files matched by --exclude are still never deleted
+    ext : Vec<String>,
+
+    #[argh(option)]
+    /// only delete files owned by this user, given as a name or a numeric uid, leaving everyone
+    /// else's files in place. unix only; elsewhere it's ignored with a warning
+    user : Option<String>,
+
+    #[argh(option)]
+    /// never delete anything in or beneath a directory containing a file with this name, so that
+    /// users can protect their own directories. defaults to .lrukeep; an empty name turns this off
+    keep_marker : Option<String>,
+
+    #[argh(option)]
+    /// treat files whose relative paths yield the same key from this regular expression, its
+    /// first capture group or else its whole match, as a group to delete whole: ranked by its least
+    /// recently used file, and kept whole if any of its files can't be deleted. the last group
+    /// deleted may overshoot the target. files it doesn't match are deleted singly
+    group_by : Option<String>,
+
+    #[argh(switch)]
+    /// never delete hidden files: those whose names begin with a dot or, on Windows, which have
+    /// the hidden attribute. only a file's own name counts, so files inside hidden directories
+    /// are still deleted
+    skip_hidden : bool,
+
+    #[argh(switch)]
+    /// never delete files matched by .gitignore or .ignore files found while traversing the
+    /// top-level directory
+    respect_ignore : bool,
+
+    #[argh(switch)]
+    /// follow symlinks while traversing the top-level directory, deleting the files they point to.
+    /// --exclude and the other filters match the link's own path, by which it's also reported.
+    /// each file is only considered once however many links lead to it. note that link targets may
+    /// live on other filesystems, whose free space doesn't count towards the target
+    follow_symlinks : bool,
+
+    #[argh(switch, short = 'x')]
+    /// never cross into other filesystems mounted beneath the top-level directory, like find
+    /// -xdev. only supported on unix
+    one_file_system : bool,
+
+    #[argh(switch)]
+    /// speed up walking huge caches whose file and directory names sort in order of the timestamp
+    /// chosen by --by, oldest first, e.g. because they're named by when they were written. each
+    /// directory is walked in order of name, and the rest of it is skipped without being stat'd
+    /// once the plan holds enough and one of its files is already too new to delete, so files
+    /// which break the assumption may be missed. only supported with --strategy lru, and not with
+    /// --keep-newest, --keep-per-dir or --purge-older-than
+    assume_sorted : bool,
+
+    #[argh(option)]
+    /// speed up repeated runs over huge caches by keeping a walk index in this file between runs.
+    /// directories whose modification and change times are the same as the index recorded aren't
+    /// read again, and files the index already knows aren't stat'd again. since reading or
+    /// writing a file doesn't change its directory, every file is stat'd again before it's
+    /// deleted and is left for another pass if it has changed, e.g. because it's been used since,
+    /// but files whose timestamps have been set back are only noticed once their directory
+    /// changes. only effective on unix, on filesystems which update directory timestamps, and not
+    /// supported with --respect-ignore, --follow-symlinks, --from-stdin, --assume-sorted or
+    /// --strategy lfu
+    index : Option<PathBuf>,
+
+    #[argh(switch)]
+    /// keep memory use constant however many files there are, by walking the tree twice: once to
+    /// find how old files must be to go, from a histogram of their ages, and once to delete them.
+    /// files of about that age are deleted in the order they're found rather than strictly oldest
+    /// first. only supported with --strategy lru, and not with --keep-newest, --keep-per-dir,
+    /// --max-candidates, --purge-older-than, --retention, --group-by, --index or --assume-sorted
+    low_memory : bool,
+
+    #[argh(option)]
+    /// only consider files at most this many levels below the top-level directory. the top-level
+    /// directory itself is at depth 0, so files directly inside it are at depth 1
+    max_depth : Option<usize>,
+
+    #[argh(option)]
+    /// only consider files at least this many levels below the top-level directory, using the
+    /// same depths as --max-depth. defaults to 0, which considers every file
+    min_depth : Option<usize>,
+
+    #[argh(switch)]
+    /// after deleting files, remove any directories beneath the top-level directory which were
+    /// left empty. directories which were already empty, and the top-level directory itself, are
+    /// never removed
+    prune_empty_dirs : bool,
+
+    #[argh(switch)]
+    /// exit with an error before deleting anything, rather than warning, if the target exceeds the
+    /// filesystem's capacity or reaching it means deleting more than 90% of the candidates, both
+    /// of which suggest a misconfigured target
+    strict : bool,
+
+    #[argh(option)]
+    /// delete nothing, and instead print the files which would have been deleted, if reaching the
+    /// target means deleting more than P% of the candidates beneath a top-level directory, by
+    /// count or by size. files purged by --purge-older-than don't count. dry runs are never
+    /// aborted. defaults to 50; pass 100 to disable
+    max_delete_percent : Option<f64>,
+
+    #[argh(option)]
+    /// never delete the N most recently accessed files beneath each top-level directory, ranked
+    /// by the timestamp chosen with --by, even if that means the target can't be met
+    keep_newest : Option<usize>,
+
+    #[argh(option)]
+    /// never delete the K most recently accessed files in each directory, grouped by their
+    /// immediate parent, so that no directory is emptied entirely. files protected by either this
+    /// or --keep-newest are kept
+    keep_per_dir : Option<usize>,
+
+    #[argh(switch)]
+    /// never delete files which some process has open, since their space isn't freed until every
+    /// process closes them. Linux only, found by scanning /proc/*/fd when the run starts. this is
+    /// best-effort and racy by nature: the files of processes we may not inspect are missed, and
+    /// files may be opened after the scan
+    skip_open : bool,
+
+    #[argh(switch)]
+    /// never delete files we lack permission to write, or, when running as root, that their owner
+    /// lacks permission to write, so that they can be pinned with chmod. this is the default, and
+    /// the switch, e.g. in a config file, only stops --force from overriding it
+    skip_readonly : bool,
+
+    #[argh(switch)]
+    /// delete read-only files as well, since deleting only takes permission to write a file's
+    /// directory, unless --skip-readonly is given. this is a breaking change: earlier versions
+    /// deleted them unasked, so runs which relied on that must now pass --force
+    force : bool,
+
+    #[argh(switch)]
+    /// move files to the system trash instead of deleting them. no space is reclaimed until the
+    /// trash is emptied, so the target may not be reached
+    trash : bool,
+
+    #[argh(option)]
+    /// move files beneath this directory instead of deleting them, preserving their paths relative
+    /// to the top-level directory. moving files within the same filesystem frees no space
+    move_to : Option<PathBuf>,
+
+    #[argh(switch)]
+    /// overwrite files with zeros before deleting them, so that their contents can't be trivially
+    /// recovered. much slower than deleting, and ineffective on copy-on-write filesystems and SSDs
+    shred : bool,
+
+    #[argh(option)]
+    /// how many times --shred overwrites each file. defaults to 1, and implies --shred
+    shred_passes : Option<u32>,
+
+    #[argh(option)]
+    /// how many times to retry deleting a file which fails with a transient error, such as the
+    /// file being busy, waiting twice as long before each retry. permanent errors such as
+    /// permission being denied are never retried. defaults to 3
+    delete_retries : Option<u32>,
+
+    #[argh(option)]
+    /// delete up to N files at once on as many threads, which speeds up deletion on network or
+    /// slow filesystems. files are still chosen in order and limited by --rate, but may finish
+    /// out of order. defaults to 1
+    delete_threads : Option<usize>,
+
+    #[argh(option)]
+    /// while deleting, re-query free space after every N files, stopping as soon as the target is
+    /// reached and walking again for more files if space was consumed meanwhile, rather than
+    /// trusting the plan made up front. ignored by dry runs
+    recheck_interval : Option<usize>,
+
+    #[argh(option)]
+    /// delete at most this many files per second, sleeping between deletions to hold the rate, so
+    /// that purging a fast disk doesn't starve other workloads of IO. this lengthens the run in
+    /// proportion to the files deleted. dry runs aren't throttled
+    rate : Option<f64>,
+
+    #[argh(option)]
+    /// delete at most this many bytes per second, such as 100MB, throttling like --rate. both may
+    /// be given, in which case the slower of the two holds
+    rate_bytes : Option<ByteSize>,
+
+    #[argh(option)]
+    /// the strftime format for timestamps in human-readable output, such as %Y-%m-%dT%H:%M:%S
+    /// for ISO 8601. defaults to %m/%d/%Y %T
+    time_format : Option<TimeFormat>,
+
+    #[argh(switch)]
+    /// print timestamps in UTC rather than the local timezone, in every output format. this only
+    /// changes how they're printed, not which files are deleted
+    utc : bool,
+
+    #[argh(option)]
+    /// the order to print the files deleted, or which would be, in: age, the order they're deleted
+    /// in, least recently used first; size, largest first; or path. only age prints each file as
+    /// it's handled, and the order of deletion and of --plan-out is always age. defaults to age
+    sort : Option<SortOrder>,
+
+    #[argh(switch)]
+    /// draw a progress bar on stderr while deleting, tracking the bytes freed towards the plan and
+    /// the files handled. only drawn for human-readable output to a terminal, and never with
+    /// --quiet
+    progress : bool,
+
+    #[argh(switch)]
+    /// break down the files deleted and bytes freed by extension, lowercased, in the human and
+    /// json summaries. files without an extension are counted under "(no extension)"
+    breakdown : bool,
+
+    #[argh(option)]
+    /// how to print results: human (the default) prints lines as files are handled, json prints a
+    /// single object describing the whole run once it finishes, ndjson prints one object per file as
+    /// it's handled and csv prints a path,size_bytes,accessed header followed by a row per file
+    format : Option<Format>,
+
+    #[argh(switch, short = '0')]
+    /// print each file's path followed by a NUL byte and nothing else, so that paths containing
+    /// spaces or newlines survive being piped to xargs -0, e.g. with --dry-run. incompatible with
+    /// --format
+    print0 : bool,
+
+    #[argh(option)]
+    /// whether to colorize human output: auto (the default) colorizes when stdout is a terminal
+    /// and the NO_COLOR environment variable isn't set, always or never
+    color : Option<ColorChoice>,
+
+    #[argh(option)]
+    /// what to do when another run is already reclaiming from a top-level directory, as detected
+    /// by an advisory lock on the directory: skip (leave the directory to the other run, the
+    /// default) or wait (wait for the other run to finish)
+    if_locked : Option<IfLocked>,
+
+    #[argh(option)]
+    /// after the run, write metrics about it to this file in the Prometheus text format, for
+    /// node_exporter's textfile collector. the file is replaced atomically, so scrapes never see a
+    /// partial file
+    metrics_file : Option<PathBuf>,
+
+    #[argh(switch)]
+    /// send an entry to the systemd journal for every file disposed of and for the outcome of each
+    /// top-level directory, with fields such as LRU_PATH, LRU_SIZE and LRU_BYTES_FREED for
+    /// journalctl to filter by. linux only; elsewhere it's ignored with a warning
+    journald : bool,
+
+    #[argh(option)]
+    /// append a line to this file for every file deleted, trashed, moved or shredded, giving the
+    /// time, an id for the run, the action, the bytes freed and the path, whatever --verbose and
+    /// --format say. each line is written as soon as the file is handled. dry runs write nothing
+    log_file : Option<PathBuf>,
+
+    #[argh(option)]
+    /// once the --log-file would grow beyond this size, such as 10MB, move it aside to the same
+    /// name with .1 appended, replacing any earlier one, and start a new log
+    log_max_size : Option<ByteSize>,
+
+    #[argh(option)]
+    /// instead of running once, run again and again forever, waiting this long after each run
+    /// finishes, such as 30s, 5m, 2h or 1d; a bare number is seconds. a run which fails is logged
+    /// and the next goes ahead as usual. SIGINT or SIGTERM between runs exits with status 0,
+    /// while during a run it stops the run as usual. when started by systemd as a Type=notify
+    /// service, tells it once we're ready and pings its watchdog between runs, so WatchdogSec must
+    /// be longer than the longest run
+    interval : Option<Interval>,
+
+    #[argh(option)]
+    /// with --interval, or --watch once it falls back to running regularly, adapt the wait between
+    /// runs to how close free space is to the target instead of always waiting --interval, from
+    /// this long when the last run left free space at or below the target up to --max-interval
+    /// when it left at least twice the target free. the wait grows exponentially in between, and
+    /// is judged by whichever top-level directory is closest to its target. --interval is still
+    /// waited after runs which fail or have no target. requires --max-interval
+    min_interval : Option<Interval>,
+
+    #[argh(option)]
+    /// the longest wait between runs with --min-interval
+    max_interval : Option<Interval>,
+
+    #[argh(switch)]
+    /// instead of running once, run again whenever files beneath the top-level directories are
+    /// created or written to, once the burst of changes has quietened down for --watch-debounce.
+    /// unlike --interval, quiet caches aren't walked again and again, while a burst of writes
+    /// filling the disk is reacted to quickly. only Linux is watched, through inotify: elsewhere,
+    /// e.g. on macOS and the BSDs, or where inotify watches (one per directory) run out, it falls
+    /// back to running every --interval, or every minute. otherwise behaves as --interval does
+    watch : bool,
+
+    #[argh(option)]
+    /// with --watch, how long to wait after a change for more before running, such as 5s or 1m.
+    /// a run still goes ahead once changes have kept coming for ten times as long. defaults to 5s
+    watch_debounce : Option<Interval>,
 
-    #[argh(option, short = 'o', default = "0")]
-    /// only delete files that were last accessed more than --older-than minutes ago
-    older_than : i64,
+    #[argh(option)]
+    /// with --watch, the comma-separated changes to run after: create, for files and directories
+    /// created or moved in, write, for files written to, and delete, for files and directories
+    /// deleted or moved out. defaults to create,write
+    watch_events : Option<WatchEvents>,
+
+    #[argh(option)]
+    /// load options from this TOML file (or JSON, if its name ends in .json), whose keys are the
+    /// names of these options with underscores in place of dashes, e.g. target_available_space =
+    /// "50GB". options given on the command line take precedence over the file, except that
+    /// --exclude and --include patterns from both apply, and switches are on if either turns them
+    /// on. unknown keys are an error
+    config : Option<PathBuf>,
 
     #[argh(positional)]
-    /// the top-level directory at which to recursively reclaim files when the filesystem capacity
-    /// exceeds the target
-    path : PathBuf,
+    /// the top-level directories at which to recursively reclaim files when the filesystem
+    /// capacity exceeds the target, handled one after another. space freed beneath one directory
+    /// counts towards the target of later directories on the same filesystem. at least one is
+    /// required unless given by the paths key of --config, which they replace
+    paths : Vec<PathBuf>,
 
     #[argh(switch, short = 'v')]
-    /// enable verbose logging
-    verbose : bool,
+    /// enable verbose logging to stderr, or debug logging if given twice. the RUST_LOG environment
+    /// variable overrides the level
+    verbose : u8,
+
+    #[argh(switch, short = 'q')]
+    /// print nothing but errors, not even the files removed by --dry-run or the summary line
+    /// printed to stderr after every run. mutually exclusive with --verbose
+    quiet : bool,
+
+    #[argh(subcommand)]
+    command : Option<Command>,
+}
+
+/// The exit status when at least one file could not be deleted.
+const EXIT_DELETION_FAILED : i32 = 1;
+/// The exit status when there weren't enough deletable files to reach the target.
+const EXIT_TARGET_UNMET : i32 = 2;
+/// The exit status when the arguments were invalid or the filesystem could not be queried.
+const EXIT_USAGE_ERROR : i32 = 3;
+/// The exit status when the run was interrupted by a signal.
+const EXIT_INTERRUPTED : i32 = 4;
+/// The exit status when the run deleted nothing because of --max-delete-percent or because the
+/// plan wasn't confirmed, including when stdin isn't a terminal to confirm it on.
+const EXIT_ABORTED : i32 = 5;
+
+/// How long --watch waits after a change for more before running, by default.
+const DEFAULT_WATCH_DEBOUNCE : time::Duration = time::Duration::from_secs(5);
+/// How often --watch runs when it can't watch for changes and no --interval is given.
+const DEFAULT_WATCH_FALLBACK_INTERVAL : time::Duration = time::Duration::from_secs(60);
+
+/// Print an error about the provided arguments and exit with a nonzero status.
+fn exit_with_usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_USAGE_ERROR);
+}
+
+/// Parse `Args` from the command line like `argh::from_env`, but exit with our own status for
+/// invalid arguments.
+fn parse_args() -> Args {
+    let strings : Vec<String> = std::env::args_os()
+        .map(|s| s.into_string())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|arg| exit_with_usage_error(&format!("Invalid utf8: {}", arg.to_string_lossy())));
+    let command = strings.first()
+        .and_then(|command| Path::new(command).file_name())
+        .and_then(|command| command.to_str())
+        .unwrap_or("lru");
+    let strs : Vec<&str> = strings.iter().skip(1).map(|s| s.as_str()).collect();
+    Args::from_args(&[command], &strs).unwrap_or_else(|early_exit| match early_exit.status {
+        Ok(()) => {
+            println!("{}", early_exit.output);
+            std::process::exit(0);
+        }
+        Err(()) => exit_with_usage_error(&format!("{}\nRun {} --help for more information.", early_exit.output, command)),
+    })
 }
 
 fn main() {
-    let args: Args = argh::from_env();
-    let current_available_space = fs2::available_space(&args.path).unwrap();
-    let older_than_time = Local::now() - Duration::minutes(args.older_than);
-
-    let mut n_bytes_deleted = 0;
-    if current_available_space < args.target_available_space {
-        let mut files_to_delete = BinaryHeap::<FileInfo>::new();
-        let mut aggregate_heap_file_size = 0;
-        let max_n_bytes_to_delete = args.target_available_space - current_available_space;
-
-        for entry in WalkDir::new(&args.path)
-            .into_iter()
-            .filter_map(|entry| entry.ok()) {
-            if let Ok(metadata) = entry.metadata() {
-                if metadata.is_file() {
-                    let accessed = metadata.accessed().unwrap().into();
-                    if accessed < older_than_time && (aggregate_heap_file_size < max_n_bytes_to_delete || accessed <= files_to_delete.peek().unwrap().accessed) {
-                        // NOTE: if our aggregate heap file size is above capacity, we _must_ have something
-                        // in the heap already
-                        let file = FileInfo { accessed, size : metadata.len(), path : entry.into_path() };
-                        aggregate_heap_file_size += file.size;
-                        files_to_delete.push(file);
-
-                        // NOTE: we should always have at least one file on the heap at this point
-                        while aggregate_heap_file_size - files_to_delete.peek().unwrap().size > max_n_bytes_to_delete {
-                            // forget about any newer files that we no longer need to delete now that we have
-                            // pushed an older file onto the heap
-                            aggregate_heap_file_size -= files_to_delete.pop().unwrap().size;
-                        }
-                    } else {
-                        // if our file is newer than the newest thing already on the heap, and our heap
-                        // is already at capacity, there's no sense in pushing the file onto the heap
-                        // only to remove it immediately afterward
-                    }
+    let mut args = parse_args();
+    if let Some(Command::Restore(restore_args)) = &args.command {
+        init_logging(Verbosity::Normal);
+        match restore::run(restore_args) {
+            Ok(status) => std::process::exit(status),
+            Err(err) => {
+                eprintln!("error: {:#}", err);
+                std::process::exit(EXIT_USAGE_ERROR);
+            }
+        }
+    }
+    if let Some(config) = args.config.clone() {
+        FileConfig::load(&config)
+            .and_then(|file_config| {
+                file_config.merge_into(&mut args).with_context(|| format!("invalid config file {}", config.display()))
+            })
+            .unwrap_or_else(|err| exit_with_usage_error(&format!("error: {:#}", err)));
+    }
+    if args.plan_out.is_some() {
+        if args.apply_plan.is_some() {
+            exit_with_usage_error("--plan-out and --apply-plan are mutually exclusive");
+        }
+        args.dry_run = true;
+    }
+    if args.diff_against.is_some() {
+        if args.apply_plan.is_some() || args.histogram || args.top.is_some() || args.print0 || !matches!(args.format, None | Some(Format::Human)) {
+            exit_with_usage_error("--diff-against can't be combined with --apply-plan, --histogram, --top, --print0 or --format other than human");
+        }
+        args.dry_run = true;
+    }
+    if args.apply_plan.is_some() && (args.interval.is_some() || args.watch) {
+        exit_with_usage_error("--apply-plan can't be combined with --interval or --watch");
+    }
+    if args.histogram {
+        if args.interval.is_some() || args.watch || args.apply_plan.is_some() || args.plan_out.is_some() || args.print0 {
+            exit_with_usage_error("--histogram can't be combined with --interval, --watch, --apply-plan, --plan-out or --print0");
+        }
+        if !matches!(args.format, None | Some(Format::Human) | Some(Format::Json)) {
+            exit_with_usage_error("--histogram only supports --format human and json");
+        }
+    }
+    if let Some(top) = args.top {
+        if top == 0 {
+            exit_with_usage_error("--top must be greater than 0");
+        }
+        if args.histogram || args.interval.is_some() || args.watch || args.apply_plan.is_some() || args.plan_out.is_some() || args.print0 {
+            exit_with_usage_error("--top can't be combined with --histogram, --interval, --watch, --apply-plan, --plan-out or --print0");
+        }
+        if !matches!(args.format, None | Some(Format::Human) | Some(Format::Json)) {
+            exit_with_usage_error("--top only supports --format human and json");
+        }
+    }
+    if args.print0 && args.format.is_some() {
+        exit_with_usage_error("--print0 and --format are mutually exclusive");
+    }
+    if args.from_stdin && (args.interval.is_some() || args.watch) {
+        exit_with_usage_error("--from-stdin can't be combined with --interval or --watch");
+    }
+    if !args.watch && (args.watch_debounce.is_some() || args.watch_events.is_some()) {
+        exit_with_usage_error("--watch-debounce and --watch-events only apply with --watch");
+    }
+    match (args.min_interval, args.max_interval) {
+        (None, None) => {}
+        (Some(min_interval), Some(max_interval)) => {
+            if args.interval.is_none() && !args.watch {
+                exit_with_usage_error("--min-interval and --max-interval only apply with --interval or --watch");
+            }
+            if min_interval > max_interval {
+                exit_with_usage_error("--min-interval must not be longer than --max-interval");
+            }
+        }
+        _ => exit_with_usage_error("--min-interval and --max-interval must be given together"),
+    }
+    if args.from_stdin && args.apply_plan.is_some() {
+        exit_with_usage_error("--from-stdin and --apply-plan are mutually exclusive");
+    }
+    // applying a plan checks each path against the metadata planned for it, which a group's totals
+    // wouldn't match
+    if args.group_by.is_some() && (args.plan_out.is_some() || args.apply_plan.is_some()) {
+        exit_with_usage_error("--group-by can't be combined with --plan-out or --apply-plan");
+    }
+    match run(&args) {
+        Ok(status) => std::process::exit(status),
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            std::process::exit(EXIT_USAGE_ERROR);
+        }
+    }
+}
+
+/// Route `log` output to stderr at the level implied by `verbosity`, unless overridden by RUST_LOG.
+fn init_logging(verbosity: Verbosity) {
+    let level = match verbosity {
+        Verbosity::Quiet => LevelFilter::Error,
+        Verbosity::Normal => LevelFilter::Warn,
+        Verbosity::Verbose => LevelFilter::Info,
+        Verbosity::Debug => LevelFilter::Debug,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            // informational messages are the verbose output, and so are printed as-is
+            Level::Info => writeln!(buf, "{}", record.args()),
+            Level::Warn => writeln!(buf, "warning: {}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// Resolve a user given to --user, as either a name or a numeric uid, to a uid.
+fn resolve_user(user: &str) -> anyhow::Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    #[cfg(unix)]
+    {
+        match nix::unistd::User::from_name(user).with_context(|| format!("failed to look up user {}", user))? {
+            Some(user) => Ok(user.uid.as_raw()),
+            None => anyhow::bail!("no such user {:?}", user),
+        }
+    }
+    #[cfg(not(unix))]
+    anyhow::bail!("user names can't be resolved on this platform, so give a uid to --user")
+}
+
+/// Read the paths given to --from-stdin, separated by NULs if there are any and by newlines
+/// otherwise, in the order given and without duplicates.
+fn read_listed_files() -> anyhow::Result<Vec<PathBuf>> {
+    let mut input = Vec::new();
+    io::stdin().read_to_end(&mut input).context("failed to read paths from stdin")?;
+    let separator = if input.contains(&0) { 0 } else { b'\n' };
+    let mut seen = HashSet::new();
+    Ok(input.split(|byte| *byte == separator)
+        .filter(|path| !path.is_empty())
+        .map(|path| {
+            #[cfg(unix)]
+            let path = PathBuf::from(std::ffi::OsStr::from_bytes(path));
+            #[cfg(not(unix))]
+            let path = PathBuf::from(String::from_utf8_lossy(path).trim_end_matches('\r'));
+            path
+        })
+        .filter(|path| seen.insert(path.clone()))
+        .collect())
+}
+
+/// Ask on stderr whether to carry out `plan` for `path`, reading the answer from stdin. Nobody can
+/// answer unless stdin is a terminal, so the plan is then declined.
+fn confirm(path: &Path, plan: &Plan) -> bool {
+    if !io::stdin().is_terminal() {
+        log::warn!(
+            "not deleting {} files from {} without confirmation, since stdin isn't a terminal; pass --yes to delete without asking, as runs from cron and scripts now must",
+            plan.files, path.display(),
+        );
+        return false;
+    }
+    eprint!("Delete {} files freeing {} bytes from {}? [y/N] ", plan.files, plan.bytes, path.display());
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Print the distribution of the ages and sizes of the files beneath every top-level directory,
+/// for --histogram, returning the status to exit with.
+fn print_histogram(args: &Args, config: &ReclaimConfig, interrupt: &AtomicBool) -> anyhow::Result<i32> {
+    let mut histogram = Histogram::new();
+    for path in &args.paths {
+        let skipped = lru::survey(&ReclaimConfig { path : path.clone(), ..config.clone() }, |file| histogram.add(file))?;
+        let n_skipped : usize = skipped.values().sum();
+        if n_skipped > 0 {
+            log::warn!("skipped {} entries beneath {} which could not be read", n_skipped, path.display());
+        }
+        if interrupt.load(Ordering::SeqCst) {
+            return Ok(EXIT_INTERRUPTED);
+        }
+    }
+    match args.format {
+        Some(Format::Json) => histogram.print_json(),
+        _ => histogram.print_human(),
+    }
+    Ok(0)
+}
+
+/// Print the `n` largest files which could be deleted beneath every top-level directory, for
+/// --top, returning the status to exit with.
+fn print_top(args: &Args, config: &ReclaimConfig, n: usize, interrupt: &AtomicBool) -> anyhow::Result<i32> {
+    let mut top = Top::new(n);
+    for path in &args.paths {
+        let largest = lru::largest(&ReclaimConfig { path : path.clone(), ..config.clone() }, n)?;
+        let n_skipped : usize = largest.skipped.values().sum();
+        if n_skipped > 0 {
+            log::warn!("skipped {} entries beneath {} which could not be read", n_skipped, path.display());
+        }
+        top.add(largest);
+        if interrupt.load(Ordering::SeqCst) {
+            return Ok(EXIT_INTERRUPTED);
+        }
+    }
+    let time_style = TimeStyle { format : args.time_format.clone().unwrap_or_default(), utc : args.utc };
+    match args.format {
+        Some(Format::Json) => top.print_json(&time_style),
+        _ => top.print_human(&time_style),
+    }
+    Ok(0)
+}
+
+/// Reclaim space as described by `args`, returning the status to exit with.
+fn run(args: &Args) -> anyhow::Result<i32> {
+    let verbosity = match (args.quiet, args.verbose) {
+        (false, 0) => Verbosity::Normal,
+        (true, 0) => Verbosity::Quiet,
+        (false, 1) => Verbosity::Verbose,
+        (false, _) => Verbosity::Debug,
+        (true, _) => exit_with_usage_error("--quiet and --verbose are mutually exclusive"),
+    };
+    init_logging(verbosity);
+
+    let target = match (args.target_available_space, args.target_available_percent) {
+        (Some(target_available_space), None) => Some(Target::Bytes(target_available_space.0)),
+        (None, Some(percent)) => Some(Target::Percent(percent)),
+        (Some(_), Some(_)) => exit_with_usage_error("--target-available-space and --target-available-percent are mutually exclusive"),
+        (None, None) if args.target_available_inodes.is_some() || args.purge_older_than.is_some() || args.apply_plan.is_some() || args.histogram || args.top.is_some() => None,
+        (None, None) if args.retention.as_ref().is_some_and(|retention| retention.0.iter().any(|band| band.policy == RetentionPolicy::Purge)) => None,
+        (None, None) => exit_with_usage_error(
+            "one of --target-available-space, --target-available-percent, --target-available-inodes, --purge-older-than or a --retention band to purge is required",
+        ),
+    };
+    let shred = args.shred || args.shred_passes.is_some();
+    let disposal = match (args.trash, &args.move_to, shred) {
+        (false, None, false) => Disposal::Delete,
+        (true, None, false) => Disposal::Trash,
+        (false, Some(move_to), false) => Disposal::MoveTo(move_to.clone()),
+        (false, None, true) => Disposal::Shred(args.shred_passes.unwrap_or(1)),
+        _ => exit_with_usage_error("--trash, --move-to and --shred are mutually exclusive"),
+    };
+    #[cfg(not(target_os = "linux"))]
+    if args.journald {
+        log::warn!("the systemd journal is only available on Linux, so --journald has no effect");
+    }
+    if args.paths.is_empty() {
+        exit_with_usage_error("at least one top-level directory is required, either as an argument or in the --config file");
+    }
+    let interrupt = Arc::new(AtomicBool::new(false));
+    {
+        let interrupt = interrupt.clone();
+        ctrlc::set_handler(move || interrupt.store(true, Ordering::SeqCst)).context("failed to install a signal handler")?;
+    }
+    let exclude = if args.no_global_ignore {
+        args.exclude.clone()
+    } else {
+        global_ignore::merge(&global_ignore::layers(), args.exclude.clone())?
+    };
+    let config = ReclaimConfig {
+        path : PathBuf::new(),
+        target,
+        target_inodes : args.target_available_inodes,
+        dry_run : args.dry_run,
+        max_bytes_to_delete : args.max_bytes_to_delete.map(|cap| cap.0),
+        max_files_to_delete : args.max_files_to_delete,
+        max_candidates : args.max_candidates,
+        older_than : Duration::minutes(args.older_than.unwrap_or(0)),
+        purge_older_than : args.purge_older_than.map(Duration::minutes),
+        retention : args.retention.clone().map_or_else(Vec::new, |retention| retention.0),
+        by : args.by.unwrap_or(Timestamp::Accessed),
+        on_missing_atime : args.on_missing_atime.unwrap_or(MissingAtime::Skip),
+        future_time : args.future_time.unwrap_or(FutureTime::Warn),
+        strategy : args.strategy.unwrap_or(Strategy::Lru),
+        size_source : args.size_source.unwrap_or(SizeSource::Blocks),
+        min_file_size : args.min_file_size.map_or(0, |min_file_size| min_file_size.0),
+        max_file_size : args.max_file_size.map(|max_file_size| max_file_size.0),
+        // the manifest of trashed files is all that lets them be restored
+        exclude : exclude.into_iter().chain([restore::MANIFEST_FILE_NAME.to_string()]).collect(),
+        include : args.include.clone(),
+        exclude_regex : args.exclude_regex.clone(),
+        match_regex : args.match_regex.clone(),
+        extensions : args.ext.clone(),
+        owner : args.user.as_deref().map(resolve_user).transpose()?,
+        keep_marker : match args.keep_marker.as_deref() {
+            None => Some(lru::DEFAULT_KEEP_MARKER.to_string()),
+            Some("") => None,
+            Some(keep_marker) => Some(keep_marker.to_string()),
+        },
+        group_by : args.group_by.clone(),
+        skip_hidden : args.skip_hidden,
+        assume_sorted : args.assume_sorted,
+        listed_files : if args.from_stdin { Some(Arc::new(read_listed_files()?)) } else { None },
+        walk_index : args.index.clone(),
+        low_memory : args.low_memory,
+        respect_ignore : args.respect_ignore,
+        follow_symlinks : args.follow_symlinks,
+        one_file_system : args.one_file_system,
+        max_depth : args.max_depth,
+        min_depth : args.min_depth.unwrap_or(0),
+        prune_empty_dirs : args.prune_empty_dirs,
+        strict : args.strict,
+        max_delete_percent : Some(args.max_delete_percent.unwrap_or(50.0)),
+        keep_newest : args.keep_newest.unwrap_or(0),
+        keep_per_dir : args.keep_per_dir.unwrap_or(0),
+        skip_open : args.skip_open,
+        skip_readonly : args.skip_readonly || !args.force,
+        disposal,
+        delete_retries : args.delete_retries.unwrap_or(3),
+        delete_threads : args.delete_threads.unwrap_or(1),
+        recheck_interval : args.recheck_interval,
+        max_files_per_second : args.rate,
+        max_bytes_per_second : args.rate_bytes.map(|rate_bytes| rate_bytes.0),
+        on_planned : None,
+        // NOTE: a daemon has nobody to ask
+        confirm : if args.yes || args.interval.is_some() || args.watch { None } else { Some(Arc::new(confirm) as Confirm) },
+        clock : Arc::new(SystemClock),
+        filesystem : Arc::new(RealFileSystem),
+        interrupt : Some(interrupt.clone()),
+    };
+
+    if args.histogram {
+        return print_histogram(args, &config, &interrupt);
+    }
+    if let Some(top) = args.top {
+        return print_top(args, &config, top, &interrupt);
+    }
+    let interval = match args.interval {
+        Some(interval) => interval.0,
+        None if args.watch => DEFAULT_WATCH_FALLBACK_INTERVAL,
+        None => return reclaim_once(args, &config, verbosity, &interrupt).map(|(status, _)| status),
+    };
+    let mut watcher = if args.watch {
+        match Watcher::new(&args.paths, args.watch_events.unwrap_or_default()) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                log::warn!("can't watch for changes ({}), so running every {}s instead", err, interval.as_secs());
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let debounce = args.watch_debounce.map_or(DEFAULT_WATCH_DEBOUNCE, |debounce| debounce.0);
+    let mut notifier = Notifier::new();
+    notifier.ready();
+    let status = loop {
+        // a failed run may well be followed by one which succeeds, so its error is only logged
+        let headroom = match reclaim_once(args, &config, verbosity, &interrupt) {
+            Ok((EXIT_INTERRUPTED, _)) => break EXIT_INTERRUPTED,
+            Ok((_, headroom)) => headroom,
+            Err(err) => {
+                log::error!("{:#}", err);
+                None
+            }
+        };
+        if let Some(active_watcher) = watcher.as_mut() {
+            log::info!("Running again once files change");
+            match active_watcher.wait(debounce, &interrupt, || notifier.ping()) {
+                Ok(true) => continue,
+                Ok(false) => break 0,
+                Err(err) => {
+                    // the next run is due now, since a directory created since can't be watched
+                    log::warn!("can't watch for changes any longer ({}), so running every {}s instead", err, interval.as_secs());
+                    watcher = None;
+                    continue;
                 }
             }
         }
+        let interval = match (args.min_interval, args.max_interval, headroom) {
+            (Some(min_interval), Some(max_interval), Some(headroom)) => interval::adaptive(min_interval.0, max_interval.0, headroom),
+            _ => interval,
+        };
+        log::info!("Running again in {}s", interval.as_secs());
+        let next_run = Instant::now() + interval;
+        while Instant::now() < next_run && !interrupt.load(Ordering::SeqCst) {
+            notifier.ping();
+            thread::sleep((next_run - Instant::now()).min(time::Duration::from_millis(100)));
+        }
+        if interrupt.load(Ordering::SeqCst) {
+            break 0;
+        }
+    };
+    notifier.stopping();
+    Ok(status)
+}
+
+/// How much more free space than its target the top-level directory closest to its target has
+/// after a run, as a fraction of the target, or `None` if none has a target.
+fn headroom(reports: &[ReclaimReport]) -> Option<f64> {
+    reports.iter()
+        .filter_map(|report| {
+            let target = report.target?;
+            // dry runs free nothing, so are judged as though they had
+            let free_after = if report.target_met == Some(true) { report.free_after.max(target) } else { report.free_after };
+            Some(if target == 0 { f64::INFINITY } else { (free_after as f64 - target as f64) / target as f64 })
+        })
+        .reduce(f64::min)
+}
+
+/// Reclaim space beneath each top-level directory once, as described by `config`, returning the
+/// exit status for the run along with its `headroom`.
+fn reclaim_once(args: &Args, config: &ReclaimConfig, verbosity: Verbosity, interrupt: &AtomicBool) -> anyhow::Result<(i32, Option<f64>)> {
+    // a run which overlaps with another on the same directory would double-count the space
+    // reclaimed and race it to delete the same files. locks are taken in a consistent order so
+    // that waiting runs can't deadlock, and released when the process exits
+    let roots = args.paths.iter()
+        .map(|path| path.canonicalize().with_context(|| format!("failed to resolve {}", path.display())))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut unique_roots = roots.clone();
+    unique_roots.sort();
+    unique_roots.dedup();
+    let mut locks = Vec::new();
+    let mut locked_roots = HashSet::new();
+    for root in unique_roots {
+        match lock::acquire(&root, args.if_locked.unwrap_or(IfLocked::Skip), interrupt)? {
+            Some(lock) => locks.push(lock),
+            None if interrupt.load(Ordering::SeqCst) => {
+                eprintln!("interrupted while waiting for another run on {} to finish", root.display());
+                return Ok((EXIT_INTERRUPTED, None));
+            }
+            None => {
+                log::warn!("another run is already reclaiming from {}, so skipping it", root.display());
+                locked_roots.insert(root);
+            }
+        }
+    }
 
-        // re-query available space in case our capacity has been reduced since we started running the program
-        let n_bytes_to_delete = args.target_available_space as i64 - fs2::available_space(&args.path).unwrap() as i64;
-        if n_bytes_to_delete > 0 {
-            while let Some(file) = files_to_delete.peek() {
-                // if the space we need to reclaim has shrunk since we initially queried it (prior
-                // to filling up the heap), pop the most-recently-accessed elements until the heap
-                // reaches an appropriate size.
-                if aggregate_heap_file_size - file.size > n_bytes_to_delete as u64 {
-                    aggregate_heap_file_size -= files_to_delete.pop().unwrap().size;
-                    // we don't need to delete this file
-                } else {
+    let format = if args.print0 { Format::Print0 } else { args.format.unwrap_or(Format::Human) };
+    let color = args.color.unwrap_or(ColorChoice::Auto);
+    let time_style = TimeStyle { format : args.time_format.clone().unwrap_or_default(), utc : args.utc };
+    let mut reporter = Reporter::new(format, verbosity, args.dry_run, color.enabled(), time_style, args.progress, &config.disposal);
+    reporter.sort_by(args.sort.unwrap_or(SortOrder::Age));
+    if args.breakdown {
+        reporter.break_down();
+    }
+    let on_planned = reporter.on_planned().map(|on_planned| Arc::new(on_planned) as OnPlanned);
+    let configs : Vec<ReclaimConfig> = args.paths.iter()
+        .zip(&roots)
+        .filter(|(_, root)| !locked_roots.contains(*root))
+        .map(|(path, _)| ReclaimConfig { path : path.clone(), on_planned : on_planned.clone(), ..config.clone() })
+        .collect();
+    let mut audit_log = match &args.log_file {
+        Some(log_file) if !args.dry_run => {
+            Some(AuditLog::open(log_file, args.log_max_size.map(|log_max_size| log_max_size.0), config.disposal.action())?)
+        }
+        _ => None,
+    };
+    #[cfg(target_os = "linux")]
+    let mut journal = if args.journald {
+        Journal::connect(&config.disposal, args.dry_run)
+            .map_err(|err| log::warn!("failed to connect to the systemd journal, so nothing will be sent to it: {}", err))
+            .ok()
+    } else {
+        None
+    };
+    let mut manifest_writer = (matches!(config.disposal, Disposal::Trash) && !args.dry_run).then(|| ManifestWriter::new(&roots));
+    // NOTE: the plan to compare with may be the one about to be replaced by --plan-out
+    let previous_plan = args.diff_against.as_deref().map(plan::read).transpose()?;
+    let mut plan_writer = args.plan_out.as_deref().map(PlanWriter::create).transpose()?;
+    let mut plan_error = None;
+    let started = Instant::now();
+    let mut on_disposed = |file: &lru::FileInfo| {
+        reporter.disposed(file);
+        if let Some(log) = audit_log.as_mut() {
+            if let Err(err) = log.record(file) {
+                log::warn!("failed to write to log file, so no more files will be logged: {}", err);
+                audit_log = None;
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(Err(err)) = journal.as_ref().map(|journal| journal.disposed(file)) {
+            log::warn!("failed to send to the systemd journal, so nothing more will be sent to it: {}", err);
+            journal = None;
+        }
+        if let Some(writer) = manifest_writer.as_mut() {
+            if let Err(err) = writer.record(file) {
+                log::warn!("failed to write to the manifest of trashed files, so no more files can be restored: {}", err);
+                manifest_writer = None;
+            }
+        }
+        if let Some(writer) = plan_writer.as_mut() {
+            if let Err(err) = writer.record(file) {
+                plan_error.get_or_insert(err);
+            }
+        }
+    };
+    let reports = match &args.apply_plan {
+        Some(apply_plan) => {
+            let mut planned = plan::read(apply_plan)?;
+            let mut reports = Vec::new();
+            for config in &configs {
+                let (beneath, rest) = planned.into_iter().partition(|file| file.path.starts_with(&config.path));
+                planned = rest;
+                let report = lru::apply_plan(config, beneath, &mut on_disposed)?;
+                let interrupted = report.interrupted;
+                reports.push(report);
+                if interrupted {
                     break;
                 }
             }
-            while let Some(file) = files_to_delete.pop() {
-                if args.dry_run {
-                    n_bytes_deleted += file.size;
-                    println!("{} {}", file.accessed.format("%m/%d/%Y %T"), file.path.display());
-                } else if remove_file(&file.path).is_ok() && args.verbose {
-                    n_bytes_deleted += file.size;
-                    println!("Deleted {} {}", file.accessed.format("%m/%d/%Y %T"), file.path.display());
-                }
+            if !planned.is_empty() && !reports.iter().any(|report| report.interrupted) {
+                log::warn!("skipped {} planned file(s) which aren't beneath any of the paths reclaimed from", planned.len());
             }
+            reports
         }
+        None => lru::reclaim_all(&configs, on_disposed)?,
+    };
+    let duration = started.elapsed();
+    if let Some(err) = plan_error {
+        anyhow::bail!("failed to write plan file: {}", err);
     }
-
-    if args.verbose {
-        println!("Deleted {} bytes", n_bytes_deleted);
+    if let Some(plan_writer) = plan_writer {
+        plan_writer.finish()?;
     }
+    reporter.finish(&reports, duration);
+    if let (Some(previous_plan), Some(diff_against)) = (&previous_plan, &args.diff_against) {
+        PlanDiff::new(previous_plan, &reports).print(diff_against);
+    }
+    if let Some(metrics_file) = &args.metrics_file {
+        if let Err(err) = metrics::write(metrics_file, &reports, duration) {
+            log::warn!("{:#}", err);
+        }
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(journal) = &journal {
+        if let Err(err) = reports.iter().try_for_each(|report| journal.finished(report)) {
+            log::warn!("failed to send the outcome of the run to the systemd journal: {}", err);
+        }
+    }
+    drop(locks);
+
+    let status = if reports.iter().any(|report| report.interrupted) {
+        EXIT_INTERRUPTED
+    } else if reports.iter().any(|report| report.aborted) {
+        EXIT_ABORTED
+    } else if reports.iter().any(|report| !report.failed.is_empty()) {
+        EXIT_DELETION_FAILED
+    } else if reports.iter().any(|report| report.target_unmet) {
+        EXIT_TARGET_UNMET
+    } else {
+        0
+    };
+    Ok((status, headroom(&reports)))
 }
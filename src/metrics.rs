@@ -0,0 +1,54 @@
+use anyhow::Context;
+use lru::ReclaimReport;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+/// Escape `value` for use as a label value in the Prometheus text format.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Render the outcome of a run in the Prometheus text format, labelling each metric by top-level
+/// directory.
+fn render(reports: &[ReclaimReport], duration: Duration) -> String {
+    let mut metrics = String::new();
+    // directories for which a metric has no value are left out of it
+    let mut metric = |name: &str, help: &str, value: &dyn Fn(&ReclaimReport) -> Option<u64>| {
+        writeln!(metrics, "# HELP {} {}", name, help).unwrap();
+        writeln!(metrics, "# TYPE {} gauge", name).unwrap();
+        for report in reports {
+            if let Some(value) = value(report) {
+                writeln!(metrics, "{}{{path=\"{}\"}} {}", name, escape_label(&report.path.to_string_lossy()), value).unwrap();
+            }
+        }
+    };
+    metric("lru_bytes_freed_total", "Bytes freed by the last run.", &|report| Some(report.bytes_freed));
+    metric("lru_files_deleted_total", "Files deleted by the last run.", &|report| Some(report.deleted.len() as u64));
+    metric("lru_free_bytes_before", "Bytes available before the last run.", &|report| Some(report.free_before));
+    metric("lru_free_bytes", "Bytes available after the last run.", &|report| Some(report.free_after));
+    metric("lru_target_bytes", "Bytes the last run aimed to leave available.", &|report| report.target);
+    metric("lru_target_met", "Whether the bytes available after the last run met its target.", &|report| report.target_met.map(u64::from));
+    metric("lru_total_bytes", "Total capacity of the filesystem.", &|report| Some(fs2::total_space(&report.path).unwrap_or(0)));
+    metric("lru_candidates", "Files old enough to be deleted in the last run.", &|report| Some(report.candidates as u64));
+    writeln!(metrics, "# HELP lru_run_duration_seconds How long the last run took.").unwrap();
+    writeln!(metrics, "# TYPE lru_run_duration_seconds gauge").unwrap();
+    writeln!(metrics, "lru_run_duration_seconds {}", duration.as_secs_f64()).unwrap();
+    metrics
+}
+
+/// Write the metrics for a run to `path`, for node_exporter's textfile collector. The metrics are
+/// written to a temporary file beside `path` and renamed over it, so that a scrape never reads a
+/// partial file.
+pub fn write(path: &Path, reports: &[ReclaimReport], duration: Duration) -> anyhow::Result<()> {
+    let file_name = path.file_name().with_context(|| format!("{} is not a file", path.display()))?;
+    let temporary_path = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), process::id()));
+    fs::write(&temporary_path, render(reports, duration))
+        .with_context(|| format!("failed to write {}", temporary_path.display()))?;
+    fs::rename(&temporary_path, path).with_context(|| {
+        let _ = fs::remove_file(&temporary_path);
+        format!("failed to replace {}", path.display())
+    })
+}
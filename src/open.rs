@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+
+/// The device and inode numbers of every file which some process has open, found by following
+/// the descriptors listed under `/proc/*/fd`. This is best-effort: the descriptors of processes
+/// we aren't allowed to inspect are silently missed, and files may be opened or closed at any
+/// moment after the scan.
+#[cfg(target_os = "linux")]
+pub fn open_files() -> Option<HashSet<(u64, u64)>> {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let processes = match fs::read_dir("/proc") {
+        Ok(processes) => processes,
+        Err(err) => {
+            log::warn!("failed to list processes in /proc, so --skip-open has no effect: {}", err);
+            return None;
+        }
+    };
+    let mut open_files = HashSet::new();
+    for process in processes.flatten() {
+        if !process.file_name().to_string_lossy().bytes().all(|byte| byte.is_ascii_digit()) {
+            continue;
+        }
+        // the process may have exited, or belong to another user
+        let descriptors = match fs::read_dir(process.path().join("fd")) {
+            Ok(descriptors) => descriptors,
+            Err(_) => continue,
+        };
+        for descriptor in descriptors.flatten() {
+            // NOTE: stating the descriptor's symlink follows it to the open file itself, even if
+            // the file has since been renamed or unlinked
+            if let Ok(metadata) = fs::metadata(descriptor.path()) {
+                if metadata.is_file() {
+                    open_files.insert((metadata.dev(), metadata.ino()));
+                }
+            }
+        }
+    }
+    Some(open_files)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_files() -> Option<HashSet<(u64, u64)>> {
+    log::warn!("open files can only be found on Linux, so --skip-open has no effect");
+    None
+}
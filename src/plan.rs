@@ -0,0 +1,123 @@
+use anyhow::Context;
+use chrono::{DateTime, Local, SecondsFormat};
+use lru::{FileInfo, ReclaimReport};
+use std::collections::BTreeSet;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A file in a plan written by --plan-out, one JSON object per line, with just enough recorded to
+/// tell whether the file has changed by the time the plan is applied.
+#[derive(Serialize, Deserialize)]
+struct PlannedFile {
+    path : PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    links : Vec<PathBuf>,
+    size : u64,
+    length : u64,
+    /// the timestamp chosen by --by, to the nanosecond
+    accessed : String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inode : Option<(u64, u64)>,
+}
+
+/// Writes the files a dry run selects to a plan file, for --apply-plan to delete later.
+pub struct PlanWriter {
+    path : PathBuf,
+    writer : BufWriter<File>,
+}
+
+impl PlanWriter {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path).with_context(|| format!("failed to create plan file {}", path.display()))?;
+        Ok(PlanWriter { path : path.to_path_buf(), writer : BufWriter::new(file) })
+    }
+
+    pub fn record(&mut self, file: &FileInfo) -> io::Result<()> {
+        let planned = PlannedFile {
+            path : file.path.clone(),
+            links : file.links.clone(),
+            size : file.size,
+            length : file.length,
+            accessed : file.accessed.to_rfc3339_opts(SecondsFormat::Nanos, true),
+            inode : file.inode,
+        };
+        serde_json::to_writer(&mut self.writer, &planned)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.writer.flush().with_context(|| format!("failed to write plan file {}", self.path.display()))
+    }
+}
+
+/// Read the files planned in `path`, in the order they were planned.
+pub fn read(path: &Path) -> anyhow::Result<Vec<FileInfo>> {
+    let file = File::open(path).with_context(|| format!("failed to open plan file {}", path.display()))?;
+    let mut files = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("failed to read plan file {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let context = || format!("invalid plan file {} at line {}", path.display(), i + 1);
+        let planned : PlannedFile = serde_json::from_str(&line).with_context(context)?;
+        let accessed = DateTime::parse_from_rfc3339(&planned.accessed).with_context(context)?;
+        files.push(FileInfo {
+            // the plan already fixes the order, so the rank is never used
+            rank : (0, 0),
+            accessed : accessed.with_timezone(&Local),
+            frequency : 0,
+            size : planned.size,
+            length : planned.length,
+            inode : planned.inode,
+            path : planned.path,
+            links : planned.links,
+        });
+    }
+    Ok(files)
+}
+
+/// How the files a run planned differ from those in an earlier plan, for --diff-against, each in
+/// order of path.
+pub struct PlanDiff {
+    /// the paths planned now which weren't before
+    pub added : Vec<PathBuf>,
+    /// the paths planned before which aren't now
+    pub removed : Vec<PathBuf>,
+}
+
+impl PlanDiff {
+    /// Compare the paths `reports` planned to delete with those in `previous`. Previously planned
+    /// paths which aren't beneath any of the reports' top-level directories weren't considered by
+    /// the run, so are left out rather than counted as removed.
+    pub fn new(previous: &[FileInfo], reports: &[ReclaimReport]) -> Self {
+        let previous : BTreeSet<&PathBuf> = previous.iter()
+            .flat_map(FileInfo::paths)
+            .filter(|path| reports.iter().any(|report| path.starts_with(&report.path)))
+            .collect();
+        let current : BTreeSet<&PathBuf> = reports.iter().flat_map(|report| &report.deleted).collect();
+        PlanDiff {
+            added : current.difference(&previous).map(|path| path.to_path_buf()).collect(),
+            removed : previous.difference(&current).map(|path| path.to_path_buf()).collect(),
+        }
+    }
+
+    /// Print a line for each path, prefixed with `+` if it was added to the plan and `-` if it was
+    /// removed, in order of path so that successive diffs can themselves be compared.
+    pub fn print(&self, previous_path: &Path) {
+        if self.added.is_empty() && self.removed.is_empty() {
+            log::info!("The plan is the same as {}", previous_path.display());
+            return;
+        }
+        log::info!("Compared with {}, {} files were added to the plan and {} removed", previous_path.display(), self.added.len(), self.removed.len());
+        let mut lines : Vec<_> = self.added.iter().map(|path| (path, '+'))
+            .chain(self.removed.iter().map(|path| (path, '-')))
+            .collect();
+        lines.sort();
+        for (path, sign) in lines {
+            println!("{} {}", sign, path.display());
+        }
+    }
+}
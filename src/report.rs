@@ -0,0 +1,653 @@
+use lru::{Disposal, FileInfo, Plan, ReclaimReport};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::fmt;
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How the results of a run are written to stdout.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// human-readable lines, printed as files are handled
+    Human,
+    /// a single JSON object describing the whole run, printed once it finishes
+    Json,
+    /// one JSON object per file, printed as soon as the file is handled
+    Ndjson,
+    /// a header row followed by one row per file, printed as soon as the file is handled
+    Csv,
+    /// each path alone, terminated by a NUL byte, printed as soon as the file is handled. chosen by
+    /// --print0 rather than --format, since it's meant for piping rather than reading
+    Print0,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown format {:?}: expected human, json, ndjson or csv", s)),
+        }
+    }
+}
+
+/// The order in which the files disposed of are printed, whatever order they were deleted in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// the order they're handled in, which is least recently used first, printed as each is
+    Age,
+    /// largest first
+    Size,
+    /// by path
+    Path,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "age" => Ok(SortOrder::Age),
+            "size" => Ok(SortOrder::Size),
+            "path" => Ok(SortOrder::Path),
+            _ => Err(format!("unknown sort order {:?}: expected age, size or path", s)),
+        }
+    }
+}
+
+impl SortOrder {
+    /// Put `files`, which are in the order they were handled, into this order. Ties keep the order
+    /// they were handled in.
+    fn sort(self, files: &mut [FileInfo]) {
+        match self {
+            SortOrder::Age => {}
+            SortOrder::Size => files.sort_by_key(|file| std::cmp::Reverse(file.size)),
+            SortOrder::Path => files.sort_by(|a, b| a.path.cmp(&b.path)),
+        }
+    }
+}
+
+/// How much to print.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// print nothing but errors
+    Quiet,
+    /// print warnings and the files a dry run would remove
+    Normal,
+    /// additionally log each file removed and a summary of the run
+    Verbose,
+    /// additionally log details of how the plan was made
+    Debug,
+}
+
+/// Whether to colorize human-readable output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// colorize when stdout is a terminal and NO_COLOR isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice {:?}: expected auto, always or never", s)),
+        }
+    }
+}
+
+/// A strftime format for the timestamps in human-readable output, checked when it's parsed so that
+/// an invalid format is rejected before anything is deleted.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TimeFormat(String);
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        TimeFormat("%m/%d/%Y %T".to_string())
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if StrftimeItems::new(s).any(|item| item == Item::Error) {
+            return Err(format!("invalid time format {:?}: expected a strftime format such as %Y-%m-%dT%H:%M:%S", s));
+        }
+        Ok(TimeFormat(s.to_string()))
+    }
+}
+
+/// How to print the timestamps which ranked files.
+#[derive(Clone, Default)]
+pub struct TimeStyle {
+    /// the format for human-readable output
+    pub format : TimeFormat,
+    /// print timestamps in UTC rather than the local timezone
+    pub utc : bool,
+}
+
+impl TimeStyle {
+    pub fn human(&self, time: &DateTime<Local>) -> String {
+        if self.utc {
+            time.with_timezone(&Utc).format(&self.format.0).to_string()
+        } else {
+            time.format(&self.format.0).to_string()
+        }
+    }
+
+    pub fn rfc3339(&self, time: &DateTime<Local>) -> String {
+        if self.utc {
+            time.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Secs, true)
+        } else {
+            time.to_rfc3339_opts(SecondsFormat::Secs, true)
+        }
+    }
+}
+
+const BOLD : &str = "1";
+const CYAN : &str = "36";
+const RED : &str = "1;31";
+const YELLOW : &str = "1;33";
+
+#[derive(Serialize)]
+struct DisposedFile {
+    path : String,
+    /// the bytes freed by deleting the file
+    size : u64,
+    /// the file's logical length in bytes
+    length : u64,
+    /// the timestamp which ranked the file, in RFC 3339 format
+    accessed : String,
+}
+
+impl DisposedFile {
+    /// One record for each path of `file`, with its timestamp printed in `time_style`'s timezone.
+    /// The file's size is attributed to its first path, and its other hard links free nothing by
+    /// themselves
+    fn new<'a>(file: &'a FileInfo, time_style: &TimeStyle) -> impl Iterator<Item = Self> + 'a {
+        let accessed = time_style.rfc3339(&file.accessed);
+        file.paths().enumerate().map(move |(i, path)| DisposedFile {
+            path : path.display().to_string(),
+            size : if i == 0 { file.size } else { 0 },
+            length : file.length,
+            accessed : accessed.clone(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct FileEvent {
+    #[serde(flatten)]
+    file : DisposedFile,
+    /// what happened to the file: delete, trash, move, shred or would-delete
+    action : &'static str,
+}
+
+/// What the files disposed of with one extension came to, for --breakdown.
+#[derive(Default, Serialize)]
+struct ExtensionTotals {
+    files : usize,
+    bytes_freed : u64,
+}
+
+/// The key --breakdown counts `path` under: its extension, lowercased since extensions are
+/// compared case-insensitively, or "(no extension)".
+fn extension_key(path: &Path) -> String {
+    path.extension().map_or_else(|| "(no extension)".to_string(), |extension| extension.to_string_lossy().to_lowercase())
+}
+
+/// Describe counts by error kind, e.g. "permission denied: 2, not found: 1".
+fn describe_kinds(kinds: &BTreeMap<String, usize>) -> String {
+    kinds.iter().map(|(kind, count)| format!("{}: {}", kind, count)).collect::<Vec<_>>().join(", ")
+}
+
+/// The outcome of the runs for every top-level directory, added together.
+struct Totals {
+    candidates : usize,
+    files : usize,
+    bytes_freed : u64,
+    /// the free space before the first run
+    free_before : u64,
+    /// the free space after the last run
+    free_after : u64,
+    /// the space target of the last run, in bytes
+    target : Option<u64>,
+    /// whether every run with a space target met it, if any had one
+    target_met : Option<bool>,
+    skipped : BTreeMap<String, usize>,
+    failed : BTreeMap<String, usize>,
+    interrupted : bool,
+    aborted : bool,
+}
+
+impl Totals {
+    fn new(reports: &[ReclaimReport]) -> Self {
+        let mut totals = Totals {
+            candidates : 0,
+            files : 0,
+            bytes_freed : 0,
+            free_before : reports.first().map_or(0, |report| report.free_before),
+            free_after : reports.last().map_or(0, |report| report.free_after),
+            target : reports.last().and_then(|report| report.target),
+            target_met : reports.iter().filter_map(|report| report.target_met).reduce(|a, b| a && b),
+            skipped : BTreeMap::new(),
+            failed : BTreeMap::new(),
+            interrupted : reports.iter().any(|report| report.interrupted),
+            aborted : reports.iter().any(|report| report.aborted),
+        };
+        for report in reports {
+            totals.candidates += report.candidates;
+            totals.files += report.deleted.len();
+            totals.bytes_freed += report.bytes_freed;
+            for (kind, count) in &report.skipped {
+                *totals.skipped.entry(kind.clone()).or_insert(0) += count;
+            }
+            for (kind, count) in &report.failed {
+                *totals.failed.entry(kind.clone()).or_insert(0) += count;
+            }
+        }
+        totals
+    }
+}
+
+#[derive(Serialize)]
+struct JsonPathReport {
+    path : String,
+    candidates : usize,
+    files : usize,
+    bytes_freed : u64,
+    free_before : u64,
+    free_after : u64,
+    target : Option<u64>,
+    target_met : Option<bool>,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    candidates : usize,
+    deleted : &'a [DisposedFile],
+    bytes_freed : u64,
+    free_before : u64,
+    free_after : u64,
+    /// the space target of the last top-level directory, in bytes, if there was one
+    target : Option<u64>,
+    /// whether every top-level directory with a space target met it, judged by the free space
+    /// queried after the run rather than the bytes freed
+    target_met : Option<bool>,
+    skipped : usize,
+    skipped_by_kind : &'a BTreeMap<String, usize>,
+    failed : usize,
+    failed_by_kind : &'a BTreeMap<String, usize>,
+    dry_run : bool,
+    /// whether a signal stopped the run before it finished
+    interrupted : bool,
+    /// how long the run took, in seconds, from the start of the walk to the end of deletion
+    duration_seconds : f64,
+    files_per_second : f64,
+    bytes_per_second : f64,
+    /// whether the run deleted nothing since it would have deleted more than --max-delete-percent
+    aborted : bool,
+    /// the files an aborted run would have deleted
+    withheld : Vec<DisposedFile>,
+    /// the outcome for each top-level directory, in the order they were handled
+    paths : Vec<JsonPathReport>,
+    /// the files disposed of and bytes freed by extension, with --breakdown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    breakdown : Option<&'a BTreeMap<String, ExtensionTotals>>,
+}
+
+/// Writes the results of a run to stdout in the requested format.
+pub struct Reporter {
+    format : Format,
+    verbosity : Verbosity,
+    dry_run : bool,
+    color : bool,
+    time_style : TimeStyle,
+    /// the past-tense verb for what happens to files, e.g. "Deleted"
+    verb : &'static str,
+    /// the machine-readable name for what happens to files, e.g. "delete"
+    action : &'static str,
+    disposed : Vec<DisposedFile>,
+    csv : Option<csv::Writer<Stdout>>,
+    /// tracks the bytes freed towards every plan made so far
+    progress : Option<ProgressBar>,
+    n_files_disposed : usize,
+    sort : SortOrder,
+    /// the files disposed of so far, held back to be printed in `sort` order once every one is known
+    unsorted : Vec<FileInfo>,
+    /// the files disposed of so far by extension, if the summary breaks them down
+    breakdown : Option<BTreeMap<String, ExtensionTotals>>,
+}
+
+impl Reporter {
+    /// A reporter which also draws a progress bar on stderr if `progress` is set, provided that
+    /// output is human-readable, not --quiet, and going to a terminal.
+    pub fn new(format: Format, verbosity: Verbosity, dry_run: bool, color: bool, time_style: TimeStyle, progress: bool, disposal: &Disposal) -> Self {
+        let csv = if format == Format::Csv && verbosity > Verbosity::Quiet {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            // NOTE: the header is written up front so that even an empty plan is valid CSV
+            writer.write_record(["path", "size_bytes", "accessed"]).expect("failed to write to stdout");
+            Some(writer)
+        } else {
+            None
+        };
+        let progress = if progress && format == Format::Human && verbosity > Verbosity::Quiet && io::stdout().is_terminal() {
+            let style = ProgressStyle::with_template("{bar:40} {binary_bytes}/{binary_total_bytes} ({msg})")
+                .expect("the progress template is valid");
+            Some(ProgressBar::new(0).with_style(style).with_message("0 files"))
+        } else {
+            None
+        };
+        Reporter {
+            format,
+            verbosity,
+            dry_run,
+            color,
+            time_style,
+            verb : disposal.verb(),
+            action : if dry_run { "would-delete" } else { disposal.action() },
+            disposed : Vec::new(),
+            csv,
+            progress,
+            n_files_disposed : 0,
+            sort : SortOrder::Age,
+            unsorted : Vec::new(),
+            breakdown : None,
+        }
+    }
+
+    /// Break down the files disposed of and bytes freed by extension in the summary.
+    pub fn break_down(&mut self) {
+        self.breakdown = Some(BTreeMap::new());
+    }
+
+    /// Print the files disposed of in `sort` order rather than as each is handled.
+    pub fn sort_by(&mut self, sort: SortOrder) {
+        self.sort = sort;
+    }
+
+    /// A callback to size the progress bar by each plan as it's made, if there is a bar.
+    pub fn on_planned(&self) -> Option<impl Fn(&Plan) + Send + Sync + 'static> {
+        let progress = self.progress.clone()?;
+        Some(move |plan: &Plan| progress.inc_length(plan.bytes))
+    }
+
+    /// Wrap `text` in the ANSI escape sequence for the SGR parameters `style`, if color is enabled.
+    fn paint(&self, style: &str, text: impl fmt::Display) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", style, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Record a file which was deleted, or which would have been in a dry run.
+    pub fn disposed(&mut self, file: &FileInfo) {
+        if let Some(progress) = &self.progress {
+            self.n_files_disposed += file.paths().count();
+            progress.inc(file.size);
+            progress.set_message(format!("{} files", self.n_files_disposed));
+        }
+        if let Some(breakdown) = &mut self.breakdown {
+            // as when listing files, the size goes to the first path and other links free nothing
+            for (i, path) in file.paths().enumerate() {
+                let totals = breakdown.entry(extension_key(path)).or_default();
+                totals.files += 1;
+                totals.bytes_freed += if i == 0 { file.size } else { 0 };
+            }
+        }
+        match self.sort {
+            SortOrder::Age => self.print_disposed(file),
+            _ => self.unsorted.push(file.clone()),
+        }
+    }
+
+    /// Print the files held back to be sorted, in order.
+    fn print_sorted(&mut self) {
+        let mut files = std::mem::take(&mut self.unsorted);
+        self.sort.sort(&mut files);
+        for file in &files {
+            self.print_disposed(file);
+        }
+    }
+
+    fn print_disposed(&mut self, file: &FileInfo) {
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        match self.format {
+            Format::Human => {
+                let accessed = self.paint(CYAN, self.time_style.human(&file.accessed));
+                let print = || {
+                    for path in file.paths() {
+                        let path = self.paint(BOLD, path.display());
+                        if self.dry_run {
+                            println!("{} {}", accessed, path);
+                        } else {
+                            let verb = self.paint(if self.verb == "Deleted" { RED } else { YELLOW }, self.verb);
+                            log::info!("{} {} {}", verb, accessed, path);
+                        }
+                    }
+                };
+                match &self.progress {
+                    // NOTE: hide the bar while printing, or it's left stranded between lines
+                    Some(progress) => progress.suspend(print),
+                    None => print(),
+                }
+            }
+            Format::Json => self.disposed.extend(DisposedFile::new(file, &self.time_style)),
+            Format::Ndjson => {
+                for file in DisposedFile::new(file, &self.time_style) {
+                    let event = FileEvent { file, action : self.action };
+                    println!("{}", serde_json::to_string(&event).expect("events are always serializable"));
+                }
+            }
+            Format::Csv => {
+                let writer = self.csv.as_mut().expect("csv reporters always have a writer");
+                for file in DisposedFile::new(file, &self.time_style) {
+                    writer.write_record([file.path, file.size.to_string(), file.accessed]).expect("failed to write to stdout");
+                }
+            }
+            Format::Print0 => {
+                let mut stdout = io::stdout().lock();
+                for path in file.paths() {
+                    // NOTE: paths are written byte for byte, since they needn't be valid UTF-8
+                    #[cfg(unix)]
+                    let bytes = path.as_os_str().as_bytes();
+                    #[cfg(not(unix))]
+                    let bytes = path.to_string_lossy();
+                    #[cfg(not(unix))]
+                    let bytes = bytes.as_bytes();
+                    stdout.write_all(bytes).and_then(|()| stdout.write_all(b"\0")).expect("failed to write to stdout");
+                }
+            }
+        }
+    }
+
+    /// The files aborted runs would have deleted, in `sort` order.
+    fn withheld(&self, reports: &[ReclaimReport]) -> Vec<FileInfo> {
+        let mut withheld : Vec<_> = reports.iter().flat_map(|report| report.withheld.iter().cloned()).collect();
+        self.sort.sort(&mut withheld);
+        withheld
+    }
+
+    /// The outcome of the runs for every top-level directory, which took `duration` in all, as a
+    /// single JSON object, for --format json.
+    fn summary(&mut self, reports: &[ReclaimReport], duration: Duration) -> String {
+        self.print_sorted();
+        let summary = &Totals::new(reports);
+        let seconds = duration.as_secs_f64();
+        let per_second = |n: f64| if seconds > 0.0 { n / seconds } else { 0.0 };
+        let (files_per_second, bytes_per_second) = (per_second(summary.files as f64), per_second(summary.bytes_freed as f64));
+        let withheld = self.withheld(reports);
+        let report = JsonReport {
+            candidates : summary.candidates,
+            deleted : &self.disposed,
+            bytes_freed : summary.bytes_freed,
+            free_before : summary.free_before,
+            free_after : summary.free_after,
+            target : summary.target,
+            target_met : summary.target_met,
+            skipped : summary.skipped.values().sum(),
+            skipped_by_kind : &summary.skipped,
+            failed : summary.failed.values().sum(),
+            failed_by_kind : &summary.failed,
+            dry_run : self.dry_run,
+            interrupted : summary.interrupted,
+            duration_seconds : seconds,
+            files_per_second,
+            bytes_per_second,
+            aborted : summary.aborted,
+            withheld : withheld.iter().flat_map(|file| DisposedFile::new(file, &self.time_style)).collect(),
+            paths : reports.iter().map(|report| JsonPathReport {
+                path : report.path.display().to_string(),
+                candidates : report.candidates,
+                files : report.deleted.len(),
+                bytes_freed : report.bytes_freed,
+                free_before : report.free_before,
+                free_after : report.free_after,
+                target : report.target,
+                target_met : report.target_met,
+            }).collect(),
+            breakdown : self.breakdown.as_ref(),
+        };
+        serde_json::to_string(&report).expect("reports are always serializable")
+    }
+
+    /// How the free space on the filesystem of `report`'s directory changed, and how that compares
+    /// to its target.
+    fn describe_free_space(&self, report: &ReclaimReport) -> String {
+        // dry runs free nothing, so only the outcome they project is worth giving
+        let mut description = if self.dry_run {
+            format!("Free space for {} is {} bytes", report.path.display(), report.free_after)
+        } else {
+            format!("Free space for {} went from {} to {} bytes", report.path.display(), report.free_before, report.free_after)
+        };
+        if let (Some(target), Some(target_met)) = (report.target, report.target_met) {
+            let outcome = match (self.dry_run, target_met) {
+                (false, true) => "meeting",
+                (false, false) => "short of",
+                (true, true) => "and the run would meet",
+                (true, false) => "and the run would fall short of",
+            };
+            description += &format!(", {} the target of {} bytes", outcome, target);
+        }
+        description
+    }
+
+    /// Print the summary of the runs for every top-level directory, which took `duration` in all.
+    pub fn finish(mut self, reports: &[ReclaimReport], duration: Duration) {
+        self.print_sorted();
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        if let Some(progress) = &self.progress {
+            progress.finish_and_clear();
+        }
+        let summary = &Totals::new(reports);
+        let seconds = duration.as_secs_f64();
+        let per_second = |n: f64| if seconds > 0.0 { n / seconds } else { 0.0 };
+        let (files_per_second, bytes_per_second) = (per_second(summary.files as f64), per_second(summary.bytes_freed as f64));
+        let withheld = self.withheld(reports);
+        // an aborted run prints its plan as a dry run would, so that it can be checked before
+        // raising --max-delete-percent
+        match self.format {
+            Format::Human => {
+                for file in &withheld {
+                    let accessed = self.paint(CYAN, self.time_style.human(&file.accessed));
+                    for path in file.paths() {
+                        println!("{} {}", accessed, self.paint(BOLD, path.display()));
+                    }
+                }
+            }
+            Format::Ndjson => {
+                for file in withheld.iter().flat_map(|file| DisposedFile::new(file, &self.time_style)) {
+                    let event = FileEvent { file, action : "would-delete" };
+                    println!("{}", serde_json::to_string(&event).expect("events are always serializable"));
+                }
+            }
+            // NOTE: csv rows and bare paths carry no action, so withheld files would be mistaken
+            // for deleted ones
+            Format::Json | Format::Csv | Format::Print0 => {}
+        }
+        if self.format == Format::Human && reports.len() > 1 {
+            for report in reports {
+                log::info!("{} {} files ({} bytes) from {}", self.verb, report.deleted.len(), report.bytes_freed, report.path.display());
+            }
+        }
+        match self.format {
+            Format::Human => {
+                if self.dry_run || self.verb == "Deleted" {
+                    log::info!("Deleted {} bytes", summary.bytes_freed);
+                } else {
+                    log::info!("{} {} files ({} bytes)", self.verb, summary.files, summary.bytes_freed);
+                }
+                if let Some(breakdown) = &self.breakdown {
+                    // the extensions which freed the most come first
+                    let mut breakdown : Vec<_> = breakdown.iter().collect();
+                    breakdown.sort_by(|(a, a_totals), (b, b_totals)| b_totals.bytes_freed.cmp(&a_totals.bytes_freed).then_with(|| a.cmp(b)));
+                    for (extension, totals) in breakdown {
+                        log::info!("  {}: {} files ({} bytes)", extension, totals.files, totals.bytes_freed);
+                    }
+                }
+                for report in reports {
+                    log::info!("{}", self.describe_free_space(report));
+                }
+                log::info!("Took {:.3}s ({:.1} files/s, {:.0} bytes/s)", seconds, files_per_second, bytes_per_second);
+            }
+            Format::Json => println!("{}", self.summary(reports, duration)),
+            // every file has already been printed
+            Format::Ndjson => {}
+            Format::Print0 => io::stdout().flush().expect("failed to write to stdout"),
+            Format::Csv => self.csv.as_mut().expect("csv reporters always have a writer").flush().expect("failed to write to stdout"),
+        }
+        let n_skipped : usize = summary.skipped.values().sum();
+        if n_skipped > 0 {
+            log::info!("Skipped {} entries which could not be read ({})", n_skipped, describe_kinds(&summary.skipped));
+        }
+        let n_failed : usize = summary.failed.values().sum();
+        if n_failed > 0 {
+            log::info!("Failed to {} {} files ({})", self.action, n_failed, describe_kinds(&summary.failed));
+        }
+
+        // a single key=value line on stderr, regardless of format or --verbose, so that cron mail
+        // always shows the outcome of a run at a glance
+        let target = match (summary.target, summary.target_met) {
+            (Some(target), Some(target_met)) => format!(" target={} target_met={}", target, target_met),
+            _ => String::new(),
+        };
+        eprintln!(
+            "lru: dry_run={} files_deleted={} bytes_freed={} free_before={} free_after={}{} skipped={} failed={} interrupted={} aborted={} duration={:.3}",
+            self.dry_run, summary.files, summary.bytes_freed, summary.free_before, summary.free_after, target, n_skipped, n_failed, summary.interrupted, summary.aborted, seconds,
+        );
+    }
+}
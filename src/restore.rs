@@ -0,0 +1,265 @@
+use anyhow::Context;
+use argh::FromArgs;
+use chrono::{DateTime, Local, SecondsFormat};
+use lru::FileInfo;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// The name of the sidecar manifest, stored at each top-level directory, which records the files
+/// --trash moved to the trash from beneath it, so that `lru restore` can put them back.
+pub const MANIFEST_FILE_NAME : &str = ".lru-trash.jsonl";
+
+/// The most seconds apart the time a file was recorded as trashed and the time the trash says it
+/// was deleted may be for the two to be taken as the same trashing.
+#[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android"))))]
+const MAX_CLOCK_DIFFERENCE : i64 = 60;
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Restore(RestoreArgs),
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "restore")]
+/// Put back the files which runs with --trash moved to the trash from beneath a top-level
+/// directory, as recorded in the directory's manifest. A file is never restored over one which
+/// has since been created at its original path, unless --force is given. Restoring is supported
+/// on Linux and Windows. Exits with status 0 if every file was restored, 1 if any could not be
+/// and 3 if the manifest or the trash could not be read.
+pub struct RestoreArgs {
+    #[argh(switch)]
+    /// list the trashed files, oldest first, along with whether each is still in the trash,
+    /// instead of restoring them
+    list : bool,
+
+    #[argh(switch)]
+    /// replace any file which has since been created at the original path of a trashed file
+    force : bool,
+
+    #[argh(positional)]
+    /// the top-level directory the files were trashed from
+    path : PathBuf,
+}
+
+/// A file moved to the trash, one JSON object per line of the manifest.
+#[derive(Serialize, Deserialize)]
+struct TrashedFile {
+    /// the canonical path the file was trashed from
+    path : PathBuf,
+    trashed_at : String,
+}
+
+/// Appends each file a run moves to the trash to the manifest of the top-level directory it was
+/// found beneath, opening each manifest the first time it's needed.
+pub struct ManifestWriter {
+    /// the canonical top-level directories, each with its manifest once opened
+    roots : Vec<(PathBuf, Option<File>)>,
+}
+
+impl ManifestWriter {
+    pub fn new(roots: &[PathBuf]) -> Self {
+        ManifestWriter { roots : roots.iter().map(|root| (root.clone(), None)).collect() }
+    }
+
+    /// Record that every path of `file` was just moved to the trash.
+    pub fn record(&mut self, file: &FileInfo) -> io::Result<()> {
+        let trashed_at = Local::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        for path in file.paths() {
+            // NOTE: the trash records files by the canonical path of their parent, and the file
+            // itself is already gone
+            let path = match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) => fs::canonicalize(parent)?.join(name),
+                _ => path.clone(),
+            };
+            // the deepest root is the one the file was found beneath when roots are nested
+            let root = self.roots.iter_mut()
+                .filter(|(root, _)| path.starts_with(root))
+                .max_by_key(|(root, _)| root.components().count());
+            let (root, manifest) = match root {
+                Some(root) => root,
+                None => {
+                    log::debug!("Not recording {} in a manifest, since it isn't beneath any top-level directory", path.display());
+                    continue;
+                }
+            };
+            if manifest.is_none() {
+                *manifest = Some(OpenOptions::new().create(true).append(true).open(root.join(MANIFEST_FILE_NAME))?);
+            }
+            let mut line = serde_json::to_vec(&TrashedFile { path, trashed_at : trashed_at.clone() })?;
+            line.push(b'\n');
+            manifest.as_mut().expect("opened above").write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the files recorded in the manifest at `path`, in the order they were trashed, or none if
+/// there's no manifest.
+fn read_manifest(path: &Path) -> anyhow::Result<Vec<(TrashedFile, DateTime<Local>)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to open manifest {}", path.display())),
+    };
+    let mut trashed = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.with_context(|| format!("failed to read manifest {}", path.display()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let context = || format!("invalid manifest {} at line {}", path.display(), i + 1);
+        let file : TrashedFile = serde_json::from_str(&line).with_context(context)?;
+        let trashed_at = DateTime::parse_from_rfc3339(&file.trashed_at).with_context(context)?.with_timezone(&Local);
+        trashed.push((file, trashed_at));
+    }
+    Ok(trashed)
+}
+
+/// Replace the manifest at `path` with just `trashed`, removing it once nothing is left.
+fn write_manifest(path: &Path, trashed: &[(TrashedFile, DateTime<Local>)]) -> anyhow::Result<()> {
+    if trashed.is_empty() {
+        return match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err).with_context(|| format!("failed to remove manifest {}", path.display())),
+            _ => Ok(()),
+        };
+    }
+    // the manifest is replaced in one step, so that an interrupted write can't lose its entries
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        for (file, _) in trashed {
+            serde_json::to_writer(&mut writer, file)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+        fs::rename(&temporary, path)
+    };
+    write().with_context(|| format!("failed to write manifest {}", path.display()))
+}
+
+#[cfg(any(target_os = "windows", all(unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android"))))]
+mod platform {
+    use super::{TrashedFile, MAX_CLOCK_DIFFERENCE};
+    use anyhow::Context;
+    use chrono::{DateTime, Local};
+    pub use trash::TrashItem;
+
+    pub const SUPPORTED : bool = true;
+
+    /// Every item in the trash.
+    pub fn list() -> anyhow::Result<Vec<TrashItem>> {
+        trash::os_limited::list().context("failed to list the trash")
+    }
+
+    /// Take the item in `items` which is the trashing of `file` at `trashed_at`, if it's still
+    /// there: the item deleted from the same path at the closest time.
+    pub fn take(items: &mut Vec<TrashItem>, file: &TrashedFile, trashed_at: DateTime<Local>) -> Option<TrashItem> {
+        let i = items.iter()
+            .enumerate()
+            .filter(|(_, item)| item.original_path() == file.path)
+            .map(|(i, item)| (i, (item.time_deleted - trashed_at.timestamp()).abs()))
+            .filter(|(_, difference)| *difference <= MAX_CLOCK_DIFFERENCE)
+            .min_by_key(|(_, difference)| *difference)?
+            .0;
+        Some(items.swap_remove(i))
+    }
+
+    pub fn restore(item: TrashItem) -> anyhow::Result<()> {
+        Ok(trash::os_limited::restore_all([item])?)
+    }
+}
+
+#[cfg(not(any(target_os = "windows", all(unix, not(target_os = "macos"), not(target_os = "ios"), not(target_os = "android")))))]
+mod platform {
+    use super::TrashedFile;
+    use chrono::{DateTime, Local};
+
+    pub enum TrashItem {}
+
+    pub const SUPPORTED : bool = false;
+
+    pub fn list() -> anyhow::Result<Vec<TrashItem>> {
+        anyhow::bail!("the trash can only be listed and restored from on Linux and Windows")
+    }
+
+    pub fn take(_items: &mut Vec<TrashItem>, _file: &TrashedFile, _trashed_at: DateTime<Local>) -> Option<TrashItem> {
+        None
+    }
+
+    pub fn restore(item: TrashItem) -> anyhow::Result<()> {
+        match item {}
+    }
+}
+
+/// List or restore the files trashed from beneath `args.path`, returning the exit status.
+pub fn run(args: &RestoreArgs) -> anyhow::Result<i32> {
+    let root = args.path.canonicalize().with_context(|| format!("failed to resolve {}", args.path.display()))?;
+    let manifest_path = root.join(MANIFEST_FILE_NAME);
+    let trashed = read_manifest(&manifest_path)?;
+    if trashed.is_empty() {
+        println!("Nothing has been trashed from {}", root.display());
+        return Ok(0);
+    }
+
+    if args.list {
+        // where the trash can't be listed, the manifest is still worth showing
+        let mut items = if platform::SUPPORTED { Some(platform::list()?) } else { None };
+        for (file, trashed_at) in &trashed {
+            let status = match items.as_mut().map(|items| platform::take(items, file, *trashed_at).is_some()) {
+                Some(true) => "trashed",
+                Some(false) => "gone",
+                None => "unknown",
+            };
+            println!("{}\t{}\t{}", file.trashed_at, status, file.path.display());
+        }
+        return Ok(0);
+    }
+
+    let mut items = platform::list()?;
+    let mut kept = Vec::new();
+    let mut n_restored = 0;
+    let mut n_unrestored = 0;
+    for (file, trashed_at) in trashed {
+        let item = match platform::take(&mut items, &file, trashed_at) {
+            Some(item) => item,
+            None => {
+                // the trash has been emptied of it, so there's nothing left to restore
+                log::warn!("{} is no longer in the trash, so it can't be restored", file.path.display());
+                n_unrestored += 1;
+                continue;
+            }
+        };
+        let restored = (|| {
+            if fs::symlink_metadata(&file.path).is_ok() {
+                if !args.force {
+                    anyhow::bail!("{} already exists; pass --force to replace it", file.path.display());
+                }
+                fs::remove_file(&file.path).with_context(|| format!("failed to replace {}", file.path.display()))?;
+            }
+            // --prune-empty-dirs may have removed the file's directory since
+            if let Some(parent) = file.path.parent() {
+                fs::create_dir_all(parent).with_context(|| format!("failed to recreate {}", parent.display()))?;
+            }
+            platform::restore(item).with_context(|| format!("failed to restore {}", file.path.display()))
+        })();
+        match restored {
+            Ok(()) => {
+                println!("Restored {}", file.path.display());
+                n_restored += 1;
+            }
+            Err(err) => {
+                log::warn!("{:#}", err);
+                n_unrestored += 1;
+                kept.push((file, trashed_at));
+            }
+        }
+    }
+    write_manifest(&manifest_path, &kept)?;
+    println!("Restored {} of {} files", n_restored, n_restored + n_unrestored);
+    Ok(if n_unrestored > 0 { 1 } else { 0 })
+}
@@ -0,0 +1,39 @@
+use crate::interval::Interval;
+use lru::{RetentionBand, RetentionPolicy};
+use std::str::FromStr;
+
+/// The bands of a retention policy for --retention, parsed from a comma-separated list of
+/// policies, youngest first, each but the last followed by a colon and the age it applies up to,
+/// e.g. `protect:7d,pressure:30d,purge`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retention(pub Vec<RetentionBand>);
+
+impl FromStr for Retention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bands = Vec::new();
+        let specs : Vec<&str> = s.split(',').map(str::trim).collect();
+        for (i, spec) in specs.iter().enumerate() {
+            let (policy, younger_than) = match spec.split_once(':') {
+                Some((policy, age)) => {
+                    let age = age.parse::<Interval>().map_err(|err| format!("invalid retention band {:?}: {}", spec, err))?;
+                    let age = chrono::Duration::from_std(age.0).map_err(|_| format!("invalid retention band {:?}: too old", spec))?;
+                    (policy, Some(age))
+                }
+                None => (*spec, None),
+            };
+            let policy = policy.parse::<RetentionPolicy>().map_err(|err| format!("invalid retention band {:?}: {}", spec, err))?;
+            let last = i + 1 == specs.len();
+            match (younger_than, last) {
+                (None, false) => return Err(format!("invalid retention {:?}: every band but the last needs an age, e.g. {}:7d", s, spec)),
+                (Some(_), true) => return Err(format!("invalid retention {:?}: the last band takes every older file, so has no age", s)),
+                _ => bands.push(RetentionBand { younger_than, policy }),
+            }
+        }
+        if bands.windows(2).any(|pair| pair[0].younger_than >= pair[1].younger_than && pair[1].younger_than.is_some()) {
+            return Err(format!("invalid retention {:?}: the ages must increase from each band to the next", s));
+        }
+        Ok(Retention(bands))
+    }
+}
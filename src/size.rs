@@ -0,0 +1,135 @@
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// A number of bytes, parsed from a human-readable string such as `50GB`, `500MiB` or `1.5T`.
+///
+/// Suffixes follow the coreutils convention: `KB`, `MB`, `GB`, ... are decimal (powers of 1000),
+/// while `KiB`, `MiB`, `GiB`, ... and the single-letter forms `K`, `M`, `G`, ... are binary (powers
+/// of 1024). Suffixes are case-insensitive, fractional values are rounded to the nearest byte
+/// (`1.5GiB` is 1610612736 bytes) and a bare number is a plain byte count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+const SUFFIXES : &[(&str, u64)] = &[
+    ("b", 1),
+    ("k", 1 << 10), ("kib", 1 << 10), ("kb", 1_000),
+    ("m", 1 << 20), ("mib", 1 << 20), ("mb", 1_000_000),
+    ("g", 1 << 30), ("gib", 1 << 30), ("gb", 1_000_000_000),
+    ("t", 1 << 40), ("tib", 1 << 40), ("tb", 1_000_000_000_000),
+    ("p", 1 << 50), ("pib", 1 << 50), ("pb", 1_000_000_000_000_000),
+];
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let split = trimmed.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(trimmed.len());
+        let (number, suffix) = trimmed.split_at(split);
+        let suffix = suffix.trim();
+
+        let multiplier = if suffix.is_empty() {
+            1
+        } else {
+            let lowercase = suffix.to_ascii_lowercase();
+            match SUFFIXES.iter().find(|(name, _)| *name == lowercase) {
+                Some((_, multiplier)) => *multiplier,
+                None => return Err(format!("invalid size {:?}: unknown suffix {:?} (expected B, K/KB/KiB, M/MB/MiB, G/GB/GiB, T/TB/TiB or P/PB/PiB)", s, suffix)),
+            }
+        };
+
+        // parse whole numbers exactly so that large byte counts don't lose precision in an f64
+        if let Ok(whole) = number.parse::<u64>() {
+            return whole.checked_mul(multiplier)
+                .map(ByteSize)
+                .ok_or_else(|| format!("invalid size {:?}: too large", s));
+        }
+
+        match number.parse::<f64>() {
+            Ok(fractional) if fractional.is_finite() => {
+                let bytes = (fractional * multiplier as f64).round();
+                if bytes < u64::MAX as f64 {
+                    Ok(ByteSize(bytes as u64))
+                } else {
+                    Err(format!("invalid size {:?}: too large", s))
+                }
+            }
+            _ => Err(format!("invalid size {:?}: expected a number optionally followed by a unit, e.g. 50GB or 1.5TiB", s)),
+        }
+    }
+}
+
+/// Sizes in config files may be either a byte count or a string in the same format as on the
+/// command line.
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ByteSizeVisitor;
+
+        impl Visitor<'_> for ByteSizeVisitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a number of bytes or a size such as \"50GB\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, bytes: u64) -> Result<ByteSize, E> {
+                Ok(ByteSize(bytes))
+            }
+
+            fn visit_i64<E: de::Error>(self, bytes: i64) -> Result<ByteSize, E> {
+                u64::try_from(bytes).map(ByteSize).map_err(|_| E::custom(format!("invalid size {}: must not be negative", bytes)))
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<ByteSize, E> {
+                s.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(ByteSizeVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteSize;
+
+    fn parse(s: &str) -> Result<u64, String> {
+        s.parse::<ByteSize>().map(|size| size.0)
+    }
+
+    #[test]
+    fn every_suffix_is_understood_in_any_case() {
+        let cases : &[(&str, u64)] = &[
+            ("512", 512),
+            ("512B", 512),
+            ("2K", 2 << 10), ("2KiB", 2 << 10), ("2KB", 2_000),
+            ("2M", 2 << 20), ("2MiB", 2 << 20), ("2MB", 2_000_000),
+            ("2G", 2 << 30), ("2GiB", 2 << 30), ("2GB", 2_000_000_000),
+            ("2T", 2 << 40), ("2TiB", 2 << 40), ("2TB", 2_000_000_000_000),
+            ("2P", 2 << 50), ("2PiB", 2 << 50), ("2PB", 2_000_000_000_000_000),
+            ("2gib", 2 << 30), ("2gb", 2_000_000_000), (" 2 GiB ", 2 << 30),
+        ];
+        for (s, bytes) in cases {
+            assert_eq!(parse(s), Ok(*bytes), "parsing {:?}", s);
+        }
+    }
+
+    #[test]
+    fn fractions_are_rounded_to_the_nearest_byte() {
+        assert_eq!(parse("1.5GiB"), Ok(1610612736));
+        assert_eq!(parse("1.5G"), Ok(1610612736));
+        assert_eq!(parse("1.5GB"), Ok(1_500_000_000));
+        assert_eq!(parse("0.5K"), Ok(512));
+        assert_eq!(parse("1.0005KB"), Ok(1001));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(parse("5XY").unwrap_err().contains("unknown suffix"));
+        for s in ["", "GB", "1.2.3GB", "-5GB", "18446744073709551616", "20000PB"] {
+            assert!(parse(s).is_err(), "parsing {:?}", s);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant};
+
+/// Tells systemd how a daemon started with --interval is doing, for services with Type=notify and
+/// optionally WatchdogSec. Nothing is sent unless systemd started us, i.e. NOTIFY_SOCKET is set.
+pub struct Notifier {
+    /// how often to ping the watchdog, if systemd enabled it
+    watchdog_interval : Option<Duration>,
+    last_ping : Instant,
+}
+
+#[cfg(unix)]
+fn notify(state: &[sd_notify::NotifyState]) {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    if let Err(err) = sd_notify::notify(state) {
+        log::debug!("Failed to notify systemd: {}", err);
+    }
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        // NOTE: ping twice as often as systemd requires, so that a slow ping doesn't get us killed
+        let watchdog_interval = sd_notify::watchdog_enabled().map(|timeout| timeout / 2);
+        #[cfg(not(unix))]
+        let watchdog_interval = None;
+        Notifier { watchdog_interval, last_ping : Instant::now() }
+    }
+
+    /// Tell systemd that we've started up.
+    pub fn ready(&self) {
+        #[cfg(unix)]
+        notify(&[sd_notify::NotifyState::Ready]);
+    }
+
+    /// Tell the watchdog that we're still alive, if it's been long enough since we last did.
+    pub fn ping(&mut self) {
+        if self.watchdog_interval.is_some_and(|interval| self.last_ping.elapsed() >= interval) {
+            #[cfg(unix)]
+            notify(&[sd_notify::NotifyState::Watchdog]);
+            self.last_ping = Instant::now();
+        }
+    }
+
+    /// Tell systemd that we're shutting down.
+    pub fn stopping(&self) {
+        #[cfg(unix)]
+        notify(&[sd_notify::NotifyState::Stopping]);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::report::TimeStyle;
+use lru::{FileInfo, Largest};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct LargeFile {
+    path : String,
+    /// the bytes deleting the file would free
+    size : u64,
+    /// the file's logical length in bytes
+    length : u64,
+    /// the timestamp which would rank the file, in RFC 3339 format
+    accessed : String,
+}
+
+/// The largest files beneath every top-level directory which a run could delete, for --top.
+/// Sizes are measured by --size-source, and timestamps by --by.
+pub struct Top {
+    n : usize,
+    files : Vec<FileInfo>,
+    candidates : usize,
+    candidate_bytes : u64,
+}
+
+#[derive(Serialize)]
+struct JsonTop {
+    candidates : usize,
+    candidate_bytes : u64,
+    files : Vec<LargeFile>,
+}
+
+impl Top {
+    /// An empty list of at most `n` files.
+    pub fn new(n: usize) -> Self {
+        Top { n, files : Vec::new(), candidates : 0, candidate_bytes : 0 }
+    }
+
+    /// Add the largest files beneath one top-level directory, keeping the `n` largest of all.
+    pub fn add(&mut self, largest: Largest) {
+        self.candidates += largest.candidates;
+        self.candidate_bytes += largest.candidate_bytes;
+        self.files.extend(largest.files);
+        // files are ranked by size, largest first
+        self.files.sort();
+        self.files.truncate(self.n);
+    }
+
+    /// Print a line for each file, largest first, followed by what they add up to.
+    pub fn print_human(&self, time_style: &TimeStyle) {
+        for file in &self.files {
+            println!("{:>16} {} {}", file.size, time_style.human(&file.accessed), file.path.display());
+        }
+        let n_bytes : u64 = self.files.iter().map(|file| file.size).sum();
+        println!("The {} largest of {} candidates take {} of their {} bytes", self.files.len(), self.candidates, n_bytes, self.candidate_bytes);
+    }
+
+    pub fn print_json(&self, time_style: &TimeStyle) {
+        let files = self.files.iter().map(|file| LargeFile {
+            path : file.path.display().to_string(),
+            size : file.size,
+            length : file.length,
+            accessed : time_style.rfc3339(&file.accessed),
+        });
+        let top = JsonTop { candidates : self.candidates, candidate_bytes : self.candidate_bytes, files : files.collect() };
+        println!("{}", serde_json::to_string(&top).expect("lists of files are always serializable"));
+    }
+}
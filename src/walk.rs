@@ -0,0 +1,260 @@
+use rayon::prelude::*;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use crate::filesystem::{FileStat, FileSystem};
+use crate::walk_index::SharedWalkIndex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use walkdir::WalkDir;
+
+/// Controls how the top-level directory is traversed.
+pub struct WalkOptions {
+    /// skip files matched by `.gitignore` and `.ignore` files found during the traversal
+    pub respect_ignore : bool,
+    /// descend into symlinked directories and yield symlinked files with their targets' metadata
+    pub follow_symlinks : bool,
+    /// skip anything on a different filesystem to the root
+    pub one_file_system : bool,
+    /// don't descend more than this many levels below the root, which is at depth 0
+    pub max_depth : Option<usize>,
+    /// skip anything fewer than this many levels below the root
+    pub min_depth : usize,
+    /// skip every directory containing a file with this name, along with everything beneath it
+    pub keep_marker : Option<String>,
+    /// visit each directory's entries in order of name, and skip the rest of a directory's entries
+    /// as soon as it's added to this set, without stat'ing them. the walk reads ahead of the
+    /// entries yielded, so entries already read may still be yielded
+    pub closed_directories : Option<ClosedDirectories>,
+    /// walk through this index, reusing what it cached of directories which haven't changed since
+    /// and recording what's found. only regular files are yielded, and `respect_ignore`,
+    /// `follow_symlinks` and `closed_directories` aren't supported. filesystems other than the real
+    /// one may ignore it
+    pub index : Option<SharedWalkIndex>,
+}
+
+/// The directories whose remaining entries a walk should skip, shared with its consumer.
+pub type ClosedDirectories = Arc<Mutex<HashSet<PathBuf>>>;
+
+/// A file or directory found while walking the top-level directory. A followed symlink keeps the
+/// path it was found at, by which it's filtered and reported, with the metadata of its target.
+pub struct WalkEntry {
+    pub path : PathBuf,
+    pub metadata : FileStat,
+}
+
+/// An entry which couldn't be read or stat'd while walking the top-level directory.
+pub struct WalkError {
+    /// the path of the entry, if known
+    pub path : Option<PathBuf>,
+    pub error : io::Error,
+}
+
+impl From<walkdir::Error> for WalkError {
+    fn from(err: walkdir::Error) -> Self {
+        let path = err.path().map(Path::to_path_buf);
+        // NOTE: symlink loops are reported as errors without an underlying io error
+        let error = if err.io_error().is_some() {
+            err.into_io_error().expect("checked above")
+        } else {
+            io::Error::other(err.to_string())
+        };
+        WalkError { path, error }
+    }
+}
+
+impl From<ignore::Error> for WalkError {
+    fn from(err: ignore::Error) -> Self {
+        let error = match err.io_error() {
+            Some(error) => io::Error::new(error.kind(), err.to_string()),
+            None => io::Error::other(err.to_string()),
+        };
+        WalkError { path : None, error }
+    }
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.display(), self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// An entry found by one of the walkers, which hasn't been stat'd yet.
+enum UnstatedEntry {
+    WalkDir(walkdir::DirEntry),
+    Ignore(ignore::DirEntry),
+}
+
+impl UnstatedEntry {
+    fn stat(self) -> Result<WalkEntry, WalkError> {
+        match self {
+            UnstatedEntry::WalkDir(entry) => {
+                Ok(WalkEntry { path : entry.path().to_path_buf(), metadata : FileStat::from_metadata(&entry.metadata()?) })
+            }
+            UnstatedEntry::Ignore(entry) => {
+                Ok(WalkEntry { path : entry.path().to_path_buf(), metadata : FileStat::from_metadata(&entry.metadata()?) })
+            }
+        }
+    }
+}
+
+/// The number of entries stat'd at once. Larger batches keep more threads busy, at the cost of
+/// holding more entries in memory.
+const STAT_BATCH_SIZE : usize = 4096;
+
+/// Stats the entries yielded by a walker in parallel, yielding them in the order they were found.
+/// Reading directories remains sequential, but stat'ing their entries is what dominates the walk
+/// of a large tree, especially on high-latency filesystems.
+struct ParallelStat<I> {
+    entries : I,
+    statted : VecDeque<Result<WalkEntry, WalkError>>,
+}
+
+impl<I: Iterator<Item = Result<UnstatedEntry, WalkError>>> Iterator for ParallelStat<I> {
+    type Item = Result<WalkEntry, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.statted.is_empty() {
+            let batch : Vec<_> = self.entries.by_ref().take(STAT_BATCH_SIZE).collect();
+            self.statted = batch.into_par_iter().map(|entry| entry.and_then(UnstatedEntry::stat)).collect::<Vec<_>>().into();
+        }
+        self.statted.pop_front()
+    }
+}
+
+/// Whether the file at `path`, described by `metadata`, is hidden: its name begins with a dot or
+/// the platform marks it as hidden. Only the file itself is considered, not the directories above
+/// it.
+pub fn is_hidden(path: &Path, metadata: &FileStat) -> bool {
+    metadata.hidden || path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// Recursively walk `root`, yielding an error for each entry which can't be read or stat'd and
+/// carrying on past it.
+pub fn walk(root: &Path, options: &WalkOptions) -> Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> {
+    // NOTE: without device ids, --one-file-system has no effect
+    let root_device = if options.one_file_system {
+        fs::metadata(root).ok().and_then(|metadata| FileStat::from_metadata(&metadata).inode).map(|(device, _)| device)
+    } else {
+        None
+    };
+
+    // NOTE: the walkers don't filter entries above the minimum depth, so the root is checked here
+    if let Some(keep_marker) = &options.keep_marker {
+        if root.join(keep_marker).exists() {
+            log::info!("Keeping everything beneath {}, since it contains {}", root.display(), keep_marker);
+            return Box::new(std::iter::empty());
+        }
+    }
+    let keep_marker = options.keep_marker.clone();
+    let closed_directories = options.closed_directories.clone();
+    let is_kept = move |path: &Path, is_dir: bool| {
+        let kept = is_dir && keep_marker.as_ref().is_some_and(|keep_marker| path.join(keep_marker).exists());
+        if kept {
+            log::debug!("Keeping everything beneath {}, since it contains a keep marker", path.display());
+        }
+        let closed = closed_directories.as_ref().is_some_and(|closed_directories| {
+            path.parent().is_some_and(|parent| closed_directories.lock().expect("the lock is never poisoned").contains(parent))
+        });
+        kept || closed
+    };
+    let sorted = options.closed_directories.is_some();
+
+    let entries : Box<dyn Iterator<Item = Result<UnstatedEntry, WalkError>>> = if options.respect_ignore {
+        let mut builder = ignore::WalkBuilder::new(root);
+        if sorted {
+            builder.sort_by_file_name(|a, b| a.cmp(b));
+        }
+        let walker = builder
+            // only honor ignore files found within the tree, not hidden-file rules, global git
+            // configuration or ignore files in the root's ancestors
+            .standard_filters(false)
+            .git_ignore(true)
+            .ignore(true)
+            .require_git(false)
+            .follow_links(options.follow_symlinks)
+            .same_file_system(root_device.is_some())
+            .max_depth(options.max_depth)
+            .filter_entry(move |entry| !is_kept(entry.path(), entry.file_type().is_some_and(|file_type| file_type.is_dir())))
+            .build();
+        let min_depth = options.min_depth;
+        Box::new(walker
+            .filter(move |entry| entry.as_ref().map_or(true, |entry| entry.depth() >= min_depth))
+            .map(|entry| Ok(UnstatedEntry::Ignore(entry?))))
+    } else {
+        let mut walker = WalkDir::new(root)
+            .follow_links(options.follow_symlinks)
+            .same_file_system(root_device.is_some())
+            .min_depth(options.min_depth);
+        if let Some(max_depth) = options.max_depth {
+            walker = walker.max_depth(max_depth);
+        }
+        if sorted {
+            walker = walker.sort_by_file_name();
+        }
+        Box::new(walker
+            .into_iter()
+            .filter_entry(move |entry| !is_kept(entry.path(), entry.file_type().is_dir()))
+            .map(|entry| Ok(UnstatedEntry::WalkDir(entry?))))
+    };
+    let entries : Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> = Box::new(ParallelStat { entries, statted : VecDeque::new() });
+
+    let entries : Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> = match root_device {
+        // the walkers won't descend into directories on other filesystems, but a followed symlink
+        // may still lead to a file on one
+        Some(root_device) => Box::new(entries.filter(move |entry| {
+            entry.as_ref().map_or(true, |entry| entry.metadata.inode.is_none_or(|(device, _)| device == root_device))
+        })),
+        None => entries,
+    };
+
+    if options.follow_symlinks {
+        // several symlinks may lead to the same file, which must only be considered once. files are
+        // told apart by their canonical paths rather than their inodes, since hard links to the
+        // same inode are distinct files which must each be deleted to free any space
+        let mut visited = HashSet::new();
+        Box::new(entries.filter(move |entry| match entry {
+            Ok(entry) => !entry.metadata.is_file || fs::canonicalize(&entry.path).map_or(true, |path| visited.insert(path)),
+            Err(_) => true,
+        }))
+    } else {
+        entries
+    }
+}
+
+/// Stat each of `paths` in turn on `filesystem`, as though they'd been found by walking `root`,
+/// yielding an error for each which can't be stat'd. Paths which aren't beneath `root` are passed
+/// over, so that each top-level directory only considers its own share of the list.
+pub fn stat_listed(filesystem: Arc<dyn FileSystem>, root: &Path, paths: Arc<Vec<PathBuf>>, follow_symlinks: bool) -> Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> {
+    // NOTE: the listed paths may be relative, or reach the root through symlinks, so both are
+    // compared by their canonical parents
+    let root = filesystem.canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let mut i = 0;
+    Box::new(std::iter::from_fn(move || loop {
+        let path = paths.get(i)?;
+        i += 1;
+        let path = match (path.parent().filter(|parent| !parent.as_os_str().is_empty()), path.file_name()) {
+            (Some(parent), Some(name)) => match filesystem.canonicalize(parent) {
+                Ok(parent) => parent.join(name),
+                Err(error) => return Some(Err(WalkError { path : Some(path.clone()), error })),
+            },
+            (None, Some(_)) => match std::env::current_dir() {
+                Ok(current_dir) => current_dir.join(path),
+                Err(error) => return Some(Err(WalkError { path : Some(path.clone()), error })),
+            },
+            _ => path.clone(),
+        };
+        if !path.starts_with(&root) {
+            log::debug!("Passing over {}, since it isn't beneath {}", path.display(), root.display());
+            continue;
+        }
+        return Some(match filesystem.stat(&path, follow_symlinks) {
+            Ok(metadata) => Ok(WalkEntry { path, metadata }),
+            Err(error) => Err(WalkError { path : Some(path), error }),
+        });
+    }))
+}
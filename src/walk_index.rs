@@ -0,0 +1,358 @@
+use crate::filesystem::FileStat;
+use crate::walk::{WalkEntry, WalkError, WalkOptions};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// How long after a directory last changed before its listing may be cached. Entries created in
+/// the same tick of the directory's timestamps as the listing was read would otherwise go unseen,
+/// and some filesystems only keep timestamps to the nearest second or two.
+const RACY_WINDOW : Duration = Duration::from_secs(2);
+
+/// A timestamp as nanoseconds since the unix epoch, which is how they're cached.
+fn nanos(time: SystemTime) -> Option<i64> {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => i64::try_from(since.as_nanos()).ok(),
+        Err(err) => i64::try_from(err.duration().as_nanos()).ok().map(|nanos| -nanos),
+    }
+}
+
+fn time(nanos: i64) -> SystemTime {
+    let since = Duration::from_nanos(nanos.unsigned_abs());
+    if nanos >= 0 { SystemTime::UNIX_EPOCH + since } else { SystemTime::UNIX_EPOCH - since }
+}
+
+/// What identifies one state of a directory's entries: its device and inode numbers, and its mtime
+/// and ctime, which any entry being created, removed or renamed updates. The ctime can't be set
+/// back, so a directory whose mtime has been restored still doesn't match.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp(u64, u64, i64, i64);
+
+impl Stamp {
+    fn of(metadata: &FileStat) -> Option<Self> {
+        let (device, inode) = metadata.inode?;
+        Some(Stamp(device, inode, nanos(metadata.modified?)?, nanos(metadata.changed?)?))
+    }
+}
+
+/// A file's metadata as cached, which is just what a walk yields for a regular file. Serialized as
+/// an array to keep the index small.
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedStat(u64, Option<u64>, Option<i64>, Option<i64>, Option<i64>, (u64, u64), u64, Option<u32>);
+
+impl CachedStat {
+    fn new(metadata: &FileStat) -> Option<Self> {
+        // NOTE: the inode is what tells a cached file apart from one since created in its place
+        let inode = metadata.inode?;
+        Some(CachedStat(
+            metadata.len,
+            metadata.allocated,
+            metadata.accessed.and_then(nanos),
+            metadata.modified.and_then(nanos),
+            metadata.created.and_then(nanos),
+            inode,
+            metadata.links,
+            metadata.owner,
+        ))
+    }
+
+    #[cfg(unix)]
+    fn inode(&self) -> (u64, u64) {
+        self.5
+    }
+
+    fn to_stat(&self) -> FileStat {
+        FileStat {
+            is_file : true,
+            is_symlink : false,
+            len : self.0,
+            allocated : self.1,
+            accessed : self.2.map(time),
+            modified : self.3.map(time),
+            created : self.4.map(time),
+            changed : None,
+            inode : Some(self.5),
+            links : self.6,
+            owner : self.7,
+            hidden : false,
+            // NOTE: changing the mode leaves the directory's timestamps alone, so it isn't cached
+            // but checked again before deletion
+            readonly : false,
+        }
+    }
+}
+
+/// The entries of a directory as of its stamp: each regular file, with its metadata unless it has
+/// to be stat'd again, and each subdirectory.
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedDirectory {
+    stamp : Stamp,
+    files : Vec<(String, Option<CachedStat>)>,
+    directories : Vec<String>,
+}
+
+/// The directories a previous walk listed, along with the metadata of their files, so that a later
+/// walk can skip reading each directory whose entries haven't changed since and stat'ing the files
+/// it already knows. Used for `ReclaimConfig::walk_index`.
+///
+/// Directory timestamps only change when entries are added, removed or renamed, so reading or
+/// writing a file leaves the cached metadata stale: its access time, size or modification time may
+/// since have moved on. A walk through the index therefore yields lower bounds on the access times
+/// of the files it found cached, and every file must be stat'd again before it's deleted, with any
+/// which have changed left for another pass to reconsider. Nothing is cached on platforms without
+/// inode numbers and ctimes, or for directories changed too recently to be sure of.
+#[derive(Default)]
+pub struct WalkIndex {
+    directories : HashMap<PathBuf, CachedDirectory>,
+    /// the directories walked during this run, whose entries `save` keeps
+    walked : HashSet<PathBuf>,
+}
+
+impl WalkIndex {
+    /// Load the index written by a previous run, or start an empty one if none exists.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        let directories = serde_json::from_reader(BufReader::new(file))?;
+        Ok(WalkIndex { directories, walked : HashSet::new() })
+    }
+
+    /// Make the next walk stat the file at `path` again, since its cached metadata is out of date.
+    pub fn forget(&mut self, path: &Path) {
+        let (parent, name) = match (path.parent(), path.file_name().and_then(|name| name.to_str())) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return,
+        };
+        if let Some(directory) = self.directories.get_mut(parent) {
+            for (_, stat) in directory.files.iter_mut().filter(|(file, _)| file == name) {
+                *stat = None;
+            }
+        }
+    }
+
+    /// Persist the index to `path`. Directories beneath `root` which weren't walked during this run
+    /// are dropped, while those beneath other top-level directories are kept for their own runs.
+    pub fn save(&mut self, path: &Path, root: &Path) -> io::Result<()> {
+        let walked = &self.walked;
+        self.directories.retain(|directory, _| walked.contains(directory) || !directory.starts_with(root));
+        // write to a temporary file and rename it into place so that an interrupted run never
+        // leaves a truncated index behind
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(".tmp");
+        let temporary_path = PathBuf::from(temporary_path);
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        serde_json::to_writer(&mut writer, &self.directories)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temporary_path, path)
+    }
+}
+
+/// The index a walk reads from and updates, shared with the run it's walking for.
+pub type SharedWalkIndex = Arc<Mutex<WalkIndex>>;
+
+/// Walks a tree through a `WalkIndex`, yielding only regular files. Directories are visited depth
+/// first, and each is read and its files stat'd in one go.
+struct IndexedWalk {
+    index : SharedWalkIndex,
+    /// the directories still to visit, with their depths
+    directories : Vec<(PathBuf, usize)>,
+    /// the entries found in the directory visited last, which are yet to be yielded
+    entries : VecDeque<Result<WalkEntry, WalkError>>,
+    min_depth : usize,
+    max_depth : Option<usize>,
+    keep_marker : Option<String>,
+    root_device : Option<u64>,
+}
+
+impl IndexedWalk {
+    fn lock(&self) -> std::sync::MutexGuard<'_, WalkIndex> {
+        self.index.lock().expect("the lock is never poisoned")
+    }
+
+    /// Find the entries of `directory`, which is `depth` levels below the root, from the index if
+    /// it hasn't changed and by reading and stat'ing them otherwise.
+    fn visit(&mut self, directory: PathBuf, depth: usize) {
+        // NOTE: the directory is stat'd before it's read, so that anything which changes it while
+        // it's being read leaves its stamp out of date
+        let started = SystemTime::now();
+        // the root may be reached through a symlink, as in the plain walk, but nothing beneath it
+        let metadata = if depth == 0 { fs::metadata(&directory) } else { fs::symlink_metadata(&directory) };
+        let metadata = match metadata {
+            Ok(metadata) => FileStat::from_metadata(&metadata),
+            Err(error) => return self.entries.push_back(Err(WalkError { path : Some(directory), error })),
+        };
+        if depth > 0 && self.root_device.is_some_and(|root_device| metadata.inode.is_some_and(|(device, _)| device != root_device)) {
+            return;
+        }
+        if let Some(keep_marker) = &self.keep_marker {
+            if directory.join(keep_marker).exists() {
+                if depth == 0 {
+                    log::info!("Keeping everything beneath {}, since it contains {}", directory.display(), keep_marker);
+                } else {
+                    log::debug!("Keeping everything beneath {}, since it contains a keep marker", directory.display());
+                }
+                return;
+            }
+        }
+        let stamp = Stamp::of(&metadata);
+        let cached = self.lock().directories.get(&directory).cloned();
+        let mut listing = match cached {
+            Some(cached) if Some(cached.stamp) == stamp => Listing::from_cached(cached),
+            cached => match list(&directory, cached.as_ref()) {
+                Ok(listing) => listing,
+                Err(error) => return self.entries.push_back(Err(WalkError { path : Some(directory), error })),
+            },
+        };
+
+        // stat whichever files the index doesn't already know, in parallel as the plain walk does
+        let yield_files = depth + 1 >= self.min_depth && self.max_depth.is_none_or(|max_depth| depth < max_depth);
+        let statted : Vec<_> = listing.files.into_par_iter()
+            .map(|(name, cached)| {
+                let path = directory.join(&name);
+                let metadata = match &cached {
+                    Some(cached) => Ok(cached.to_stat()),
+                    None => fs::symlink_metadata(&path).map(|metadata| FileStat::from_metadata(&metadata)),
+                };
+                (name, path, metadata)
+            })
+            .collect();
+        let mut files = Vec::new();
+        for (name, path, metadata) in statted {
+            match metadata {
+                // a file replaced by something else since it was listed is no longer a file
+                Ok(metadata) if metadata.is_file => {
+                    if let Ok(name) = name.into_string() {
+                        files.push((name, CachedStat::new(&metadata)));
+                    }
+                    if yield_files {
+                        self.entries.push_back(Ok(WalkEntry { path, metadata }));
+                    }
+                }
+                Ok(_) => listing.cacheable = false,
+                Err(error) => {
+                    listing.cacheable = false;
+                    self.entries.push_back(Err(WalkError { path : Some(path), error }));
+                }
+            }
+        }
+        if self.max_depth.is_none_or(|max_depth| depth + 1 < max_depth) {
+            for name in listing.directories.iter().rev() {
+                self.directories.push((directory.join(name), depth + 1));
+            }
+        }
+
+        // NOTE: a directory changed no longer ago than its timestamps' resolution may change again
+        // without them moving on, so it's read afresh every time until it settles
+        let settled = [metadata.modified, metadata.changed].iter()
+            .all(|time| time.is_some_and(|time| time + RACY_WINDOW < started));
+        let mut index = self.lock();
+        match stamp {
+            Some(stamp) if listing.cacheable && settled && directory.to_str().is_some() => {
+                let directories = listing.directories.into_iter().filter_map(|name| name.into_string().ok()).collect();
+                index.directories.insert(directory.clone(), CachedDirectory { stamp, files, directories });
+                index.walked.insert(directory);
+            }
+            _ => {
+                index.directories.remove(&directory);
+            }
+        }
+    }
+}
+
+/// The entries of a directory as read, before they're cached.
+struct Listing {
+    files : Vec<(OsString, Option<CachedStat>)>,
+    directories : Vec<OsString>,
+    /// whether every name is valid UTF-8, as only then can the listing be cached
+    cacheable : bool,
+}
+
+impl Listing {
+    fn from_cached(cached: CachedDirectory) -> Self {
+        Listing {
+            files : cached.files.into_iter().map(|(name, stat)| (name.into(), stat)).collect(),
+            directories : cached.directories.into_iter().map(OsString::from).collect(),
+            cacheable : true,
+        }
+    }
+}
+
+/// Read the entries of `directory`, reusing the metadata `cached` for each file which is still the
+/// same inode.
+fn list(directory: &Path, cached: Option<&CachedDirectory>) -> io::Result<Listing> {
+    // NOTE: nothing is cached without inode numbers, so there's nothing to reuse
+    #[cfg(not(unix))]
+    let _ = cached;
+    #[cfg(unix)]
+    let known : HashMap<&str, &CachedStat> = cached.iter()
+        .flat_map(|cached| &cached.files)
+        .filter_map(|(name, stat)| Some((name.as_str(), stat.as_ref()?)))
+        .collect();
+    let mut listing = Listing { files : Vec::new(), directories : Vec::new(), cacheable : true };
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let name = entry.file_name();
+        listing.cacheable &= name.to_str().is_some();
+        if file_type.is_dir() {
+            listing.directories.push(name);
+        } else if file_type.is_file() {
+            #[cfg(unix)]
+            let stat = {
+                use std::os::unix::fs::DirEntryExt;
+                name.to_str()
+                    .and_then(|name| known.get(name))
+                    .filter(|stat| stat.inode().1 == entry.ino())
+                    .map(|stat| (*stat).clone())
+            };
+            #[cfg(not(unix))]
+            let stat = None;
+            listing.files.push((name, stat));
+        }
+    }
+    Ok(listing)
+}
+
+impl Iterator for IndexedWalk {
+    type Item = Result<WalkEntry, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.pop_front() {
+                return Some(entry);
+            }
+            let (directory, depth) = self.directories.pop()?;
+            self.visit(directory, depth);
+        }
+    }
+}
+
+/// Recursively walk `root` through `index`, which must already hold whatever a previous walk
+/// cached. Only the depth limits, keep markers and --one-file-system of `options` are honored.
+pub fn walk(root: &Path, options: &WalkOptions, index: SharedWalkIndex) -> Box<dyn Iterator<Item = Result<WalkEntry, WalkError>>> {
+    let root_device = if options.one_file_system {
+        fs::metadata(root).ok().and_then(|metadata| FileStat::from_metadata(&metadata).inode).map(|(device, _)| device)
+    } else {
+        None
+    };
+    Box::new(IndexedWalk {
+        index,
+        directories : vec![(root.to_path_buf(), 0)],
+        entries : VecDeque::new(),
+        min_depth : options.min_depth,
+        max_depth : options.max_depth,
+        keep_marker : options.keep_marker.clone(),
+        root_device,
+    })
+}
@@ -0,0 +1,212 @@
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+/// The kinds of change beneath the top-level directories which wake --watch, parsed from a
+/// comma-separated list such as `create,write`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchEvents {
+    /// files or directories created in or moved into a watched directory
+    pub create : bool,
+    /// files written to
+    pub write : bool,
+    /// files or directories deleted from or moved out of a watched directory, which only free
+    /// space but may still be worth reacting to, e.g. to prune directories
+    pub delete : bool,
+}
+
+impl Default for WatchEvents {
+    fn default() -> Self {
+        WatchEvents { create : true, write : true, delete : false }
+    }
+}
+
+impl FromStr for WatchEvents {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = WatchEvents { create : false, write : false, delete : false };
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "create" => events.create = true,
+                "write" => events.write = true,
+                "delete" => events.delete = true,
+                _ => return Err(format!("invalid watch event {:?}: expected create, write or delete", name)),
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::WatchEvents;
+    use nix::errno::Errno;
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// How many times longer than the debounce interval a burst of events may keep a run
+    /// waiting, so that a steady stream of writes still leads to runs rather than postponing them
+    /// forever.
+    const MAX_DEBOUNCES_PER_BATCH : u32 = 10;
+
+    /// How often to check for an interrupt, and ping systemd's watchdog, while waiting for events.
+    const POLL_INTERVAL : Duration = Duration::from_millis(100);
+
+    /// Watches every directory beneath the top-level directories through inotify, adding watches
+    /// for directories as they're created.
+    pub struct Watcher {
+        inotify : Inotify,
+        /// what each watch is on, to find the directories created within it
+        directories : HashMap<WatchDescriptor, PathBuf>,
+        events : WatchEvents,
+    }
+
+    /// The events we ask for, which always include those needed to watch new directories.
+    fn mask(events: WatchEvents) -> AddWatchFlags {
+        let mut mask = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_ONLYDIR | AddWatchFlags::IN_DONT_FOLLOW;
+        if events.write {
+            mask |= AddWatchFlags::IN_MODIFY;
+        }
+        if events.delete {
+            mask |= AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM;
+        }
+        mask
+    }
+
+    impl Watcher {
+        pub fn new(roots: &[PathBuf], events: WatchEvents) -> io::Result<Self> {
+            let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+            let mut watcher = Watcher { inotify, directories : HashMap::new(), events };
+            for root in roots {
+                watcher.watch_tree(root)?;
+            }
+            log::debug!("Watching {} directories for changes", watcher.directories.len());
+            Ok(watcher)
+        }
+
+        /// Watch `root` and every directory beneath it. Directories which vanish before they can
+        /// be watched are passed over, but running out of watches is an error.
+        fn watch_tree(&mut self, root: &Path) -> io::Result<()> {
+            let mask = mask(self.events);
+            for entry in walkdir::WalkDir::new(root).into_iter().filter_map(Result::ok).filter(|entry| entry.file_type().is_dir()) {
+                match self.inotify.add_watch(entry.path(), mask) {
+                    Ok(watch) => {
+                        self.directories.insert(watch, entry.into_path());
+                    }
+                    Err(Errno::ENOSPC) => {
+                        return Err(io::Error::other("the limit on inotify watches was reached, which fs.inotify.max_user_watches sets"));
+                    }
+                    Err(Errno::ENOMEM) => return Err(io::Error::other("the kernel ran out of memory for inotify watches")),
+                    Err(err) => log::debug!("Not watching {}: {}", entry.path().display(), err),
+                }
+            }
+            Ok(())
+        }
+
+        /// Read the events queued so far, returning whether any matched. Directories created since
+        /// are watched in turn.
+        fn drain(&mut self) -> io::Result<bool> {
+            let mut matched = false;
+            loop {
+                let events = match self.inotify.read_events() {
+                    Ok(events) => events,
+                    Err(Errno::EAGAIN) => return Ok(matched),
+                    Err(err) => return Err(err.into()),
+                };
+                for event in events {
+                    if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                        // some events were lost, so whichever they were, the space is worth checking
+                        log::debug!("The inotify queue overflowed");
+                        matched = true;
+                        continue;
+                    }
+                    if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                        self.directories.remove(&event.wd);
+                        continue;
+                    }
+                    let created = event.mask.intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO);
+                    if created && event.mask.contains(AddWatchFlags::IN_ISDIR) {
+                        if let (Some(directory), Some(name)) = (self.directories.get(&event.wd), &event.name) {
+                            let directory = directory.join(name);
+                            self.watch_tree(&directory)?;
+                        }
+                    }
+                    matched |= (created && self.events.create)
+                        || event.mask.contains(AddWatchFlags::IN_MODIFY)
+                        || event.mask.intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM);
+                }
+            }
+        }
+
+        pub fn wait(&mut self, debounce: Duration, interrupt: &AtomicBool, mut tick: impl FnMut()) -> io::Result<bool> {
+            // wait for the first matching event, then for the burst it begins to quieten down
+            let mut batch_started = None;
+            let mut last_event = Instant::now();
+            loop {
+                if interrupt.load(Ordering::SeqCst) {
+                    return Ok(false);
+                }
+                if self.drain()? {
+                    last_event = Instant::now();
+                    batch_started.get_or_insert(last_event);
+                }
+                if let Some(batch_started) = batch_started {
+                    let now = Instant::now();
+                    if now - last_event >= debounce || now - batch_started >= debounce * MAX_DEBOUNCES_PER_BATCH {
+                        return Ok(true);
+                    }
+                }
+                tick();
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use super::WatchEvents;
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    pub enum Watcher {}
+
+    impl Watcher {
+        pub fn new(_roots: &[PathBuf], _events: WatchEvents) -> io::Result<Self> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "watching for changes is only supported on Linux"))
+        }
+
+        pub fn wait(&mut self, _debounce: Duration, _interrupt: &AtomicBool, _tick: impl FnMut()) -> io::Result<bool> {
+            match *self {}
+        }
+    }
+}
+
+/// Watches the top-level directories for the changes chosen by --watch-events, for --watch.
+pub struct Watcher(platform::Watcher);
+
+impl Watcher {
+    /// Start watching `roots` and everything beneath them. Fails where the platform can't watch
+    /// for changes, or there aren't enough inotify watches for every directory.
+    pub fn new(roots: &[PathBuf], events: WatchEvents) -> io::Result<Self> {
+        platform::Watcher::new(roots, events).map(Watcher)
+    }
+
+    /// Wait until a matching change is followed by `debounce` without any more, or a burst of
+    /// them has gone on for ten times as long, calling `tick` regularly in the meantime. Returns
+    /// false if `interrupt` was set first, and fails if new directories can't be watched, e.g.
+    /// because inotify watches have run out.
+    pub fn wait(&mut self, debounce: Duration, interrupt: &AtomicBool, tick: impl FnMut()) -> io::Result<bool> {
+        self.0.wait(debounce, interrupt, tick)
+    }
+}
@@ -0,0 +1,38 @@
+use chrono::{Local, TimeZone};
+use lru::FileInfo;
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+fn file(rank: i128, path: &str, size: u64) -> FileInfo {
+    FileInfo {
+        rank : (rank, 0),
+        accessed : Local.timestamp_opt(rank as i64, 0).unwrap(),
+        frequency : 0,
+        size,
+        length : size,
+        inode : None,
+        path : PathBuf::from(path),
+        links : Vec::new(),
+    }
+}
+
+#[test]
+fn files_are_equal_exactly_when_they_compare_as_equal() {
+    let files = [
+        file(1, "a", 10),
+        file(1, "a", 20),
+        file(1, "b", 10),
+        file(2, "a", 10),
+        file(2, "b", 20),
+    ];
+    for a in &files {
+        for b in &files {
+            assert_eq!(a == b, a.cmp(b) == Ordering::Equal);
+            assert_eq!(a.partial_cmp(b), Some(a.cmp(b)));
+        }
+    }
+    // the rank decides first, then the path, then the size
+    let mut sorted : Vec<_> = files.iter().rev().collect();
+    sorted.sort();
+    assert!(sorted.iter().zip(&files).all(|(a, b)| *a == b));
+}
@@ -0,0 +1,793 @@
+//! Runs of the reclaim routine against an in-memory filesystem, whose files have controlled sizes
+//! and timestamps and whose free space can change while a run is planning, and against real
+//! directory trees where disposing of files must touch the files themselves.
+
+use chrono::Duration;
+use lru::{largest, reclaim, FileSystem, FixedClock, FutureTime, MemoryFileSystem, RealFileSystem, ReclaimConfig, RetentionBand, RetentionPolicy, Target};
+use std::fs::{self, File, FileTimes};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// The size of every real file written.
+const FILE_SIZE : u64 = 64 << 10;
+
+const DAY : std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Seeds the contents of each file written, so that no two files are alike.
+static SEED : AtomicU64 = AtomicU64::new(0);
+
+/// A directory beneath the system's temporary directory, removed along with everything in it when
+/// dropped.
+struct TempDir {
+    path : PathBuf,
+}
+
+impl TempDir {
+    fn new() -> Self {
+        static COUNT : AtomicUsize = AtomicUsize::new(0);
+        let name = format!("lru-test-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::SeqCst));
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// Write a file of `FILE_SIZE` bytes at `name`, last accessed and modified `age` ago.
+    fn file(&self, name: &str, age: std::time::Duration) -> PathBuf {
+        let path = self.path.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // NOTE: the contents are random, so that overwriting them can be told apart
+        let mut state = 0x2545_f491_4f6c_dd1d_u64 ^ SEED.fetch_add(1, Ordering::SeqCst);
+        let contents : Vec<u8> = (0..FILE_SIZE).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        }).collect();
+        fs::write(&path, contents).unwrap();
+        let now = SystemTime::now();
+        File::options().write(true).open(&path).unwrap()
+            .set_times(FileTimes::new().set_accessed(now - age).set_modified(now - age))
+            .unwrap();
+        path
+    }
+
+    /// A config whose target is `n_files` files' worth of space, counting only what the run frees.
+    fn config(&self, n_files: f64) -> ReclaimConfig {
+        let mut config = ReclaimConfig::new(&self.path, Target::Bytes((FILE_SIZE as f64 * n_files) as u64));
+        config.filesystem = Arc::new(Isolated::default());
+        config
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The names of the files beneath `root`, in order.
+fn survivors(root: &Path) -> Vec<String> {
+    let mut names : Vec<_> = walkdir::WalkDir::new(root).into_iter()
+        .map(Result::unwrap)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.path().strip_prefix(root).unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// The real filesystem, but with no space free other than what the run itself frees, so that
+/// nothing else writing to the same disk, the other tests included, can change what it deletes.
+#[derive(Default)]
+struct Isolated {
+    freed : AtomicU64,
+}
+
+impl FileSystem for Isolated {
+    fn walk(&self, root: &Path, options: &lru::WalkOptions) -> Box<dyn Iterator<Item = Result<lru::WalkEntry, lru::WalkError>>> {
+        RealFileSystem.walk(root, options)
+    }
+
+    fn stat(&self, path: &Path, follow_symlinks: bool) -> io::Result<lru::FileStat> {
+        RealFileSystem.stat(path, follow_symlinks)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        RealFileSystem.canonicalize(path)
+    }
+
+    fn remove(&self, disposal: &lru::Disposal, root: &Path, path: &Path) -> io::Result<()> {
+        let len = RealFileSystem.stat(path, false)?.len;
+        RealFileSystem.remove(disposal, root, path)?;
+        self.freed.fetch_add(len, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        RealFileSystem.remove_dir(path)
+    }
+
+    fn available_space(&self, _path: &Path) -> io::Result<u64> {
+        Ok(self.freed.load(Ordering::SeqCst))
+    }
+
+    fn total_space(&self, path: &Path) -> io::Result<u64> {
+        RealFileSystem.total_space(path)
+    }
+
+    fn available_inodes(&self, _path: &Path) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    fn is_undeletable(&self, path: &Path) -> bool {
+        RealFileSystem.is_undeletable(path)
+    }
+}
+
+// NOTE: only unix lets a file be read through an open handle once it's been deleted
+#[cfg(unix)]
+#[test]
+fn shredding_overwrites_files_before_deleting_them() {
+    use std::io::Read;
+    let dir = TempDir::new();
+    let path = dir.file("a", DAY);
+    let mut handle = File::open(&path).unwrap();
+    let mut config = dir.config(0.5);
+    config.disposal = lru::Disposal::Shred(2);
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [path]);
+    let mut contents = Vec::new();
+    handle.read_to_end(&mut contents).unwrap();
+    assert_eq!(contents.len() as u64, FILE_SIZE);
+    assert!(contents.iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn moving_never_replaces_a_file_already_archived() {
+    let dir = TempDir::new();
+    let archived = dir.file("archive/a", 3 * DAY);
+    let earlier = fs::read(&archived).unwrap();
+    dir.file("cache/a", 2 * DAY);
+    dir.file("cache/b", DAY);
+    let mut config = dir.config(10.0);
+    config.path = dir.path.join("cache");
+    config.disposal = lru::Disposal::MoveTo(dir.path.join("archive"));
+    config.delete_retries = 0;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [dir.path.join("cache/b")]);
+    assert_eq!(report.failed.values().sum::<usize>(), 1);
+    assert_eq!(survivors(&dir.path), ["archive/a", "archive/b", "cache/a"]);
+    assert_eq!(fs::read(&archived).unwrap(), earlier);
+}
+
+/// A `MemoryFileSystem` on which another process frees `freed_later` bytes as soon as the run
+/// first queries the free space, i.e. while it's walking the tree.
+struct FreedDuringWalk {
+    memory : MemoryFileSystem,
+    freed_later : u64,
+    queries : AtomicUsize,
+}
+
+impl FileSystem for FreedDuringWalk {
+    fn walk(&self, root: &Path, options: &lru::WalkOptions) -> Box<dyn Iterator<Item = Result<lru::WalkEntry, lru::WalkError>>> {
+        self.memory.walk(root, options)
+    }
+
+    fn stat(&self, path: &Path, follow_symlinks: bool) -> io::Result<lru::FileStat> {
+        self.memory.stat(path, follow_symlinks)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.memory.canonicalize(path)
+    }
+
+    fn remove(&self, disposal: &lru::Disposal, root: &Path, path: &Path) -> io::Result<()> {
+        self.memory.remove(disposal, root, path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.memory.remove_dir(path)
+    }
+
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        let freed = if self.queries.fetch_add(1, Ordering::SeqCst) == 0 { 0 } else { self.freed_later };
+        Ok(self.memory.available_space(path)? + freed)
+    }
+
+    fn total_space(&self, path: &Path) -> io::Result<u64> {
+        self.memory.total_space(path)
+    }
+
+    fn available_inodes(&self, path: &Path) -> io::Result<Option<u64>> {
+        self.memory.available_inodes(path)
+    }
+
+    fn is_undeletable(&self, path: &Path) -> bool {
+        self.memory.is_undeletable(path)
+    }
+}
+
+#[test]
+fn plans_for_the_space_still_needed_once_the_walk_is_done() {
+    let memory = MemoryFileSystem::new(1000);
+    let now = SystemTime::now();
+    for (name, age) in [("a", 5), ("b", 4), ("c", 3), ("d", 2), ("e", 1)] {
+        memory.add_file(Path::new("/cache").join(name), 100, now - age * DAY);
+    }
+    // 500 bytes are available, and the target needs 300 more, until 150 of them are freed by
+    // someone else during the walk
+    let filesystem = Arc::new(FreedDuringWalk { memory, freed_later : 150, queries : AtomicUsize::new(0) });
+    let mut config = ReclaimConfig::new("/cache", Target::Bytes(800));
+    config.filesystem = filesystem.clone();
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [Path::new("/cache/a"), Path::new("/cache/b")]);
+    assert_eq!(filesystem.memory.files(), [Path::new("/cache/c"), Path::new("/cache/d"), Path::new("/cache/e")]);
+    assert!(!report.target_unmet);
+}
+
+#[test]
+fn plans_exactly_the_files_needed_when_every_size_differs() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    // oldest first: the heap must hold the oldest files totalling at least the 250 bytes needed,
+    // and no more, however the sizes of the newer files it considered along the way compare
+    for (name, size, age) in [("a", 200, 6), ("b", 20, 5), ("c", 300, 4), ("d", 10, 3), ("e", 400, 2)] {
+        memory.add_file(Path::new("/cache").join(name), size, now - age * DAY);
+    }
+    let mut config = ReclaimConfig::new("/cache", Target::Bytes(320));
+    config.filesystem = memory.clone();
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [Path::new("/cache/a"), Path::new("/cache/b"), Path::new("/cache/c")]);
+    assert_eq!(report.bytes_freed, 520);
+}
+
+/// A config reclaiming every file of `memory` beneath /cache, as of `now`.
+fn reclaim_everything(memory: &Arc<MemoryFileSystem>, now: SystemTime) -> ReclaimConfig {
+    let mut config = ReclaimConfig::new("/cache", Target::Bytes(memory.total_space(Path::new("/cache")).unwrap()));
+    config.filesystem = memory.clone();
+    config.clock = Arc::new(FixedClock(now.into()));
+    config
+}
+
+#[test]
+fn deletes_the_least_recently_used_files_until_the_target_is_met() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/a", 100, now - 4 * DAY);
+    memory.add_file("/cache/nested/b", 100, now - 3 * DAY);
+    memory.add_file("/cache/c", 100, now - 2 * DAY);
+    memory.add_file("/cache/nested/d", 100, now - DAY);
+    // one and a half files' worth more than the 600 bytes free can only be freed by deleting two
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(750));
+    let report = reclaim(&config).unwrap();
+    assert_eq!(memory.files(), [Path::new("/cache/c"), Path::new("/cache/nested/d")]);
+    assert_eq!(report.deleted, [Path::new("/cache/a"), Path::new("/cache/nested/b")]);
+    assert_eq!(report.bytes_freed, 200);
+    assert!(!report.target_unmet);
+}
+
+#[test]
+fn never_deletes_files_newer_than_older_than() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (name, age) in [("a", 4), ("b", 3), ("c", 2), ("d", 1)] {
+        memory.add_file(Path::new("/cache").join(name), 100, now - age * DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.older_than = Duration::hours(60);
+    let report = reclaim(&config).unwrap();
+    assert_eq!(memory.files(), [Path::new("/cache/c"), Path::new("/cache/d")]);
+    assert_eq!(report.candidates, 2);
+    assert!(report.target_unmet);
+}
+
+#[test]
+fn deletes_nothing_when_every_file_is_too_new() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/a", 100, now - 2 * DAY);
+    memory.add_file("/cache/b", 100, now - DAY);
+    let mut config = reclaim_everything(&memory, now);
+    config.older_than = Duration::days(7);
+    let report = reclaim(&config).unwrap();
+    assert_eq!(memory.files(), [Path::new("/cache/a"), Path::new("/cache/b")]);
+    assert_eq!(report.candidates, 0);
+    assert!(report.deleted.is_empty());
+    assert!(report.target_unmet);
+}
+
+#[test]
+fn deletes_nothing_when_the_target_is_already_met() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/a", 100, now - 2 * DAY);
+    memory.add_file("/cache/b", 100, now - DAY);
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(0));
+    let report = reclaim(&config).unwrap();
+    assert_eq!(memory.files(), [Path::new("/cache/a"), Path::new("/cache/b")]);
+    assert!(report.deleted.is_empty());
+    assert!(!report.target_unmet);
+}
+
+#[test]
+fn dry_runs_report_the_same_files_without_deleting_them() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (name, age) in [("a", 3), ("b", 2), ("c", 1)] {
+        memory.add_file(Path::new("/cache").join(name), 100, now - age * DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(850));
+    config.dry_run = true;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(memory.files().len(), 3);
+    assert_eq!(report.deleted, [Path::new("/cache/a"), Path::new("/cache/b")]);
+}
+
+#[test]
+fn files_used_either_way_count_as_recently_used_by_newest() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (name, accessed, modified) in [("read", 1, 5), ("written", 5, 1), ("idle", 3, 4)] {
+        let path = Path::new("/cache").join(name);
+        memory.add_file(&path, 100, now - accessed * DAY);
+        memory.set_modified(&path, Some(now - modified * DAY));
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(750));
+    config.by = lru::Timestamp::Newest;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(memory.files(), [Path::new("/cache/read"), Path::new("/cache/written")]);
+    assert_eq!(report.deleted, [Path::new("/cache/idle")]);
+}
+
+#[test]
+fn every_file_is_a_candidate_without_a_minimum_age() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/now", 100, now);
+    memory.add_file("/cache/future", 100, now + DAY);
+    let report = reclaim(&reclaim_everything(&memory, now)).unwrap();
+    assert_eq!(report.candidates, 2);
+    assert!(memory.files().is_empty());
+}
+
+#[test]
+fn refuses_a_file_in_place_of_the_top_level_directory() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    memory.add_file("/cache/a", 100, SystemTime::now() - DAY);
+    let mut config = ReclaimConfig::new("/cache/a", Target::Bytes(1000));
+    config.filesystem = memory.clone();
+    assert!(reclaim(&config).is_err());
+    assert_eq!(memory.files(), [Path::new("/cache/a")]);
+}
+
+#[test]
+fn files_exactly_the_minimum_age_are_old_enough() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/old_enough", 100, now - DAY);
+    memory.add_file("/cache/too_new", 100, now - DAY + std::time::Duration::from_millis(1));
+    let mut config = reclaim_everything(&memory, now);
+    config.older_than = Duration::days(1);
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [Path::new("/cache/old_enough")]);
+    assert_eq!(memory.files(), [Path::new("/cache/too_new")]);
+}
+
+#[test]
+fn the_target_is_judged_by_the_free_space_after_the_run() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/a", 300, now - 2 * DAY);
+    memory.add_file("/cache/b", 300, now - DAY);
+    memory.add_file("/cache/c", 300, now);
+    memory.fail_removal("/cache/a", io::ErrorKind::PermissionDenied);
+    let mut config = ReclaimConfig::new("/cache", Target::Percent(40.0));
+    config.filesystem = memory.clone();
+    config.delete_retries = 0;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.bytes_freed, 0);
+    assert_eq!((report.free_before, report.free_after), (100, 100));
+    assert_eq!(report.target, Some(400));
+    assert_eq!(report.target_met, Some(false));
+
+    // once the file can be deleted, the same run meets the target
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    for (name, age) in [("a", 2), ("b", 1), ("c", 0)] {
+        memory.add_file(Path::new("/cache").join(name), 300, now - age * DAY);
+    }
+    config.filesystem = memory.clone();
+    let report = reclaim(&config).unwrap();
+    assert_eq!((report.free_before, report.free_after), (100, 400));
+    assert_eq!(report.target_met, Some(true));
+}
+
+#[test]
+fn stops_once_there_is_no_room_to_dispose_of_files_into() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (name, age) in [("a", 3), ("b", 2), ("c", 1)] {
+        memory.add_file(Path::new("/cache").join(name), 300, now - age * DAY);
+    }
+    memory.fail_removal("/cache/b", io::ErrorKind::StorageFull);
+    let mut config = reclaim_everything(&memory, now);
+    config.disposal = lru::Disposal::MoveTo(PathBuf::from("/archive"));
+    config.delete_retries = 0;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [Path::new("/cache/a")]);
+    assert_eq!(report.failed.values().sum::<usize>(), 1);
+    // the file which couldn't be moved, and every later one, is left in place
+    assert_eq!(memory.files(), [Path::new("/archive/a"), Path::new("/cache/b"), Path::new("/cache/c")]);
+}
+
+#[test]
+fn undeletable_files_are_never_planned() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (name, age) in [("a", 3), ("b", 2), ("c", 1)] {
+        memory.add_file(Path::new("/cache").join(name), 300, now - age * DAY);
+    }
+    memory.make_undeletable("/cache/a");
+    let mut config = ReclaimConfig::new("/cache", Target::Bytes(800));
+    config.filesystem = memory.clone();
+    let report = reclaim(&config).unwrap();
+    // the oldest file is passed over rather than failing, and its space isn't counted on
+    assert_eq!(report.candidates, 2);
+    assert_eq!(report.deleted, [Path::new("/cache/b"), Path::new("/cache/c")]);
+    assert!(report.failed.is_empty());
+    assert_eq!(report.target_met, Some(false));
+}
+
+#[test]
+fn only_included_files_are_planned_and_excludes_win() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for path in ["/cache/a.tmp", "/cache/b.dat", "/cache/scratch/c.dat", "/cache/scratch/keep.tmp", "/cache/precious/d.tmp.bak"] {
+        memory.add_file(path, 100, now - DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.include = vec!["*.tmp".to_string(), "scratch/**".to_string()];
+    config.exclude = vec!["**/keep.tmp".to_string()];
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.candidates, 2);
+    assert_eq!(memory.files(), [Path::new("/cache/b.dat"), Path::new("/cache/precious/d.tmp.bak"), Path::new("/cache/scratch/keep.tmp")]);
+}
+
+#[test]
+fn files_below_the_maximum_depth_are_untouched() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for path in ["/cache/one", "/cache/a/two", "/cache/a/b/three"] {
+        memory.add_file(path, 100, now - DAY);
+    }
+    // the top-level directory is at depth 0, so its files are at depth 1
+    let mut config = reclaim_everything(&memory, now);
+    config.max_depth = Some(2);
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.candidates, 2);
+    assert_eq!(memory.files(), [Path::new("/cache/a/b/three")]);
+}
+
+#[test]
+fn unreadable_metadata_is_skipped_and_missing_access_times_follow_the_policy() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/a", 100, now - 3 * DAY);
+    memory.add_file("/cache/b_unreadable", 100, now - 2 * DAY);
+    memory.add_file("/cache/c_no_atime", 100, now - DAY);
+    memory.fail_stat("/cache/b_unreadable", io::ErrorKind::PermissionDenied);
+    memory.set_accessed(Path::new("/cache/c_no_atime"), None);
+    let mut config = reclaim_everything(&memory, now);
+    config.dry_run = true;
+    config.on_missing_atime = lru::MissingAtime::Skip;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [Path::new("/cache/a")]);
+    assert_eq!(report.skipped.values().sum::<usize>(), 1);
+    assert_eq!(report.skipped.get("permission denied"), Some(&1));
+
+    config.on_missing_atime = lru::MissingAtime::Mtime;
+    assert_eq!(reclaim(&config).unwrap().deleted, [Path::new("/cache/a"), Path::new("/cache/c_no_atime")]);
+    config.on_missing_atime = lru::MissingAtime::Error;
+    assert!(reclaim(&config).is_err());
+}
+
+#[test]
+fn hard_linked_files_are_counted_once_and_deleted_by_every_link() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/a", 100, now - 3 * DAY);
+    memory.add_link(Path::new("/cache/a"), "/cache/nested/a_link");
+    memory.add_file("/cache/b", 100, now - 2 * DAY);
+    memory.add_file("/cache/c", 100, now - DAY);
+    memory.add_link(Path::new("/cache/c"), "/elsewhere/c_link");
+    assert_eq!(memory.available_space(Path::new("/cache")).unwrap(), 700);
+    let report = reclaim(&reclaim_everything(&memory, now)).unwrap();
+    // c can't be freed while it's linked from outside the top-level directory
+    assert_eq!(report.candidates, 2);
+    let mut deleted = report.deleted.clone();
+    deleted.sort();
+    assert_eq!(deleted, [Path::new("/cache/a"), Path::new("/cache/b"), Path::new("/cache/nested/a_link")]);
+    assert_eq!(report.bytes_freed, 200);
+    assert_eq!(memory.files(), [Path::new("/cache/c"), Path::new("/elsewhere/c_link")]);
+}
+
+#[test]
+fn files_smaller_than_the_minimum_size_survive() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (path, len) in [("/cache/large_a", 200), ("/cache/tiny_b", 1), ("/cache/large_c", 100), ("/cache/tiny_d", 99), ("/cache/empty", 0)] {
+        memory.add_file(path, len, now - DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.min_file_size = 100;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.candidates, 2);
+    assert_eq!(report.bytes_freed, 300);
+    assert_eq!(memory.files(), [Path::new("/cache/empty"), Path::new("/cache/tiny_b"), Path::new("/cache/tiny_d")]);
+}
+
+#[test]
+fn files_created_first_go_first_by_creation_time() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    // the files were created in the reverse of the order they were last used in
+    for (name, used, created) in [("a", 3, 1), ("b", 2, 2), ("c", 1, 3)] {
+        let path = PathBuf::from("/cache").join(name);
+        memory.add_file(&path, 100, now - used * DAY);
+        memory.set_created(&path, Some(now - created * DAY));
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(750));
+    config.dry_run = true;
+    assert_eq!(reclaim(&config).unwrap().deleted, [Path::new("/cache/a")]);
+    config.by = lru::Timestamp::Created;
+    assert_eq!(reclaim(&config).unwrap().deleted, [Path::new("/cache/c")]);
+
+    // without a creation time, the file falls back to when it was last modified
+    memory.set_created(Path::new("/cache/a"), None);
+    assert_eq!(reclaim(&config).unwrap().deleted, [Path::new("/cache/a")]);
+}
+
+#[test]
+fn read_only_files_are_kept_unless_forced() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (name, age) in [("a", 2), ("b", 1)] {
+        memory.add_file(Path::new("/cache").join(name), 300, now - age * DAY);
+    }
+    memory.make_readonly(Path::new("/cache/a"));
+    let mut config = ReclaimConfig::new("/cache", Target::Bytes(500));
+    config.filesystem = memory.clone();
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [Path::new("/cache/b")]);
+
+    // deleting only takes permission to write the directory, as --force allows
+    let mut config = ReclaimConfig::new("/cache", Target::Bytes(800));
+    config.filesystem = memory.clone();
+    config.skip_readonly = false;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [Path::new("/cache/a")]);
+}
+
+#[test]
+fn groups_are_deleted_whole_or_not_at_all() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for (name, age) in [("job1/a", 9), ("job1/keep", 1), ("job2/a", 8), ("job2/b", 0), ("job3/a", 7), ("loose", 5)] {
+        memory.add_file(Path::new("/cache").join(name), 100, now - age * DAY);
+    }
+    let mut config = ReclaimConfig::new("/cache", Target::Bytes(550));
+    config.filesystem = memory.clone();
+    config.group_by = Some("^(job[0-9]+)/".to_string());
+    config.exclude = vec!["**/keep".to_string()];
+    let report = reclaim(&config).unwrap();
+    // job1 can't be deleted whole, while job2 goes first for its oldest file, along with its newest
+    assert_eq!(report.deleted, [Path::new("/cache/job2/a"), Path::new("/cache/job2/b")]);
+    assert_eq!(report.candidates, 3);
+}
+
+#[test]
+fn files_used_in_the_future_follow_the_policy() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/a_far", 100, now + 30 * DAY);
+    memory.add_file("/cache/b_near", 100, now + DAY);
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(850));
+    config.future_time = FutureTime::Skip;
+    assert!(reclaim(&config).unwrap().deleted.is_empty());
+
+    // once clamped to now, both rank alike and are told apart by name alone
+    config.future_time = FutureTime::Clamp;
+    config.dry_run = true;
+    assert_eq!(reclaim(&config).unwrap().deleted, [Path::new("/cache/a_far")]);
+    config.future_time = FutureTime::Warn;
+    assert_eq!(reclaim(&config).unwrap().deleted, [Path::new("/cache/b_near")]);
+}
+
+#[test]
+fn deleting_on_several_threads_accounts_for_every_file() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for age in 1..=9 {
+        memory.add_file(format!("/cache/{}", age), 100, now - age * DAY);
+    }
+    memory.fail_removal("/cache/5", io::ErrorKind::PermissionDenied);
+    let mut config = reclaim_everything(&memory, now);
+    config.delete_threads = 4;
+    config.delete_retries = 0;
+    let report = reclaim(&config).unwrap();
+    // files may finish in any order, but each is counted exactly once
+    let mut deleted = report.deleted.clone();
+    deleted.sort();
+    assert_eq!(deleted, (1..=9).filter(|&age| age != 5).map(|age| PathBuf::from(format!("/cache/{}", age))).collect::<Vec<_>>());
+    assert_eq!(report.bytes_freed, 800);
+    assert_eq!(report.failed.values().sum::<usize>(), 1);
+    assert_eq!(memory.files(), [Path::new("/cache/5")]);
+}
+
+#[test]
+fn low_memory_runs_delete_the_oldest_files_to_reach_the_target() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for age in 1..=9 {
+        memory.add_file(format!("/cache/{}", age), 100, now - age * DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(500));
+    config.low_memory = true;
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.candidates, 9);
+    assert_eq!(report.bytes_freed, 400);
+    assert_eq!(memory.files(), (1..=5).map(|age| PathBuf::from(format!("/cache/{}", age))).collect::<Vec<_>>());
+
+    config.keep_newest = 1;
+    assert!(reclaim(&config).is_err());
+}
+
+#[test]
+fn the_largest_old_enough_files_are_listed_without_deleting_anything() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/small", 50, now - 3 * DAY);
+    memory.add_file("/cache/medium", 100, now - 3 * DAY);
+    memory.add_file("/cache/large", 200, now - 3 * DAY);
+    memory.add_file("/cache/new", 300, now);
+    let mut config = reclaim_everything(&memory, now);
+    config.older_than = Duration::days(1);
+    let largest = largest(&config, 2).unwrap();
+    let paths : Vec<_> = largest.files.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(paths, [Path::new("/cache/large"), Path::new("/cache/medium")]);
+    assert_eq!((largest.candidates, largest.candidate_bytes), (3, 350));
+
+    // a protected retention band keeps its files off the list however large they are
+    let mut config = reclaim_everything(&memory, now);
+    config.retention = vec![
+        RetentionBand { younger_than : Some(Duration::days(1)), policy : RetentionPolicy::Protect },
+        RetentionBand { younger_than : None, policy : RetentionPolicy::Pressure },
+    ];
+    let largest = lru::largest(&config, 2).unwrap();
+    let paths : Vec<_> = largest.files.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(paths, [Path::new("/cache/large"), Path::new("/cache/medium")]);
+    assert_eq!((largest.candidates, largest.candidate_bytes), (3, 350));
+    assert_eq!(memory.files().len(), 4);
+}
+
+#[test]
+fn hard_linked_files_are_listed_once_unless_linked_from_outside() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    memory.add_file("/cache/linked", 300, now - 3 * DAY);
+    memory.add_link(Path::new("/cache/linked"), "/cache/nested/linked_too");
+    memory.add_file("/cache/shared", 200, now - 3 * DAY);
+    memory.add_link(Path::new("/cache/shared"), "/elsewhere/shared");
+    memory.add_file("/cache/plain", 100, now - 3 * DAY);
+    let largest = largest(&reclaim_everything(&memory, now), 5).unwrap();
+    // deleting a file linked from outside the top-level directory frees nothing, so a run never would
+    let paths : Vec<_> = largest.files.iter().map(|file| file.paths().cloned().collect::<Vec<_>>()).collect();
+    assert_eq!(paths, [vec![PathBuf::from("/cache/linked"), PathBuf::from("/cache/nested/linked_too")], vec![PathBuf::from("/cache/plain")]]);
+    assert_eq!((largest.candidates, largest.candidate_bytes), (2, 400));
+}
+
+#[cfg(unix)]
+#[test]
+fn files_used_since_the_walk_index_recorded_them_are_kept() {
+    let dir = TempDir::new();
+    let a = dir.file("a", 4 * DAY);
+    dir.file("nested/b", 3 * DAY);
+    dir.file("c", 2 * DAY);
+    dir.file("nested/d", DAY);
+    // directories changed moments before they're walked are never cached
+    std::thread::sleep(std::time::Duration::from_millis(2100));
+    let index = dir.path.join(".lru-walk-index.json");
+    let mut config = dir.config(0.5);
+    config.walk_index = Some(index.clone());
+    config.dry_run = true;
+    assert_eq!(reclaim(&config).unwrap().deleted, std::slice::from_ref(&a));
+    assert!(index.exists());
+
+    // reading a file doesn't change its directory, so only the check before deleting it notices
+    File::options().write(true).open(&a).unwrap().set_times(FileTimes::new().set_accessed(SystemTime::now())).unwrap();
+    let mut config = dir.config(0.5);
+    config.walk_index = Some(index.clone());
+    let report = reclaim(&config).unwrap();
+    assert_eq!(report.deleted, [dir.path.join("nested/b")]);
+    assert_eq!(survivors(&dir.path), [".lru-walk-index.json", "a", "c", "nested/d"]);
+}
+
+#[test]
+fn retention_bands_purge_then_pressure_and_never_touch_protected_files() {
+    let now = SystemTime::now();
+    let bands = vec![
+        RetentionBand { younger_than : Some(Duration::days(7)), policy : RetentionPolicy::Protect },
+        RetentionBand { younger_than : Some(Duration::days(30)), policy : RetentionPolicy::Pressure },
+        RetentionBand { younger_than : None, policy : RetentionPolicy::Purge },
+    ];
+    let files = [("a", 50), ("b", 40), ("c", 20), ("d", 10), ("e", 1)];
+    // purging a and b leaves 700 bytes free, so only c is needed to reach 750
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    for (name, age) in files {
+        memory.add_file(Path::new("/cache").join(name), 100, now - age * DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.target = Some(Target::Bytes(750));
+    config.retention = bands.clone();
+    reclaim(&config).unwrap();
+    assert_eq!(memory.files(), [Path::new("/cache/d"), Path::new("/cache/e")]);
+
+    // protected files are kept even when the target can't be met without them
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    for (name, age) in files {
+        memory.add_file(Path::new("/cache").join(name), 100, now - age * DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.retention = bands;
+    let report = reclaim(&config).unwrap();
+    assert!(report.target_unmet);
+    assert_eq!(memory.files(), [Path::new("/cache/e")]);
+}
+
+#[test]
+fn deletions_are_paced_from_when_deleting_starts_not_from_the_walk() {
+    let memory = Arc::new(MemoryFileSystem::new(1000));
+    let now = SystemTime::now();
+    for name in ["a", "b", "c", "d"] {
+        memory.add_file(Path::new("/cache").join(name), 100, now - DAY);
+    }
+    let mut config = reclaim_everything(&memory, now);
+    config.max_files_per_second = Some(10.0);
+    // as slow a walk as would otherwise have earned every deletion in advance
+    config.on_planned = Some(Arc::new(|_| std::thread::sleep(std::time::Duration::from_millis(600))));
+    let mut disposed_at = Vec::new();
+    lru::reclaim_with(&config, |_| disposed_at.push(Instant::now())).unwrap();
+    assert_eq!(disposed_at.len(), 4);
+    for pair in disposed_at.windows(2) {
+        assert!(pair[1] - pair[0] >= std::time::Duration::from_millis(80), "deleted {:?} apart", pair[1] - pair[0]);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn excluded_symlinks_keep_their_targets_outside_the_top_level_directory() {
+    let dir = TempDir::new();
+    let pinned = dir.file("elsewhere/pinned", 3 * DAY);
+    let followed = dir.file("elsewhere/followed", 2 * DAY);
+    let old = dir.file("cache/old", DAY);
+    std::os::unix::fs::symlink(&pinned, dir.path.join("cache/pinned")).unwrap();
+    std::os::unix::fs::symlink(&followed, dir.path.join("cache/followed")).unwrap();
+    let mut config = dir.config(10.0);
+    config.path = dir.path.join("cache");
+    config.follow_symlinks = true;
+    config.exclude = vec!["pinned".to_string()];
+    let report = reclaim(&config).unwrap();
+    // the filters and the report see each link's own path, while it's the target which goes
+    assert_eq!(report.deleted, [dir.path.join("cache/followed"), old]);
+    assert_eq!(survivors(&dir.path), ["elsewhere/pinned"]);
+    assert!(dir.path.join("cache/pinned").exists());
+}
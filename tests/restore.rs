@@ -0,0 +1,50 @@
+//! Runs of the restore subcommand, against a trash kept in a temporary directory rather than the
+//! user's own.
+
+#![cfg(target_os = "linux")]
+
+use std::fs::{self, File, FileTimes};
+use std::path::Path;
+use std::process::{Command, Output};
+use std::time::{Duration, SystemTime};
+
+/// Run the binary with `args`, keeping its trash beneath `home`.
+fn lru(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_lru"))
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_DATA_HOME", home.join("data"))
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn restoring_confirms_every_file_put_back() {
+    let home = std::env::temp_dir().join(format!("lru-restore-test-{}", std::process::id()));
+    let root = home.join("cache");
+    fs::create_dir_all(&root).unwrap();
+    for name in ["a", "b"] {
+        fs::write(root.join(name), name).unwrap();
+        let used = SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60);
+        File::options().write(true).open(root.join(name)).unwrap().set_times(FileTimes::new().set_accessed(used).set_modified(used)).unwrap();
+    }
+    let root_arg = root.to_str().unwrap();
+
+    let output = lru(&home, &["restore", root_arg]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("Nothing has been trashed from {}\n", root.display()));
+
+    let trashed = lru(&home, &[root_arg, "--trash", "--purge-older-than", "1", "--yes", "--quiet"]);
+    assert!(trashed.status.success(), "{}", String::from_utf8_lossy(&trashed.stderr));
+    assert!(!root.join("a").exists() && !root.join("b").exists());
+
+    // restoring logs at the default verbosity, so the confirmation must reach stdout regardless
+    let output = lru(&home, &["restore", root_arg]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut lines : Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.pop(), Some("Restored 2 of 2 files"));
+    lines.sort();
+    assert_eq!(lines, [format!("Restored {}", root.join("a").display()), format!("Restored {}", root.join("b").display())]);
+    assert_eq!(fs::read(root.join("a")).unwrap(), b"a");
+    fs::remove_dir_all(&home).unwrap();
+}